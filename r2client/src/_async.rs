@@ -1,4 +1,12 @@
+#[cfg(feature = "encryption")]
+mod encrypted_bucket;
 mod r2bucket;
 mod r2client;
+#[cfg(feature = "object_store")]
+mod store;
+#[cfg(feature = "encryption")]
+pub use encrypted_bucket::EncryptedBucket;
 pub use r2bucket::R2Bucket;
 pub use r2client::R2Client;
+#[cfg(feature = "object_store")]
+pub use store::R2Store;
@@ -1,5 +1,11 @@
 use crate::_async::R2Client;
-use crate::R2Error;
+use crate::{
+    CompletedPart, CopyOptions, DirOptions, DirReport, GetObjectOutput, GetOptions, Jurisdiction,
+    Listing, MirrorOptions, MirrorReport, MultipartUpload, ObjectInfo, ObjectMetadata, Provider,
+    PutObjectOutput, PutOptions, R2Error, SyncOptions, SyncReport,
+};
+use bytes::Bytes;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct R2Bucket {
@@ -24,9 +30,30 @@ impl R2Bucket {
         access_key: String,
         secret_key: String,
         endpoint: String,
-    ) -> Self {
-        let client = R2Client::from_credentials(access_key, secret_key, endpoint);
-        Self { bucket, client }
+    ) -> Result<Self, R2Error> {
+        let client = R2Client::from_credentials(access_key, secret_key, endpoint)?;
+        Ok(Self { bucket, client })
+    }
+
+    pub fn from_account(
+        bucket: String,
+        account_id: &str,
+        jurisdiction: Jurisdiction,
+        access_key: String,
+        secret_key: String,
+    ) -> Result<Self, R2Error> {
+        let client = R2Client::from_account(account_id, jurisdiction, access_key, secret_key)?;
+        Ok(Self { bucket, client })
+    }
+
+    pub fn from_provider(
+        bucket: String,
+        provider: &Provider,
+        access_key: String,
+        secret_key: String,
+    ) -> Result<Self, R2Error> {
+        let client = R2Client::from_provider(provider, access_key, secret_key)?;
+        Ok(Self { bucket, client })
     }
 
     pub async fn upload_file(
@@ -40,18 +67,244 @@ impl R2Bucket {
             .await
     }
 
+    pub async fn upload_file_with_options(
+        &self,
+        local_file_path: &str,
+        r2_file_key: &str,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.client
+            .upload_file_with_options(&self.bucket, local_file_path, r2_file_key, options)
+            .await
+    }
+
+    /// See [`R2Client::upload_file_multipart`].
+    pub async fn upload_file_multipart(
+        &self,
+        local_file_path: &str,
+        r2_file_key: &str,
+        part_size: u64,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.client
+            .upload_file_multipart(
+                &self.bucket,
+                local_file_path,
+                r2_file_key,
+                part_size,
+                options,
+            )
+            .await
+    }
+
+    pub async fn create_multipart_upload(
+        &self,
+        r2_file_key: &str,
+        options: &PutOptions,
+    ) -> Result<MultipartUpload, R2Error> {
+        self.client
+            .create_multipart_upload(&self.bucket, r2_file_key, options)
+            .await
+    }
+
+    pub async fn upload_part(
+        &self,
+        upload: &MultipartUpload,
+        part_number: u32,
+        body: impl Into<Bytes>,
+        options: &PutOptions,
+    ) -> Result<CompletedPart, R2Error> {
+        self.client
+            .upload_part(upload, part_number, body, options)
+            .await
+    }
+
+    pub async fn complete_multipart_upload(
+        &self,
+        upload: &MultipartUpload,
+        parts: &[CompletedPart],
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.client.complete_multipart_upload(upload, parts).await
+    }
+
+    pub async fn abort_multipart_upload(&self, upload: &MultipartUpload) -> Result<(), R2Error> {
+        self.client.abort_multipart_upload(upload).await
+    }
+
+    pub async fn put_object(
+        &self,
+        r2_file_key: &str,
+        body: impl Into<Bytes>,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.client
+            .put_object(&self.bucket, r2_file_key, body, options)
+            .await
+    }
+
     pub async fn download_file(&self, r2_file_key: &str, local_path: &str) -> Result<(), R2Error> {
         self.client
             .download_file(&self.bucket, r2_file_key, local_path, None)
             .await
     }
 
+    pub async fn download_file_with_options(
+        &self,
+        r2_file_key: &str,
+        local_path: &str,
+        options: &GetOptions,
+    ) -> Result<(), R2Error> {
+        self.client
+            .download_file_with_options(&self.bucket, r2_file_key, local_path, options)
+            .await
+    }
+
+    pub async fn get_object(
+        &self,
+        r2_file_key: &str,
+        options: &GetOptions,
+    ) -> Result<GetObjectOutput, R2Error> {
+        self.client
+            .get_object(&self.bucket, r2_file_key, options)
+            .await
+    }
+
+    /// See [`R2Client::presigned_get_url`].
+    pub fn presigned_get_url(
+        &self,
+        r2_file_key: &str,
+        expires_in: Duration,
+        options: &GetOptions,
+    ) -> Result<String, R2Error> {
+        self.client
+            .presigned_get_url(&self.bucket, r2_file_key, expires_in, options)
+    }
+
+    pub async fn head_object(&self, r2_file_key: &str) -> Result<ObjectMetadata, R2Error> {
+        self.client.head_object(&self.bucket, r2_file_key).await
+    }
+
+    pub async fn head_object_with_options(
+        &self,
+        r2_file_key: &str,
+        options: &GetOptions,
+    ) -> Result<ObjectMetadata, R2Error> {
+        self.client
+            .head_object_with_options(&self.bucket, r2_file_key, options)
+            .await
+    }
+
+    /// Copies an object within this bucket.
+    pub async fn copy_object(
+        &self,
+        source_key: &str,
+        r2_file_key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.client
+            .copy_object(&self.bucket, source_key, &self.bucket, r2_file_key, options)
+            .await
+    }
+
     pub async fn list_files(
         &self,
     ) -> Result<std::collections::HashMap<String, Vec<String>>, R2Error> {
         self.client.list_files(&self.bucket).await
     }
 
+    pub async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        self.client.list_objects(&self.bucket, prefix).await
+    }
+
+    /// See [`R2Client::list_objects_delimited`].
+    pub async fn list_objects_delimited(
+        &self,
+        prefix: &str,
+        delimiter: &str,
+    ) -> Result<Listing, R2Error> {
+        self.client
+            .list_objects_delimited(&self.bucket, prefix, delimiter)
+            .await
+    }
+
+    /// See [`R2Client::upload_dir`].
+    pub async fn upload_dir(&self, local_dir: &str, prefix: &str) -> Result<DirReport, R2Error> {
+        self.upload_dir_with_options(local_dir, prefix, &DirOptions::default())
+            .await
+    }
+
+    pub async fn upload_dir_with_options(
+        &self,
+        local_dir: &str,
+        prefix: &str,
+        options: &DirOptions,
+    ) -> Result<DirReport, R2Error> {
+        self.client
+            .upload_dir(&self.bucket, local_dir, prefix, options)
+            .await
+    }
+
+    /// See [`R2Client::download_prefix`].
+    pub async fn download_prefix(
+        &self,
+        prefix: &str,
+        local_dir: &str,
+    ) -> Result<DirReport, R2Error> {
+        self.download_prefix_with_options(prefix, local_dir, &DirOptions::default())
+            .await
+    }
+
+    pub async fn download_prefix_with_options(
+        &self,
+        prefix: &str,
+        local_dir: &str,
+        options: &DirOptions,
+    ) -> Result<DirReport, R2Error> {
+        self.client
+            .download_prefix(&self.bucket, prefix, local_dir, options)
+            .await
+    }
+
+    /// See [`R2Client::sync_dir_to_prefix`].
+    pub async fn sync_dir_to_prefix(
+        &self,
+        local_dir: &str,
+        prefix: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncReport, R2Error> {
+        self.client
+            .sync_dir_to_prefix(&self.bucket, local_dir, prefix, options)
+            .await
+    }
+
+    /// See [`R2Client::sync_prefix_to_dir`].
+    pub async fn sync_prefix_to_dir(
+        &self,
+        prefix: &str,
+        local_dir: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncReport, R2Error> {
+        self.client
+            .sync_prefix_to_dir(&self.bucket, prefix, local_dir, options)
+            .await
+    }
+
+    /// Mirrors this bucket into `destination`, see [`R2Client::mirror`].
+    pub async fn mirror_to(
+        &self,
+        destination: &R2Bucket,
+        options: &MirrorOptions,
+    ) -> Result<MirrorReport, R2Error> {
+        self.client
+            .mirror(
+                &self.bucket,
+                &destination.client,
+                &destination.bucket,
+                options,
+            )
+            .await
+    }
+
     pub async fn list_folders(&self) -> Result<Vec<String>, R2Error> {
         self.client.list_folders(&self.bucket).await
     }
@@ -60,3 +313,58 @@ impl R2Bucket {
         self.client.delete(&self.bucket, r2_file_key).await
     }
 }
+
+impl crate::Storage for R2Bucket {
+    async fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        R2Bucket::put_object(self, key, body, options).await
+    }
+
+    async fn get_object(
+        &self,
+        key: &str,
+        options: &GetOptions,
+    ) -> Result<GetObjectOutput, R2Error> {
+        R2Bucket::get_object(self, key, options).await
+    }
+
+    async fn head_object(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        R2Bucket::head_object(self, key).await
+    }
+
+    async fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        R2Bucket::copy_object(self, source_key, key, options).await
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<(), R2Error> {
+        self.delete_file(key).await
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        R2Bucket::list_objects(self, prefix).await
+    }
+
+    async fn list_objects_delimited(
+        &self,
+        prefix: &str,
+        delimiter: &str,
+    ) -> Result<Listing, R2Error> {
+        R2Bucket::list_objects_delimited(self, prefix, delimiter).await
+    }
+}
+
+#[cfg(feature = "object_store")]
+impl From<R2Bucket> for crate::R2Store {
+    fn from(bucket: R2Bucket) -> Self {
+        Self::new(bucket.bucket, bucket.client)
+    }
+}
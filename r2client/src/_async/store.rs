@@ -0,0 +1,645 @@
+//! [`R2Store`], so anything built on the `object_store` crate (DataFusion, Arrow, ...) can read
+//! and write R2 through this client.
+
+use crate::_async::R2Client;
+use crate::{
+    CompletedPart, CopyOptions, MultipartUpload as R2MultipartUpload, ObjectInfo, ObjectMetadata,
+    PutOptions as R2PutOptions, R2Error,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::{StreamExt, TryStreamExt};
+use http::{Method, StatusCode};
+use object_store::path::Path;
+use object_store::{
+    Attribute, Attributes, GetOptions, GetResult, GetResultPayload, ListResult, MultipartUpload,
+    ObjectMeta, ObjectStore, PutMode, PutMultipartOptions, PutOptions, PutPayload, PutResult,
+    TagSet, UploadPart,
+};
+use reqwest::header::HeaderMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// What errors that don't fit anything more specific say they came from.
+const STORE: &str = "R2";
+const DELIMITER: &str = "/";
+
+/// An [`ObjectStore`] for one bucket, backed by the async [`R2Client`] (along with its retry
+/// policy, rate limit and so on).
+///
+/// Objects are read as they stream in, and `put_multipart` uploads every part as it's handed
+/// over, so neither has to fit in memory. R2 doesn't keep versions or tags, so asking for either
+/// fails with [`object_store::Error::NotSupported`], as does `copy_if_not_exists`.
+///
+/// ```no_run
+/// use object_store::{ObjectStore, path::Path};
+/// use r2client::{R2Client, R2Store};
+///
+/// # async fn example() -> object_store::Result<()> {
+/// let store = R2Store::new("my-bucket".to_string(), R2Client::new());
+/// let footer = store.get_range(&Path::from("data/part-0.parquet"), 0..8).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct R2Store {
+    bucket: String,
+    client: R2Client,
+}
+
+impl R2Store {
+    pub fn new(bucket: String, client: R2Client) -> Self {
+        Self { bucket, client }
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn client(&self) -> &R2Client {
+        &self.client
+    }
+}
+
+impl fmt::Display for R2Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "R2Store({})", self.bucket)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for R2Store {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> object_store::Result<PutResult> {
+        let key = location.as_ref();
+        let options = put_options(&opts.attributes, &opts.tags)?;
+        let body = Bytes::from(payload);
+        let content_type = options
+            .content_type
+            .as_deref()
+            .unwrap_or_else(|| self.client.mime_registry().infer(key, &body));
+        let mut conditions = Vec::new();
+        match &opts.mode {
+            PutMode::Overwrite => {}
+            PutMode::Create => conditions.push(("if-none-match".to_string(), "*".to_string())),
+            PutMode::Update(version) => {
+                let etag = version
+                    .e_tag
+                    .clone()
+                    .ok_or_else(|| object_store::Error::Generic {
+                        store: STORE,
+                        source: "conditional updates need an ETag, R2 doesn't keep versions".into(),
+                    })?;
+                conditions.push(("if-match".to_string(), etag));
+            }
+        }
+        let (resp, _) = self
+            .client
+            .send_put(&self.bucket, key, body, content_type, &options, conditions)
+            .await
+            .map_err(|e| store_error(e, key))?;
+        let status = resp.status();
+        let e_tag = header(resp.headers(), "etag");
+        if status.is_success() {
+            return Ok(PutResult {
+                e_tag,
+                version: None,
+            });
+        }
+        let error = R2Error::FailedRequest(
+            format!("put object \"{key}\" in bucket \"{}\"", self.bucket),
+            status,
+            resp.text().await.unwrap_or_default(),
+        );
+        match (&opts.mode, status) {
+            (PutMode::Create, StatusCode::PRECONDITION_FAILED) => {
+                Err(object_store::Error::AlreadyExists {
+                    path: key.to_owned(),
+                    source: Box::new(error),
+                })
+            }
+            _ => Err(store_error(error, key)),
+        }
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOptions,
+    ) -> object_store::Result<Box<dyn MultipartUpload>> {
+        let key = location.as_ref();
+        let options = put_options(&opts.attributes, &opts.tags)?;
+        let upload = self
+            .client
+            .create_multipart_upload(&self.bucket, key, &options)
+            .await
+            .map_err(|e| store_error(e, key))?;
+        Ok(Box::new(R2StoreUpload {
+            client: self.client.clone(),
+            upload,
+            parts: Arc::default(),
+            started_parts: 0,
+        }))
+    }
+
+    async fn get_opts(
+        &self,
+        location: &Path,
+        options: GetOptions,
+    ) -> object_store::Result<GetResult> {
+        let key = location.as_ref();
+        if options.version.is_some() {
+            return Err(object_store::Error::NotSupported {
+                source: "R2 doesn't keep versions of objects".into(),
+            });
+        }
+        let mut headers = Vec::new();
+        if let Some(range) = &options.range {
+            range.is_valid().map_err(|e| object_store::Error::Generic {
+                store: STORE,
+                source: Box::new(e),
+            })?;
+            headers.push(("range".to_string(), range.to_string()));
+        }
+        for (name, etag) in [
+            ("if-match", &options.if_match),
+            ("if-none-match", &options.if_none_match),
+        ] {
+            if let Some(etag) = etag {
+                headers.push((name.to_string(), etag.clone()));
+            }
+        }
+        for (name, date) in [
+            ("if-modified-since", options.if_modified_since),
+            ("if-unmodified-since", options.if_unmodified_since),
+        ] {
+            if let Some(date) = date {
+                headers.push((
+                    name.to_string(),
+                    httpdate::fmt_http_date(SystemTime::from(date)),
+                ));
+            }
+        }
+        let method = if options.head {
+            Method::HEAD
+        } else {
+            Method::GET
+        };
+        let resp = self
+            .client
+            .send(
+                method,
+                &self.bucket,
+                Some(key),
+                &[],
+                Bytes::new(),
+                Some(headers),
+            )
+            .await
+            .map_err(|e| store_error(e, key))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let error = R2Error::FailedRequest(
+                format!("getting object \"{key}\" from bucket \"{}\"", self.bucket),
+                status,
+                resp.text().await.unwrap_or_default(),
+            );
+            return Err(store_error(error, key));
+        }
+
+        let metadata = ObjectMetadata::from_response(resp.headers());
+        let (range, size) = content_range(resp.headers()).unwrap_or_else(|| {
+            let size = metadata.content_length.unwrap_or_default();
+            (0..size, size)
+        });
+        let meta = ObjectMeta {
+            location: location.clone(),
+            last_modified: metadata
+                .last_modified
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+                .into(),
+            size,
+            e_tag: metadata.etag.clone(),
+            version: None,
+        };
+        let payload = if options.head {
+            futures_util::stream::empty().boxed()
+        } else {
+            let throttle = self.client.throttle(None);
+            resp.bytes_stream()
+                .map_err(|e| object_store::Error::Generic {
+                    store: STORE,
+                    source: Box::new(e),
+                })
+                .and_then(move |chunk| {
+                    let delay = throttle.delay(chunk.len());
+                    async move {
+                        if !delay.is_zero() {
+                            tokio::time::sleep(delay).await;
+                        }
+                        Ok(chunk)
+                    }
+                })
+                .boxed()
+        };
+        Ok(GetResult {
+            payload: GetResultPayload::Stream(payload),
+            meta,
+            range,
+            attributes: attributes(&metadata),
+        })
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        let key = location.as_ref();
+        self.client
+            .delete(&self.bucket, key)
+            .await
+            .map_err(|e| store_error(e, key))
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, object_store::Result<ObjectMeta>> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let prefix = list_prefix(prefix);
+        // `None` once the last page is in, `Some(None)` before the first one
+        futures_util::stream::try_unfold(Some(None), move |continuation_token| {
+            let client = client.clone();
+            let bucket = bucket.clone();
+            let prefix = prefix.clone();
+            async move {
+                let Some(continuation_token) = continuation_token else {
+                    return Ok::<_, object_store::Error>(None);
+                };
+                let page = client
+                    .list_page(&bucket, &prefix, None, continuation_token.as_deref())
+                    .await
+                    .map_err(|e| store_error(e, &prefix))?;
+                let objects = objects(page.objects)?;
+                let next = page.next_continuation_token.map(Some);
+                Ok(Some((
+                    futures_util::stream::iter(objects.into_iter().map(Ok)),
+                    next,
+                )))
+            }
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> object_store::Result<ListResult> {
+        let prefix = list_prefix(prefix);
+        let mut result = ListResult {
+            common_prefixes: Vec::new(),
+            objects: Vec::new(),
+        };
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .client
+                .list_page(
+                    &self.bucket,
+                    &prefix,
+                    Some(DELIMITER),
+                    continuation_token.as_deref(),
+                )
+                .await
+                .map_err(|e| store_error(e, &prefix))?;
+            for common_prefix in page.common_prefixes {
+                result
+                    .common_prefixes
+                    .push(Path::parse(common_prefix.trim_end_matches(DELIMITER))?);
+            }
+            result.objects.extend(objects(page.objects)?);
+            match page.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(result),
+            }
+        }
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        self.client
+            .copy_object(
+                &self.bucket,
+                from.as_ref(),
+                &self.bucket,
+                to.as_ref(),
+                &CopyOptions::default(),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| store_error(e, from.as_ref()))
+    }
+
+    async fn copy_if_not_exists(&self, _from: &Path, _to: &Path) -> object_store::Result<()> {
+        Err(object_store::Error::NotSupported {
+            source: "R2 can't make copies conditional on the destination".into(),
+        })
+    }
+}
+
+/// A multipart upload started by [`R2Store::put_multipart`].
+#[derive(Debug)]
+struct R2StoreUpload {
+    client: R2Client,
+    upload: R2MultipartUpload,
+    /// Parts finish in whatever order they finish, since callers can upload them concurrently.
+    parts: Arc<Mutex<Vec<CompletedPart>>>,
+    started_parts: u32,
+}
+
+#[async_trait]
+impl MultipartUpload for R2StoreUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        self.started_parts += 1;
+        let part_number = self.started_parts;
+        let client = self.client.clone();
+        let upload = self.upload.clone();
+        let parts = Arc::clone(&self.parts);
+        Box::pin(async move {
+            let part = client
+                .upload_part(
+                    &upload,
+                    part_number,
+                    Bytes::from(data),
+                    &R2PutOptions::default(),
+                )
+                .await
+                .map_err(|e| store_error(e, &upload.key))?;
+            parts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(part);
+            Ok(())
+        })
+    }
+
+    async fn complete(&mut self) -> object_store::Result<PutResult> {
+        // Uploads need at least one part, even if it's empty
+        if self.started_parts == 0 {
+            self.put_part(PutPayload::default()).await?;
+        }
+        let mut parts = self
+            .parts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if parts.len() != self.started_parts as usize {
+            return Err(object_store::Error::Generic {
+                store: STORE,
+                source: format!(
+                    "only {} of the {} parts of \"{}\" finished uploading",
+                    parts.len(),
+                    self.started_parts,
+                    self.upload.key
+                )
+                .into(),
+            });
+        }
+        parts.sort_by_key(|part| part.part_number);
+        let output = self
+            .client
+            .complete_multipart_upload(&self.upload, &parts)
+            .await
+            .map_err(|e| store_error(e, &self.upload.key))?;
+        Ok(PutResult {
+            e_tag: output.etag,
+            version: None,
+        })
+    }
+
+    async fn abort(&mut self) -> object_store::Result<()> {
+        self.client
+            .abort_multipart_upload(&self.upload)
+            .await
+            .map_err(|e| store_error(e, &self.upload.key))
+    }
+}
+
+/// Turns the client's errors into the ones `object_store` users match on.
+fn store_error(error: R2Error, path: &str) -> object_store::Error {
+    let status = match &error {
+        R2Error::FailedRequest(_, status, _) => Some(*status),
+        _ => None,
+    };
+    let path = path.to_owned();
+    let source = Box::new(error);
+    match status {
+        Some(StatusCode::NOT_FOUND) => object_store::Error::NotFound { path, source },
+        Some(StatusCode::NOT_MODIFIED) => object_store::Error::NotModified { path, source },
+        Some(StatusCode::PRECONDITION_FAILED) => object_store::Error::Precondition { path, source },
+        Some(StatusCode::FORBIDDEN) => object_store::Error::PermissionDenied { path, source },
+        Some(StatusCode::UNAUTHORIZED) => object_store::Error::Unauthenticated { path, source },
+        _ => object_store::Error::Generic {
+            store: STORE,
+            source,
+        },
+    }
+}
+
+/// `object_store` paths never end in a delimiter, but prefixes are directories.
+fn list_prefix(prefix: Option<&Path>) -> String {
+    match prefix {
+        Some(prefix) if !prefix.as_ref().is_empty() => format!("{prefix}{DELIMITER}"),
+        _ => String::new(),
+    }
+}
+
+/// Listed objects as `object_store` sees them. Keys ending in `/` are only there to make
+/// "folders" show up in dashboards, and aren't valid paths anyway.
+fn objects(objects: Vec<ObjectInfo>) -> object_store::Result<Vec<ObjectMeta>> {
+    objects
+        .into_iter()
+        .filter(|object| !object.key.ends_with(DELIMITER))
+        .map(|object| {
+            Ok(ObjectMeta {
+                location: Path::parse(&object.key)?,
+                last_modified: object
+                    .last_modified
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+                    .into(),
+                size: object.size,
+                e_tag: object.etag,
+                version: None,
+            })
+        })
+        .collect()
+}
+
+/// The client's upload options for whatever `object_store` attributes R2 can store.
+fn put_options(attributes: &Attributes, tags: &TagSet) -> object_store::Result<R2PutOptions> {
+    if !tags.encoded().is_empty() {
+        return Err(object_store::Error::NotSupported {
+            source: "R2 doesn't support object tags".into(),
+        });
+    }
+    let mut options = R2PutOptions::default();
+    for (attribute, value) in attributes {
+        let value = value.as_ref().to_owned();
+        match attribute {
+            Attribute::ContentType => options.content_type = Some(value),
+            Attribute::CacheControl => options.cache_control = Some(value),
+            Attribute::ContentDisposition => options.content_disposition = Some(value),
+            Attribute::ContentEncoding => options.content_encoding = Some(value),
+            Attribute::ContentLanguage => options.content_language = Some(value),
+            Attribute::Metadata(name) => {
+                options.metadata.insert(name.to_string(), value);
+            }
+            attribute => {
+                return Err(object_store::Error::NotSupported {
+                    source: format!("R2 can't store the {attribute:?} attribute").into(),
+                });
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// The counterpart of [`put_options`], for what comes back with an object.
+fn attributes(metadata: &ObjectMetadata) -> Attributes {
+    let mut attributes = Attributes::new();
+    for (attribute, value) in [
+        (Attribute::ContentType, &metadata.content_type),
+        (Attribute::CacheControl, &metadata.cache_control),
+        (Attribute::ContentDisposition, &metadata.content_disposition),
+        (Attribute::ContentEncoding, &metadata.content_encoding),
+        (Attribute::ContentLanguage, &metadata.content_language),
+    ] {
+        if let Some(value) = value {
+            attributes.insert(attribute, value.clone().into());
+        }
+    }
+    for (name, value) in &metadata.metadata {
+        attributes.insert(
+            Attribute::Metadata(name.clone().into()),
+            value.clone().into(),
+        );
+    }
+    attributes
+}
+
+/// The range a `206 Partial Content` response holds and the size of the whole object, from its
+/// `Content-Range` (`bytes 0-99/1234`).
+fn content_range(headers: &HeaderMap) -> Option<(Range<u64>, u64)> {
+    let value = header(headers, "content-range")?;
+    let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+    Some((start..end + 1, size.parse().ok()?))
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(list_prefix(None), "");
+        assert_eq!(list_prefix(Some(&Path::from(""))), "");
+        assert_eq!(list_prefix(Some(&Path::from("data/2024"))), "data/2024/");
+    }
+
+    #[test]
+    fn content_ranges() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(content_range(&headers), None);
+        headers.insert("content-range", "bytes 100-199/1234".parse().unwrap());
+        assert_eq!(content_range(&headers), Some((100..200, 1234)));
+        headers.insert("content-range", "bytes */1234".parse().unwrap());
+        assert_eq!(content_range(&headers), None);
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        let attributes = Attributes::from_iter([
+            (Attribute::ContentType, "text/csv"),
+            (Attribute::CacheControl, "no-cache"),
+            (Attribute::Metadata("owner".into()), "pyrite"),
+        ]);
+        let options = put_options(&attributes, &TagSet::default()).unwrap();
+        assert_eq!(options.content_type.as_deref(), Some("text/csv"));
+        assert_eq!(options.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(options.metadata["owner"], "pyrite");
+
+        let metadata = ObjectMetadata {
+            content_type: options.content_type,
+            cache_control: options.cache_control,
+            metadata: options.metadata,
+            ..Default::default()
+        };
+        assert_eq!(super::attributes(&metadata), attributes);
+    }
+
+    #[test]
+    fn unsupported_attributes() {
+        let storage_class = Attributes::from_iter([(Attribute::StorageClass, "GLACIER")]);
+        assert!(matches!(
+            put_options(&storage_class, &TagSet::default()),
+            Err(object_store::Error::NotSupported { .. })
+        ));
+        let mut tags = TagSet::default();
+        tags.push("team", "data");
+        assert!(matches!(
+            put_options(&Attributes::new(), &tags),
+            Err(object_store::Error::NotSupported { .. })
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let failed = |status| R2Error::FailedRequest("getting".to_string(), status, String::new());
+        assert!(matches!(
+            store_error(failed(StatusCode::NOT_FOUND), "a"),
+            object_store::Error::NotFound { path, .. } if path == "a"
+        ));
+        assert!(matches!(
+            store_error(failed(StatusCode::PRECONDITION_FAILED), "a"),
+            object_store::Error::Precondition { .. }
+        ));
+        assert!(matches!(
+            store_error(failed(StatusCode::NOT_MODIFIED), "a"),
+            object_store::Error::NotModified { .. }
+        ));
+        assert!(matches!(
+            store_error(R2Error::Cancelled, "a"),
+            object_store::Error::Generic { store: "R2", .. }
+        ));
+    }
+
+    #[test]
+    fn listed_objects() {
+        let listed = objects(vec![
+            ObjectInfo {
+                key: "data/".to_string(),
+                size: 0,
+                etag: None,
+                last_modified: None,
+            },
+            ObjectInfo {
+                key: "data/a.parquet".to_string(),
+                size: 10,
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            },
+        ])
+        .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].location, Path::from("data/a.parquet"));
+        assert_eq!(listed[0].size, 10);
+    }
+}
@@ -0,0 +1,204 @@
+//! Stopping transfers early, either on request or once they've taken too long.
+
+use crate::R2Error;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Cancels every transfer it was handed to, from any thread.
+///
+/// Clones share the same state, so keep one around and put clones in the options of whatever
+/// should stop along with it. Cancelled transfers fail with [`R2Error::Cancelled`], and multipart
+/// uploads started by `upload_file_multipart` are aborted on the way out.
+///
+/// ```
+/// use r2client::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let handed_out = token.clone();
+/// token.cancel();
+/// assert!(handed_out.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancelling more than once does nothing.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled (right away if it already is).
+    pub async fn cancelled(&self) {
+        loop {
+            let mut notified = pin!(self.0.notify.notified());
+            // Registered before checking, so a cancel in between can't be missed
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// What can stop a transfer before it's done: a token, a deadline, or neither.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interrupt {
+    token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl Interrupt {
+    pub(crate) fn new(token: Option<&CancellationToken>, deadline: Option<Instant>) -> Self {
+        Self {
+            token: token.cloned(),
+            deadline,
+        }
+    }
+
+    /// Fails if the transfer should already have stopped.
+    pub(crate) fn check(&self) -> Result<(), R2Error> {
+        if self
+            .token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            Err(R2Error::Cancelled)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Err(R2Error::DeadlineExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Blames `error` on the interrupt if the transfer was interrupted, since that's usually why
+    /// a blocking read timed out or a body stopped short.
+    #[cfg_attr(not(feature = "sync"), allow(dead_code))]
+    pub(crate) fn explain(&self, error: impl Into<R2Error>) -> R2Error {
+        self.check().err().unwrap_or_else(|| error.into())
+    }
+
+    /// Time left until the deadline, if there is one.
+    #[cfg_attr(not(feature = "sync"), allow(dead_code))]
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs `future` until it's done or the transfer is interrupted, whichever comes first.
+    /// Whatever `future` was doing (a request, most likely) is dropped when it loses.
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) async fn run<T>(
+        &self,
+        future: impl Future<Output = Result<T, R2Error>>,
+    ) -> Result<T, R2Error> {
+        use futures_util::future::{Either, select};
+
+        self.check()?;
+        if self.token.is_none() && self.deadline.is_none() {
+            return future.await;
+        }
+        match select(pin!(future), pin!(self.interrupted())).await {
+            Either::Left((result, _)) => result,
+            Either::Right((error, _)) => Err(error),
+        }
+    }
+
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    async fn interrupted(&self) -> R2Error {
+        use futures_util::future::{Either, pending, select};
+
+        let cancelled = async {
+            match &self.token {
+                Some(token) => token.cancelled().await,
+                None => pending().await,
+            }
+        };
+        let expired = async {
+            match self.deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                None => pending().await,
+            }
+        };
+        match select(pin!(cancelled), pin!(expired)).await {
+            Either::Left(_) => R2Error::Cancelled,
+            Either::Right(_) => R2Error::DeadlineExceeded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checking() {
+        assert!(Interrupt::default().check().is_ok());
+
+        let token = CancellationToken::new();
+        let interrupt = Interrupt::new(Some(&token), None);
+        assert!(interrupt.check().is_ok());
+        token.cancel();
+        assert!(matches!(interrupt.check(), Err(R2Error::Cancelled)));
+
+        let interrupt = Interrupt::new(None, Some(Instant::now()));
+        assert!(matches!(interrupt.check(), Err(R2Error::DeadlineExceeded)));
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_running_future() {
+        let token = CancellationToken::new();
+        let interrupt = Interrupt::new(Some(&token), None);
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+        let result = interrupt
+            .run(async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(R2Error::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn deadline_cuts_a_future_short() {
+        let interrupt = Interrupt::new(None, Some(Instant::now() + Duration::from_millis(10)));
+        let result = interrupt
+            .run(async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(R2Error::DeadlineExceeded)));
+
+        let finished = Interrupt::new(None, Some(Instant::now() + Duration::from_secs(60)))
+            .run(async { Ok(1) })
+            .await;
+        assert_eq!(finished.unwrap(), 1);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::R2Error;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+use reqwest::header::HeaderMap;
+use sha2::Sha256;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Integrity checks the server can verify an upload against.
+///
+/// If the body R2 receives doesn't hash to the value sent along with it, the upload is rejected
+/// (400 BadDigest) instead of silently storing corrupted data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// The classic `Content-MD5` header.
+    ContentMd5,
+    /// `x-amz-checksum-crc32`
+    Crc32,
+    /// `x-amz-checksum-crc32c`
+    Crc32c,
+    /// `x-amz-checksum-sha256`
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn header_name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::ContentMd5 => "content-md5",
+            ChecksumAlgorithm::Crc32 => "x-amz-checksum-crc32",
+            ChecksumAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+            ChecksumAlgorithm::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    pub fn compute(self, data: &[u8]) -> Checksum {
+        let digest = match self {
+            ChecksumAlgorithm::ContentMd5 => Md5::digest(data).to_vec(),
+            ChecksumAlgorithm::Crc32 => crc32fast::hash(data).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::Crc32c => crc32c::crc32c(data).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        };
+        Checksum {
+            algorithm: self,
+            value: BASE64.encode(digest),
+        }
+    }
+}
+
+/// A computed checksum, base64 encoded the way it goes over the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub value: String,
+}
+
+impl Checksum {
+    pub(crate) fn header(&self) -> (String, String) {
+        (self.algorithm.header_name().to_string(), self.value.clone())
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.algorithm.header_name(), self.value)
+    }
+}
+
+enum RunningHash {
+    Md5(Md5),
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
+    Sha256(Sha256),
+}
+
+impl RunningHash {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::ContentMd5 => RunningHash::Md5(Md5::new()),
+            ChecksumAlgorithm::Crc32 => RunningHash::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Crc32c => RunningHash::Crc32c(0),
+            ChecksumAlgorithm::Sha256 => RunningHash::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            RunningHash::Md5(md5) => md5.update(data),
+            RunningHash::Crc32(crc) => crc.update(data),
+            RunningHash::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            RunningHash::Sha256(sha) => sha.update(data),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            RunningHash::Md5(md5) => md5.finalize().to_vec(),
+            RunningHash::Crc32(crc) => crc.finalize().to_be_bytes().to_vec(),
+            RunningHash::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+            RunningHash::Sha256(sha) => sha.finalize().to_vec(),
+        }
+    }
+}
+
+/// The MD5 (lowercase hex) an ETag stands for, if it is one. Multipart ETags end in
+/// "-<parts>" and aren't an MD5 of anything.
+pub(crate) fn etag_md5(etag: &str) -> Option<String> {
+    let etag = etag.trim_matches('"');
+    (etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| etag.to_ascii_lowercase())
+}
+
+/// The MD5 of a file in lowercase hex, like a (non-multipart) ETag, without reading it into
+/// memory all at once.
+pub(crate) fn file_md5(path: &Path) -> io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hash = Md5::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            read => hash.update(&buf[..read]),
+        }
+    }
+    Ok(hash
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Hashes a download as it comes in and checks it against what R2 says the object should be.
+pub(crate) struct ChecksumVerifier {
+    hash: RunningHash,
+    expected: String,
+    /// ETags are hex, the x-amz-checksum-* headers are base64
+    hex: bool,
+}
+
+impl ChecksumVerifier {
+    /// Picks the best thing to verify against from the response headers: an `x-amz-checksum-*`
+    /// header if R2 sent one back, otherwise the ETag, but only when it's a plain MD5.
+    /// Returns `None` when there's nothing usable, e.g. for multipart uploads.
+    pub(crate) fn from_response(headers: &HeaderMap, etag_is_md5: bool) -> Option<Self> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        for algorithm in [
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Crc32c,
+            ChecksumAlgorithm::Crc32,
+        ] {
+            // Multipart uploads get a checksum of checksums ("<value>-<parts>"), which the
+            // body alone can't reproduce
+            if let Some(expected) = header(algorithm.header_name())
+                && !expected.contains('-')
+            {
+                return Some(Self {
+                    hash: RunningHash::new(algorithm),
+                    expected: expected.to_owned(),
+                    hex: false,
+                });
+            }
+        }
+        if !etag_is_md5 {
+            return None;
+        }
+        Some(Self {
+            hash: RunningHash::new(ChecksumAlgorithm::ContentMd5),
+            expected: etag_md5(header("etag")?)?,
+            hex: true,
+        })
+    }
+
+    pub(crate) fn update(&mut self, chunk: &[u8]) {
+        self.hash.update(chunk)
+    }
+
+    pub(crate) fn verify(self, key: &str) -> Result<(), R2Error> {
+        let digest = self.hash.finish();
+        let actual = if self.hex {
+            digest.iter().map(|byte| format!("{byte:02x}")).collect()
+        } else {
+            BASE64.encode(digest)
+        };
+        if actual == self.expected {
+            Ok(())
+        } else {
+            Err(R2Error::IntegrityMismatch(
+                key.to_owned(),
+                self.expected,
+                actual,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Known values for "hello world", cross checked with `openssl dgst` and `crc32`
+    const DATA: &[u8] = b"hello world";
+
+    #[test]
+    fn md5() {
+        assert_eq!(
+            ChecksumAlgorithm::ContentMd5.compute(DATA).value,
+            "XrY7u+Ae7tCTyyK7j1rNww=="
+        );
+    }
+
+    #[test]
+    fn crc32() {
+        // 0x0d4a1185
+        assert_eq!(ChecksumAlgorithm::Crc32.compute(DATA).value, "DUoRhQ==");
+    }
+
+    #[test]
+    fn crc32c() {
+        // 0xc99465aa
+        assert_eq!(ChecksumAlgorithm::Crc32c.compute(DATA).value, "yZRlqg==");
+    }
+
+    #[test]
+    fn sha256() {
+        assert_eq!(
+            ChecksumAlgorithm::Sha256.compute(DATA).value,
+            "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+        );
+    }
+
+    fn verifier(headers: &[(&'static str, &'static str)]) -> Option<ChecksumVerifier> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, value.parse().unwrap());
+        }
+        ChecksumVerifier::from_response(&map, true)
+    }
+
+    #[test]
+    fn verify_etag() {
+        let mut good = verifier(&[("etag", "\"5eb63bbbe01eeed093cb22bb8f5acdc3\"")]).unwrap();
+        good.update(b"hello ");
+        good.update(b"world");
+        assert!(good.verify("key").is_ok());
+
+        let mut bad = verifier(&[("etag", "\"5eb63bbbe01eeed093cb22bb8f5acdc3\"")]).unwrap();
+        bad.update(b"hello w0rld");
+        assert!(matches!(
+            bad.verify("key"),
+            Err(R2Error::IntegrityMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn prefers_checksum_header() {
+        let mut verifier = verifier(&[
+            ("etag", "\"00000000000000000000000000000000\""),
+            ("x-amz-checksum-crc32", "DUoRhQ=="),
+        ])
+        .unwrap();
+        verifier.update(DATA);
+        assert!(verifier.verify("key").is_ok());
+    }
+
+    #[test]
+    fn skips_non_md5_etag() {
+        let mut map = HeaderMap::new();
+        map.insert(
+            "etag",
+            "\"5eb63bbbe01eeed093cb22bb8f5acdc3\"".parse().unwrap(),
+        );
+        assert!(ChecksumVerifier::from_response(&map, false).is_none());
+    }
+
+    #[test]
+    fn skips_multipart() {
+        assert!(verifier(&[("etag", "\"5eb63bbbe01eeed093cb22bb8f5acdc3-2\"")]).is_none());
+        assert!(
+            verifier(&[
+                ("etag", "\"5eb63bbbe01eeed093cb22bb8f5acdc3-2\""),
+                ("x-amz-checksum-crc32", "DUoRhQ==-2")
+            ])
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn header() {
+        let checksum = ChecksumAlgorithm::Crc32.compute(DATA);
+        assert_eq!(
+            checksum.header(),
+            ("x-amz-checksum-crc32".to_string(), "DUoRhQ==".to_string())
+        );
+    }
+}
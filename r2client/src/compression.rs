@@ -0,0 +1,225 @@
+use crate::R2Error;
+use std::io::{self, Write};
+
+/// Compresses uploads on the fly and records it in `Content-Encoding`, while the content type
+/// stays whatever the uncompressed data is.
+///
+/// Each variant needs the cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    pub fn content_encoding(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Picks the decoder for a stored `Content-Encoding`.
+    /// `None` means there's nothing to undo, anything this build can't decode is an error.
+    pub(crate) fn from_content_encoding(encoding: Option<&str>) -> Result<Option<Self>, R2Error> {
+        match encoding.map(str::trim) {
+            None | Some("" | "identity") => Ok(None),
+            #[cfg(feature = "gzip")]
+            Some("gzip" | "x-gzip") => Ok(Some(Compression::Gzip)),
+            #[cfg(feature = "zstd")]
+            Some("zstd") => Ok(Some(Compression::Zstd)),
+            Some(encoding) => Err(R2Error::InvalidOptions(format!(
+                "can't decompress Content-Encoding {encoding:?} (is the matching feature enabled?)"
+            ))),
+        }
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Vec<u8>, R2Error> {
+        let mut encoder = EncodingWriter::new(self, Vec::new())?;
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+// The enum is empty without any compression features, which is fine, it just means
+// `compress` can't be called
+#[allow(dead_code)]
+enum EncodingWriter<W: Write> {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+    Never(std::convert::Infallible, W),
+}
+
+impl<W: Write> EncodingWriter<W> {
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn new(compression: Compression, inner: W) -> io::Result<Self> {
+        match compression {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Self::Gzip(flate2::write::GzEncoder::new(
+                inner,
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Self::Zstd(zstd::stream::write::Encoder::new(
+                inner,
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?)),
+        }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish(),
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Self::Never(never, _) => match never {},
+        }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.write(buf),
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Self::Never(never, _) => match *never {},
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.flush(),
+            #[cfg(not(any(feature = "gzip", feature = "zstd")))]
+            Self::Never(never, _) => match *never {},
+        }
+    }
+}
+
+/// Decompresses whatever's written into it (or passes it straight through) on its way to `W`.
+pub(crate) enum DecodingWriter<W: Write> {
+    Identity(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzDecoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+}
+
+impl<W: Write> DecodingWriter<W> {
+    pub(crate) fn new(compression: Option<Compression>, inner: W) -> io::Result<Self> {
+        match compression {
+            None => Ok(Self::Identity(inner)),
+            #[cfg(feature = "gzip")]
+            Some(Compression::Gzip) => Ok(Self::Gzip(flate2::write::GzDecoder::new(inner))),
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => Ok(Self::Zstd(zstd::stream::write::Decoder::new(inner)?)),
+        }
+    }
+
+    /// Flushes whatever the decoder is still holding on to. Has to be called, dropping the
+    /// writer can silently lose the tail end of the data.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Identity(mut inner) => {
+                inner.flush()?;
+                Ok(inner)
+            }
+            #[cfg(feature = "gzip")]
+            Self::Gzip(decoder) => decoder.finish(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for DecodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Identity(inner) => inner.write(buf),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(decoder) => decoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Identity(inner) => inner.flush(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(decoder) => decoder.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(decoder) => decoder.flush(),
+        }
+    }
+}
+
+/// Undoes `compression` on an in-memory body.
+pub(crate) fn decompress(
+    compression: Option<Compression>,
+    data: &[u8],
+) -> Result<Vec<u8>, R2Error> {
+    let mut decoder = DecodingWriter::new(compression, Vec::new())?;
+    decoder.write_all(data)?;
+    Ok(decoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"id,name\n1,pyrite\n2,pyrite\n3,pyrite\n4,pyrite\n5,pyrite\n";
+
+    #[test]
+    fn identity() {
+        assert_eq!(Compression::from_content_encoding(None).unwrap(), None);
+        assert_eq!(
+            Compression::from_content_encoding(Some("identity")).unwrap(),
+            None
+        );
+        assert_eq!(decompress(None, DATA).unwrap(), DATA);
+    }
+
+    #[test]
+    fn unsupported_encoding() {
+        assert!(Compression::from_content_encoding(Some("br")).is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        let compressed = Compression::Gzip.compress(DATA).unwrap();
+        assert_ne!(compressed, DATA);
+        let encoding = Compression::from_content_encoding(Some("gzip")).unwrap();
+        assert_eq!(encoding, Some(Compression::Gzip));
+        assert_eq!(decompress(encoding, &compressed).unwrap(), DATA);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        let compressed = Compression::Zstd.compress(DATA).unwrap();
+        assert_ne!(compressed, DATA);
+        let encoding = Compression::from_content_encoding(Some("zstd")).unwrap();
+        assert_eq!(encoding, Some(Compression::Zstd));
+        assert_eq!(decompress(encoding, &compressed).unwrap(), DATA);
+    }
+}
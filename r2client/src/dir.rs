@@ -0,0 +1,389 @@
+//! Mapping local directories to key prefixes (and back), for `upload_dir` and `download_prefix`.
+
+use crate::{DirOptions, R2Error};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// How one file of a directory transfer went.
+#[derive(Debug)]
+pub struct FileReport {
+    pub local_path: PathBuf,
+    pub key: String,
+    /// How many bytes were transferred, or why the file wasn't.
+    pub result: Result<u64, R2Error>,
+}
+
+/// Every file a directory transfer tried to move, sorted by key.
+///
+/// One file failing doesn't stop the others, so check [`DirReport::is_success`] (or go through
+/// [`DirReport::failed`]) instead of assuming everything made it.
+#[derive(Debug, Default)]
+pub struct DirReport {
+    pub files: Vec<FileReport>,
+}
+
+impl DirReport {
+    pub(crate) fn new(mut files: Vec<FileReport>) -> Self {
+        files.sort_by(|a, b| a.key.cmp(&b.key));
+        Self { files }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.files.iter().all(|file| file.result.is_ok())
+    }
+
+    pub fn succeeded(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|file| file.result.is_ok())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|file| file.result.is_err())
+    }
+
+    pub fn bytes_transferred(&self) -> u64 {
+        self.files
+            .iter()
+            .filter_map(|file| file.result.as_ref().ok())
+            .sum()
+    }
+}
+
+/// A file found while walking a directory.
+#[derive(Debug)]
+pub(crate) struct LocalFile {
+    pub(crate) path: PathBuf,
+    /// Relative to the directory that was walked, with `/` separators.
+    pub(crate) relative: String,
+    pub(crate) size: u64,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+/// Something under the walked directory that couldn't be looked at: a dangling symlink, a
+/// subdirectory without read permission, ...
+#[derive(Debug)]
+pub(crate) struct Unreadable {
+    pub(crate) path: PathBuf,
+    /// Relative to the directory that was walked, like [`LocalFile::relative`].
+    pub(crate) relative: String,
+    pub(crate) error: io::Error,
+}
+
+impl Unreadable {
+    /// Whether `relative` is this entry, or (for a directory) anywhere under it.
+    pub(crate) fn covers(&self, relative: &str) -> bool {
+        relative
+            .strip_prefix(&self.relative)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// A failed upload to `prefix`, for the report.
+    pub(crate) fn into_report(self, prefix: &str) -> FileReport {
+        FileReport {
+            key: format!("{prefix}{}", self.relative),
+            local_path: self.path,
+            result: Err(self.error.into()),
+        }
+    }
+}
+
+/// What walking a directory found.
+#[derive(Debug, Default)]
+pub(crate) struct Walk {
+    pub(crate) files: Vec<LocalFile>,
+    pub(crate) unreadable: Vec<Unreadable>,
+}
+
+/// Every file under `root`, recursively, sorted by relative path. Symlinks to files are
+/// followed, symlinks to directories aren't (so a link back up the tree can't loop forever).
+///
+/// Only fails if `root` itself can't be read. Anything below it that can't be is set aside in
+/// [`Walk::unreadable`] instead, so one broken symlink doesn't hide every other file.
+pub(crate) fn walk(root: &Path) -> io::Result<Walk> {
+    let mut walk = Walk::default();
+    let mut dirs = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, relative_dir)) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if relative_dir.is_empty() => return Err(e),
+            Err(error) => {
+                walk.unreadable.push(Unreadable {
+                    path: dir,
+                    relative: relative_dir.trim_end_matches('/').to_owned(),
+                    error,
+                });
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    walk.unreadable.push(Unreadable {
+                        path: dir.clone(),
+                        relative: relative_dir.trim_end_matches('/').to_owned(),
+                        error,
+                    });
+                    break;
+                }
+            };
+            let relative = format!("{relative_dir}{}", entry.file_name().to_string_lossy());
+            // `None` for directories, the (followed) metadata of anything else
+            let metadata = entry
+                .file_type()
+                .and_then(|file_type| match file_type.is_dir() {
+                    true => Ok(None),
+                    false => std::fs::metadata(entry.path()).map(Some),
+                });
+            match metadata {
+                Ok(None) => dirs.push((entry.path(), format!("{relative}/"))),
+                Ok(Some(metadata)) if metadata.is_file() => walk.files.push(LocalFile {
+                    path: entry.path(),
+                    relative,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                }),
+                Ok(Some(_)) => {}
+                Err(error) => walk.unreadable.push(Unreadable {
+                    path: entry.path(),
+                    relative,
+                    error,
+                }),
+            }
+        }
+    }
+    walk.files.sort_by(|a, b| a.relative.cmp(&b.relative));
+    walk.unreadable.sort_by(|a, b| a.relative.cmp(&b.relative));
+    Ok(walk)
+}
+
+/// Calls `f` on every item from up to `concurrency` threads at once, the blocking counterpart of
+/// `buffered`. Results come back in the same order as the items.
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+pub(crate) fn map_concurrently<T: Send, R: Send>(
+    items: Vec<T>,
+    concurrency: usize,
+    f: impl Fn(T) -> R + Sync,
+) -> Vec<R> {
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.max(1) {
+            scope.spawn(|| {
+                while let Some((index, item)) = next(&queue) {
+                    let result = f(item);
+                    results
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((index, result));
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Takes the next item without holding the lock while it's worked on.
+fn next<I: Iterator>(queue: &Mutex<I>) -> Option<I::Item> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner).next()
+}
+
+/// Prefixes are treated like directories, so `photos` means everything under `photos/` (and not
+/// `photos2/...`).
+pub(crate) fn dir_prefix(prefix: &str) -> String {
+    match prefix.trim_start_matches('/') {
+        "" => String::new(),
+        prefix if prefix.ends_with('/') => prefix.to_owned(),
+        prefix => format!("{prefix}/"),
+    }
+}
+
+/// Where the object at `relative` (its key with the prefix taken off) goes under `root`.
+/// Keys that would escape `root` or don't name a file (`a/../../b`, `a//b`, ...) are refused.
+pub(crate) fn local_path(root: &Path, relative: &str) -> Result<PathBuf, R2Error> {
+    let mut path = root.to_path_buf();
+    for component in relative.split('/') {
+        if matches!(component, "" | "." | "..") || component.contains('\\') {
+            return Err(R2Error::InvalidOptions(format!(
+                "\"{relative}\" can't be safely mapped to a local path"
+            )));
+        }
+        path.push(component);
+    }
+    Ok(path)
+}
+
+/// The include/exclude globs of [`DirOptions`], matched against relative paths.
+#[derive(Debug)]
+pub(crate) struct Filter {
+    /// `None` includes everything.
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    /// Fails with [`R2Error::InvalidOptions`] if any of the globs don't parse.
+    pub(crate) fn new(options: &DirOptions) -> Result<Self, R2Error> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&options.include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: glob_set(&options.exclude)?,
+        })
+    }
+
+    pub(crate) fn matches(&self, relative: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, R2Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(
+            Glob::new(glob)
+                .map_err(|e| R2Error::InvalidOptions(format!("invalid glob \"{glob}\": {e}")))?,
+        );
+    }
+    builder
+        .build()
+        .map_err(|e| R2Error::InvalidOptions(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(dir_prefix(""), "");
+        assert_eq!(dir_prefix("/"), "");
+        assert_eq!(dir_prefix("photos"), "photos/");
+        assert_eq!(dir_prefix("photos/"), "photos/");
+        assert_eq!(dir_prefix("/photos/2024"), "photos/2024/");
+    }
+
+    #[test]
+    fn local_paths() {
+        let root = Path::new("downloads");
+        assert_eq!(
+            local_path(root, "a/b.txt").unwrap(),
+            root.join("a").join("b.txt")
+        );
+        for relative in [
+            "../etc/passwd",
+            "a/../../b",
+            "a//b",
+            "a/",
+            "",
+            "./a",
+            "a\\..\\b",
+        ] {
+            assert!(local_path(root, relative).is_err(), "{relative}");
+        }
+    }
+
+    #[test]
+    fn filtering() {
+        let everything = Filter::new(&DirOptions::default()).unwrap();
+        assert!(everything.matches("a/b/c.txt"));
+
+        let options = DirOptions {
+            include: vec!["*.jpg".to_string(), "docs/**".to_string()],
+            exclude: vec!["**/thumbs/**".to_string()],
+            ..Default::default()
+        };
+        let filter = Filter::new(&options).unwrap();
+        assert!(filter.matches("cat.jpg"));
+        assert!(filter.matches("2024/cat.jpg"));
+        assert!(filter.matches("docs/readme.md"));
+        assert!(!filter.matches("notes.md"));
+        assert!(!filter.matches("2024/thumbs/cat.jpg"));
+
+        let invalid = DirOptions {
+            exclude: vec!["a[".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            Filter::new(&invalid),
+            Err(R2Error::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn walking() {
+        let dir = TempDir::new("walk");
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("nested/deeper")).unwrap();
+        std::fs::write(root.join("top.txt"), "top").unwrap();
+        std::fs::write(root.join("nested/deeper/file.bin"), [0; 10]).unwrap();
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+
+        // Doesn't fail the whole walk, just gets set aside
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("missing"), root.join("nested/dangling")).unwrap();
+
+        let walk = walk(root).unwrap();
+        let relative: Vec<_> = walk
+            .files
+            .iter()
+            .map(|file| file.relative.as_str())
+            .collect();
+        assert_eq!(relative, ["nested/deeper/file.bin", "top.txt"]);
+        assert_eq!(walk.files[0].size, 10);
+        #[cfg(unix)]
+        {
+            assert_eq!(walk.unreadable.len(), 1);
+            assert_eq!(walk.unreadable[0].relative, "nested/dangling");
+            assert!(walk.unreadable[0].covers("nested/dangling"));
+            assert!(!walk.unreadable[0].covers("nested/dangling2"));
+            let report = walk
+                .unreadable
+                .into_iter()
+                .next()
+                .unwrap()
+                .into_report("p/");
+            assert_eq!(report.key, "p/nested/dangling");
+            assert!(matches!(report.result, Err(R2Error::Io(_))));
+        }
+    }
+
+    #[test]
+    fn concurrently() {
+        let doubled = map_concurrently((0..100).collect(), 8, |n| n * 2);
+        assert_eq!(doubled, (0..100).map(|n| n * 2).collect::<Vec<_>>());
+        assert_eq!(map_concurrently(vec![1], 0, |n| n), [1]);
+    }
+
+    #[test]
+    fn report() {
+        let report = DirReport::new(vec![
+            FileReport {
+                local_path: PathBuf::from("b"),
+                key: "b".to_string(),
+                result: Err(R2Error::Cancelled),
+            },
+            FileReport {
+                local_path: PathBuf::from("a"),
+                key: "a".to_string(),
+                result: Ok(5),
+            },
+        ]);
+        assert_eq!(report.files[0].key, "a");
+        assert!(!report.is_success());
+        assert_eq!(report.succeeded().count(), 1);
+        assert_eq!(report.failed().next().unwrap().key, "b");
+        assert_eq!(report.bytes_transferred(), 5);
+    }
+}
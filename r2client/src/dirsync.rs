@@ -0,0 +1,671 @@
+//! Working out what it takes to make a bucket prefix match a local directory, or the other way
+//! around, rsync style: only what's new or changed gets transferred.
+
+use crate::R2Error;
+use crate::checksum::{etag_md5, file_md5};
+use crate::dir::{Filter, LocalFile, Walk, local_path};
+use crate::{ObjectInfo, SyncOptions};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How to tell whether a file and an object that share a path are the same.
+///
+/// Sizes are always compared first, anything with a different size has changed no matter what.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Comparison {
+    /// Same size means unchanged. Fast, but misses edits that keep the size.
+    SizeOnly,
+    /// Also transfers whatever is newer on the source side than on the destination. Uploading
+    /// or downloading something makes the destination newer, so it isn't transferred again.
+    #[default]
+    SizeAndModified,
+    /// Hashes local files and compares them against the ETags. Multipart and SSE-C objects don't
+    /// have an MD5 for an ETag, so those fall back to [`Comparison::SizeAndModified`]. Objects
+    /// count as SSE-C whenever the sync's options carry an SSE-C key.
+    Checksum,
+}
+
+/// Why something is being transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncReason {
+    /// Isn't on the destination side at all.
+    New,
+    SizeChanged,
+    /// Was modified on the source side after the destination was last written.
+    Newer,
+    /// Same size, different checksum.
+    ContentChanged,
+}
+
+impl fmt::Display for SyncReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SyncReason::New => "new",
+            SyncReason::SizeChanged => "size changed",
+            SyncReason::Newer => "newer",
+            SyncReason::ContentChanged => "content changed",
+        })
+    }
+}
+
+/// One step of a sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    Upload {
+        local_path: PathBuf,
+        key: String,
+        reason: SyncReason,
+    },
+    Download {
+        key: String,
+        local_path: PathBuf,
+        reason: SyncReason,
+    },
+    DeleteRemote {
+        key: String,
+    },
+    DeleteLocal {
+        local_path: PathBuf,
+    },
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::Upload {
+                local_path,
+                key,
+                reason,
+            } => write!(f, "upload {} -> {key} ({reason})", local_path.display()),
+            SyncAction::Download {
+                key,
+                local_path,
+                reason,
+            } => write!(f, "download {key} -> {} ({reason})", local_path.display()),
+            SyncAction::DeleteRemote { key } => write!(f, "delete {key}"),
+            SyncAction::DeleteLocal { local_path } => write!(f, "delete {}", local_path.display()),
+        }
+    }
+}
+
+/// A step of a sync, and how it went.
+#[derive(Debug)]
+pub struct SyncOutcome {
+    pub action: SyncAction,
+    /// `None` on dry runs, where nothing was actually done.
+    pub result: Option<Result<(), R2Error>>,
+}
+
+impl SyncOutcome {
+    fn planned(action: SyncAction) -> Self {
+        Self {
+            action,
+            result: None,
+        }
+    }
+}
+
+/// Everything a sync did (or would have done, on a dry run), in the order it was planned.
+///
+/// Its `Display` is one line per action, which makes for a decent dry-run printout.
+#[derive(Debug)]
+pub struct SyncReport {
+    pub dry_run: bool,
+    pub outcomes: Vec<SyncOutcome>,
+}
+
+impl SyncReport {
+    /// Whether everything went through (trivially true for dry runs and no-op syncs).
+    pub fn is_success(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &SyncOutcome> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome.result, Some(Err(_))))
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = &SyncAction> {
+        self.outcomes.iter().map(|outcome| &outcome.action)
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for outcome in &self.outcomes {
+            match &outcome.result {
+                None => writeln!(f, "(dry run) {}", outcome.action)?,
+                Some(Ok(())) => writeln!(f, "{}", outcome.action)?,
+                Some(Err(e)) => writeln!(f, "{} failed: {e}", outcome.action)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remote objects under `prefix` that the filter lets through, keyed by their relative path.
+fn remote_by_relative(
+    remote: Vec<ObjectInfo>,
+    prefix: &str,
+    filter: &Filter,
+) -> BTreeMap<String, ObjectInfo> {
+    remote
+        .into_iter()
+        .filter_map(|object| {
+            let relative = object.key.strip_prefix(prefix)?.to_owned();
+            (!relative.is_empty() && !relative.ends_with('/') && filter.matches(&relative))
+                .then_some((relative, object))
+        })
+        .collect()
+}
+
+/// What it takes to bring the objects under `prefix` in line with the local files. Objects
+/// whose local counterpart couldn't be read are left alone, rather than deleted as extraneous.
+pub(crate) fn plan_upload(
+    local: Walk,
+    remote: Vec<ObjectInfo>,
+    prefix: &str,
+    filter: &Filter,
+    options: &SyncOptions,
+) -> Result<Vec<SyncOutcome>, R2Error> {
+    if options.dir.put.compression.is_some() {
+        return Err(uncomparable("compression"));
+    }
+    let mut remote = remote_by_relative(remote, prefix, filter);
+    remote.retain(|relative, _| !local.unreadable.iter().any(|entry| entry.covers(relative)));
+    let mut actions = Vec::new();
+    for file in local
+        .files
+        .into_iter()
+        .filter(|file| filter.matches(&file.relative))
+    {
+        let (reason, result) = match remote.remove(&file.relative) {
+            None => (Some(SyncReason::New), None),
+            Some(object) => compared(changed(
+                &file,
+                &object,
+                options.comparison,
+                options.dir.put.sse_customer_key.is_some(),
+                true,
+            )),
+        };
+        if let Some(reason) = reason {
+            actions.push(SyncOutcome {
+                action: SyncAction::Upload {
+                    key: format!("{prefix}{}", file.relative),
+                    local_path: file.path,
+                    reason,
+                },
+                result,
+            });
+        }
+    }
+    if options.delete_extraneous {
+        actions.extend(
+            remote
+                .into_values()
+                .map(|object| SyncOutcome::planned(SyncAction::DeleteRemote { key: object.key })),
+        );
+    }
+    Ok(actions)
+}
+
+/// What it takes to bring the files under `local_dir` in line with the objects under `prefix`.
+pub(crate) fn plan_download(
+    local: Vec<LocalFile>,
+    remote: Vec<ObjectInfo>,
+    prefix: &str,
+    local_dir: &Path,
+    filter: &Filter,
+    options: &SyncOptions,
+) -> Result<Vec<SyncOutcome>, R2Error> {
+    if options.dir.get.decompress {
+        return Err(uncomparable("decompress"));
+    }
+    let mut local: BTreeMap<_, _> = local
+        .into_iter()
+        .filter(|file| filter.matches(&file.relative))
+        .map(|file| (file.relative.clone(), file))
+        .collect();
+    let mut actions = Vec::new();
+    for (relative, object) in remote_by_relative(remote, prefix, filter) {
+        let (reason, unhashable) = match local.remove(&relative) {
+            None => (Some(SyncReason::New), None),
+            Some(file) => compared(changed(
+                &file,
+                &object,
+                options.comparison,
+                options.dir.get.sse_customer_key.is_some(),
+                false,
+            )),
+        };
+        if let Some(reason) = reason {
+            // Keys that can't be mapped safely fail right away, and never get downloaded
+            let (path, result) = match local_path(local_dir, &relative) {
+                Ok(path) => (path, unhashable),
+                Err(e) => (local_dir.join(&relative), Some(Err(e))),
+            };
+            actions.push(SyncOutcome {
+                action: SyncAction::Download {
+                    key: object.key,
+                    local_path: path,
+                    reason,
+                },
+                result,
+            });
+        }
+    }
+    if options.delete_extraneous {
+        actions.extend(local.into_values().map(|file| {
+            SyncOutcome::planned(SyncAction::DeleteLocal {
+                local_path: file.path,
+            })
+        }));
+    }
+    Ok(actions)
+}
+
+/// Compressed objects are never the size of the files they came from, so nothing would ever
+/// look unchanged.
+fn uncomparable(option: &str) -> R2Error {
+    R2Error::InvalidOptions(format!(
+        "syncing can't be combined with {option}, compressed objects and their files can't be compared"
+    ))
+}
+
+/// A file that couldn't be hashed can't be told apart from its object, so it fails right away
+/// (as changed content) and is left alone, without holding up the rest of the sync.
+fn compared(
+    changed: io::Result<Option<SyncReason>>,
+) -> (Option<SyncReason>, Option<Result<(), R2Error>>) {
+    match changed {
+        Ok(reason) => (reason, None),
+        Err(e) => (Some(SyncReason::ContentChanged), Some(Err(e.into()))),
+    }
+}
+
+/// Why `file` and `object` differ, if they do. `upload` says which one is the source.
+fn changed(
+    file: &LocalFile,
+    object: &ObjectInfo,
+    comparison: Comparison,
+    sse_c: bool,
+    upload: bool,
+) -> io::Result<Option<SyncReason>> {
+    if file.size != object.size {
+        return Ok(Some(SyncReason::SizeChanged));
+    }
+    // SSE-C ETags look just like MD5s, but have nothing to do with the content
+    let etag = match sse_c {
+        true => None,
+        false => object.etag.as_deref().and_then(etag_md5),
+    };
+    match (comparison, etag) {
+        (Comparison::SizeOnly, _) => Ok(None),
+        (Comparison::Checksum, Some(etag)) => {
+            Ok((file_md5(&file.path)? != etag).then_some(SyncReason::ContentChanged))
+        }
+        (Comparison::Checksum | Comparison::SizeAndModified, _) => {
+            let newer = match (file.modified, object.last_modified) {
+                (Some(local), Some(remote)) if upload => local > remote,
+                (Some(local), Some(remote)) => remote > local,
+                // Without both times there's nothing to go on but the size
+                _ => false,
+            };
+            Ok(newer.then_some(SyncReason::Newer))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dir::Unreadable;
+    use crate::temp_dir::TempDir;
+    use crate::{DirOptions, SseCustomerKey};
+    use std::time::{Duration, SystemTime};
+
+    fn planned_actions(outcomes: Vec<SyncOutcome>) -> Vec<SyncAction> {
+        outcomes.into_iter().map(|outcome| outcome.action).collect()
+    }
+
+    fn at(seconds: u64) -> Option<SystemTime> {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    fn file(relative: &str, size: u64, modified: u64) -> LocalFile {
+        LocalFile {
+            path: Path::new("dir").join(relative),
+            relative: relative.to_owned(),
+            size,
+            modified: at(modified),
+        }
+    }
+
+    fn object(key: &str, size: u64, modified: u64) -> ObjectInfo {
+        ObjectInfo {
+            key: key.to_owned(),
+            size,
+            etag: None,
+            last_modified: at(modified),
+        }
+    }
+
+    fn walked(files: Vec<LocalFile>) -> Walk {
+        Walk {
+            files,
+            unreadable: Vec::new(),
+        }
+    }
+
+    fn everything() -> Filter {
+        Filter::new(&DirOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn upload_new_and_changed() {
+        let local = vec![
+            file("new.txt", 1, 100),
+            file("resized.txt", 2, 100),
+            file("touched.txt", 3, 300),
+            file("same.txt", 4, 100),
+        ];
+        let remote = vec![
+            object("p/resized.txt", 20, 200),
+            object("p/touched.txt", 3, 200),
+            object("p/same.txt", 4, 200),
+            object("p/gone.txt", 5, 200),
+            object("p/folder/", 0, 200),
+        ];
+        let actions = planned_actions(
+            plan_upload(
+                walked(local),
+                remote,
+                "p/",
+                &everything(),
+                &SyncOptions::default(),
+            )
+            .unwrap(),
+        );
+        let summary: Vec<_> = actions.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            summary,
+            [
+                format!(
+                    "upload {} -> p/new.txt (new)",
+                    Path::new("dir/new.txt").display()
+                ),
+                format!(
+                    "upload {} -> p/resized.txt (size changed)",
+                    Path::new("dir/resized.txt").display()
+                ),
+                format!(
+                    "upload {} -> p/touched.txt (newer)",
+                    Path::new("dir/touched.txt").display()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn size_only_ignores_times() {
+        let options = SyncOptions {
+            comparison: Comparison::SizeOnly,
+            ..Default::default()
+        };
+        let actions = plan_upload(
+            walked(vec![file("a", 1, 300)]),
+            vec![object("a", 1, 100)],
+            "",
+            &everything(),
+            &options,
+        )
+        .unwrap();
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn delete_extraneous_respects_globs() {
+        let options = SyncOptions {
+            delete_extraneous: true,
+            dir: DirOptions {
+                exclude: vec!["*.keep".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let filter = Filter::new(&options.dir).unwrap();
+        let remote = vec![object("p/gone.txt", 1, 0), object("p/precious.keep", 1, 0)];
+        let actions =
+            planned_actions(plan_upload(Walk::default(), remote, "p/", &filter, &options).unwrap());
+        assert_eq!(
+            actions,
+            [SyncAction::DeleteRemote {
+                key: "p/gone.txt".to_string()
+            }]
+        );
+
+        let local = vec![file("gone.txt", 1, 0), file("precious.keep", 1, 0)];
+        let actions = planned_actions(
+            plan_download(local, Vec::new(), "p/", Path::new("dir"), &filter, &options).unwrap(),
+        );
+        assert_eq!(
+            actions,
+            [SyncAction::DeleteLocal {
+                local_path: Path::new("dir").join("gone.txt")
+            }]
+        );
+    }
+
+    #[test]
+    fn unreadable_files_are_not_deleted() {
+        let options = SyncOptions {
+            delete_extraneous: true,
+            ..Default::default()
+        };
+        let local = Walk {
+            files: Vec::new(),
+            unreadable: vec![
+                Unreadable {
+                    path: Path::new("dir").join("dangling"),
+                    relative: "dangling".to_string(),
+                    error: io::ErrorKind::NotFound.into(),
+                },
+                Unreadable {
+                    path: Path::new("dir").join("locked"),
+                    relative: "locked".to_string(),
+                    error: io::ErrorKind::PermissionDenied.into(),
+                },
+            ],
+        };
+        let remote = vec![
+            object("p/dangling", 1, 0),
+            object("p/locked/a.txt", 1, 0),
+            object("p/locked.txt", 1, 0),
+        ];
+        let actions =
+            planned_actions(plan_upload(local, remote, "p/", &everything(), &options).unwrap());
+        assert_eq!(
+            actions,
+            [SyncAction::DeleteRemote {
+                key: "p/locked.txt".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn compression_is_refused() {
+        #[cfg(feature = "gzip")]
+        {
+            let mut options = SyncOptions::default();
+            options.dir.put.compression = Some(crate::Compression::Gzip);
+            let upload = plan_upload(Walk::default(), Vec::new(), "", &everything(), &options);
+            assert!(matches!(upload, Err(R2Error::InvalidOptions(_))));
+        }
+
+        let mut options = SyncOptions::default();
+        options.dir.get.decompress = true;
+        let download = plan_download(
+            Vec::new(),
+            Vec::new(),
+            "",
+            Path::new("dir"),
+            &everything(),
+            &options,
+        );
+        assert!(matches!(download, Err(R2Error::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn download_newer_remote() {
+        let local = vec![file("a/old.txt", 1, 100), file("a/fresh.txt", 1, 300)];
+        let remote = vec![
+            object("p/a/old.txt", 1, 200),
+            object("p/a/fresh.txt", 1, 200),
+            object("p/a/new.txt", 1, 200),
+        ];
+        let actions = planned_actions(
+            plan_download(
+                local,
+                remote,
+                "p/",
+                Path::new("dir"),
+                &everything(),
+                &SyncOptions::default(),
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            actions,
+            [
+                SyncAction::Download {
+                    key: "p/a/new.txt".to_string(),
+                    local_path: Path::new("dir").join("a").join("new.txt"),
+                    reason: SyncReason::New,
+                },
+                SyncAction::Download {
+                    key: "p/a/old.txt".to_string(),
+                    local_path: Path::new("dir").join("a").join("old.txt"),
+                    reason: SyncReason::Newer,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unsafe_keys_fail_without_downloading() {
+        let outcomes = plan_download(
+            Vec::new(),
+            vec![object("p/../escape.txt", 1, 0)],
+            "p/",
+            Path::new("dir"),
+            &everything(),
+            &SyncOptions::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            outcomes[0].result,
+            Some(Err(R2Error::InvalidOptions(_)))
+        ));
+    }
+
+    #[test]
+    fn checksum_against_etag() {
+        let dir = TempDir::new("dirsync");
+        let path = dir.path().join("hello.txt");
+        std::fs::write(&path, "hello world").unwrap();
+        let local = LocalFile {
+            path,
+            relative: "hello.txt".to_string(),
+            size: 11,
+            modified: at(0),
+        };
+        let mut remote = object("hello.txt", 11, 100);
+        remote.etag = Some("\"5eb63bbbe01eeed093cb22bb8f5acdc3\"".to_string());
+        let same = changed(&local, &remote, Comparison::Checksum, false, false).unwrap();
+        remote.etag = Some("\"00000000000000000000000000000000\"".to_string());
+        let different = changed(&local, &remote, Comparison::Checksum, false, false).unwrap();
+        // Multipart ETags fall back to the times, and the remote copy is newer
+        remote.etag = Some("\"5eb63bbbe01eeed093cb22bb8f5acdc3-2\"".to_string());
+        let multipart = changed(&local, &remote, Comparison::Checksum, false, false).unwrap();
+
+        assert_eq!(same, None);
+        assert_eq!(different, Some(SyncReason::ContentChanged));
+        assert_eq!(multipart, Some(SyncReason::Newer));
+    }
+
+    #[test]
+    fn unhashable_files_fail_alone() {
+        let options = SyncOptions {
+            comparison: Comparison::Checksum,
+            delete_extraneous: true,
+            ..Default::default()
+        };
+        // Neither file exists, so hashing the one that has an object fails
+        let local = || vec![file("gone.txt", 4, 100), file("new.txt", 4, 100)];
+        let mut remote = object("p/gone.txt", 4, 200);
+        remote.etag = Some("\"0123456789abcdef0123456789abcdef\"".to_string());
+
+        let uploads = plan_upload(
+            walked(local()),
+            vec![remote.clone()],
+            "p/",
+            &everything(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(uploads.len(), 2);
+        assert!(matches!(uploads[0].result, Some(Err(R2Error::Io(_)))));
+        assert!(uploads[1].result.is_none());
+
+        // Only the file the bucket doesn't have is extraneous, not the one that failed
+        let downloads = plan_download(
+            local(),
+            vec![remote],
+            "p/",
+            Path::new("dir"),
+            &everything(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(downloads.len(), 2);
+        assert!(matches!(downloads[0].result, Some(Err(R2Error::Io(_)))));
+        assert!(matches!(
+            downloads[1].action,
+            SyncAction::DeleteLocal { .. }
+        ));
+    }
+
+    #[test]
+    fn checksum_skips_sse_c_etags() {
+        let mut options = SyncOptions {
+            comparison: Comparison::Checksum,
+            ..Default::default()
+        };
+        options.dir.put.sse_customer_key = Some(SseCustomerKey::new([7; 32]));
+        // Would have to be hashed (and fail to open) if the ETag were trusted
+        let local = walked(vec![file("secret.bin", 4, 100)]);
+        let mut remote = object("p/secret.bin", 4, 200);
+        remote.etag = Some("\"0123456789abcdef0123456789abcdef\"".to_string());
+        let actions = plan_upload(local, vec![remote], "p/", &everything(), &options).unwrap();
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn report_display() {
+        let report = SyncReport {
+            dry_run: true,
+            outcomes: vec![SyncOutcome {
+                action: SyncAction::DeleteRemote {
+                    key: "p/gone.txt".to_string(),
+                },
+                result: None,
+            }],
+        };
+        assert_eq!(report.to_string(), "(dry run) delete p/gone.txt\n");
+        assert!(report.is_success());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::R2Error;
+use std::fmt;
+
+/// Where an R2 bucket's data lives, which changes the hostname of the S3 API endpoint.
+///
+/// https://developers.cloudflare.com/r2/reference/data-location/#jurisdictional-restrictions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Jurisdiction {
+    #[default]
+    Default,
+    /// European Union
+    Eu,
+    /// FedRAMP Moderate
+    FedRamp,
+}
+
+impl Jurisdiction {
+    /// The subdomain inserted between the account ID and `r2.cloudflarestorage.com`, if any.
+    fn subdomain(self) -> Option<&'static str> {
+        match self {
+            Jurisdiction::Default => None,
+            Jurisdiction::Eu => Some("eu"),
+            Jurisdiction::FedRamp => Some("fedramp"),
+        }
+    }
+}
+
+impl fmt::Display for Jurisdiction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.subdomain().unwrap_or("default"))
+    }
+}
+
+/// How the bucket name ends up in request URLs.
+///
+/// R2 handles both, but some S3-compatible gateways only understand one of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressingStyle {
+    /// `https://endpoint/bucket/key`
+    #[default]
+    Path,
+    /// `https://bucket.endpoint/key`
+    ///
+    /// The bucket name becomes part of the hostname, so it has to be a valid DNS label.
+    VirtualHosted,
+}
+
+/// Builds the S3 API endpoint for an account, e.g. `https://<account_id>.eu.r2.cloudflarestorage.com`
+///
+/// Account IDs are 32 hex digits, anything else (a pasted URL, a bucket name, ...) is refused
+/// here rather than ending up in a hostname that doesn't resolve.
+pub fn r2_endpoint(account_id: &str, jurisdiction: Jurisdiction) -> Result<String, R2Error> {
+    if account_id.len() != 32 || !account_id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(R2Error::InvalidEndpoint(format!(
+            "{account_id:?} isn't an account ID (32 hex digits)"
+        )));
+    }
+    Ok(match jurisdiction.subdomain() {
+        Some(subdomain) => format!("https://{account_id}.{subdomain}.r2.cloudflarestorage.com"),
+        None => format!("https://{account_id}.r2.cloudflarestorage.com"),
+    })
+}
+
+/// Builds the URL of an object (or of the bucket itself when there's no key), with `query`
+/// strictly encoded so it reads the same as the canonical query string it gets signed with.
+/// The host of this URL is what gets signed, so the addressing style carries over to signing.
+pub(crate) fn object_url(
+    endpoint: &str,
+    addressing_style: AddressingStyle,
+    bucket: &str,
+    key: Option<&str>,
+    query: &[(String, String)],
+) -> String {
+    let path = key.map(aws_sigv4::url_encode).unwrap_or_default();
+    let mut url = match addressing_style {
+        AddressingStyle::Path => format!("{endpoint}/{bucket}/{path}"),
+        AddressingStyle::VirtualHosted => {
+            // Endpoints are validated to have a scheme, so there's always a "://"
+            let (scheme, authority) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+            format!("{scheme}://{bucket}.{authority}/{path}")
+        }
+    };
+    for (i, (name, value)) in query.iter().enumerate() {
+        url.push(if i == 0 { '?' } else { '&' });
+        url.push_str(&aws_sigv4::query_encode(name));
+        url.push('=');
+        url.push_str(&aws_sigv4::query_encode(value));
+    }
+    url
+}
+
+/// Makes sure the endpoint is something requests can actually be built from, so it blows up
+/// when the client is made instead of on the first request.
+/// Trailing slashes are trimmed since `build_url` adds its own.
+pub(crate) fn validate_endpoint(endpoint: String) -> Result<String, R2Error> {
+    let endpoint = endpoint.trim().trim_end_matches('/').to_owned();
+    let uri: http::Uri = endpoint
+        .parse()
+        .map_err(|e| R2Error::InvalidEndpoint(format!("{endpoint:?} isn't a valid URI ({e})")))?;
+    match uri.scheme_str() {
+        Some("http" | "https") => {}
+        Some(scheme) => {
+            return Err(R2Error::InvalidEndpoint(format!(
+                "{endpoint:?} uses unsupported scheme {scheme:?}"
+            )));
+        }
+        None => {
+            return Err(R2Error::InvalidEndpoint(format!(
+                "{endpoint:?} is missing a scheme (e.g. https://)"
+            )));
+        }
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err(R2Error::InvalidEndpoint(format!(
+            "{endpoint:?} doesn't have a host"
+        )));
+    }
+    if uri.query().is_some() {
+        return Err(R2Error::InvalidEndpoint(format!(
+            "{endpoint:?} shouldn't have a query string"
+        )));
+    }
+    Ok(endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jurisdiction_endpoints() {
+        assert_eq!(
+            r2_endpoint("023e105f4ecef8ad9ca31a8372d0c353", Jurisdiction::Default).unwrap(),
+            "https://023e105f4ecef8ad9ca31a8372d0c353.r2.cloudflarestorage.com"
+        );
+        assert_eq!(
+            r2_endpoint("023e105f4ecef8ad9ca31a8372d0c353", Jurisdiction::Eu).unwrap(),
+            "https://023e105f4ecef8ad9ca31a8372d0c353.eu.r2.cloudflarestorage.com"
+        );
+        assert_eq!(
+            r2_endpoint("023e105f4ecef8ad9ca31a8372d0c353", Jurisdiction::FedRamp).unwrap(),
+            "https://023e105f4ecef8ad9ca31a8372d0c353.fedramp.r2.cloudflarestorage.com"
+        );
+    }
+
+    #[test]
+    fn invalid_account_ids() {
+        for account_id in [
+            "",
+            "abc123",
+            "023e105f4ecef8ad9ca31a8372d0c3530",
+            "023e105f4ecef8ad9ca31a8372d0c35g",
+            "https://023e105f4ecef8ad9ca31a8372d0c353.r2.cloudflarestorage.com",
+        ] {
+            assert!(matches!(
+                r2_endpoint(account_id, Jurisdiction::Default),
+                Err(R2Error::InvalidEndpoint(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn path_style_urls() {
+        let endpoint = "https://example.r2.cloudflarestorage.com";
+        assert_eq!(
+            object_url(
+                endpoint,
+                AddressingStyle::Path,
+                "bucket",
+                Some("a b/c.txt"),
+                &[]
+            ),
+            "https://example.r2.cloudflarestorage.com/bucket/a%20b/c.txt"
+        );
+        assert_eq!(
+            object_url(endpoint, AddressingStyle::Path, "bucket", None, &[]),
+            "https://example.r2.cloudflarestorage.com/bucket/"
+        );
+    }
+
+    #[test]
+    fn virtual_hosted_urls() {
+        assert_eq!(
+            object_url(
+                "https://example.r2.cloudflarestorage.com",
+                AddressingStyle::VirtualHosted,
+                "bucket",
+                Some("dir/file.txt"),
+                &[]
+            ),
+            "https://bucket.example.r2.cloudflarestorage.com/dir/file.txt"
+        );
+        assert_eq!(
+            object_url(
+                "http://localhost:9000",
+                AddressingStyle::VirtualHosted,
+                "bucket",
+                None,
+                &[]
+            ),
+            "http://bucket.localhost:9000/"
+        );
+    }
+
+    #[test]
+    fn query_is_encoded() {
+        let query = [
+            (
+                "response-content-disposition".to_string(),
+                "attachment; filename=\"a b.pdf\"".to_string(),
+            ),
+            (
+                "response-content-type".to_string(),
+                "text/plain".to_string(),
+            ),
+        ];
+        assert_eq!(
+            object_url(
+                "https://example.com",
+                AddressingStyle::Path,
+                "bucket",
+                Some("dir/a b.pdf"),
+                &query
+            ),
+            "https://example.com/bucket/dir/a%20b.pdf?response-content-disposition=attachment%3B%20filename%3D%22a%20b.pdf%22&response-content-type=text%2Fplain"
+        );
+    }
+
+    #[test]
+    fn trims_trailing_slash() {
+        assert_eq!(
+            validate_endpoint("https://example.r2.cloudflarestorage.com/".to_string()).unwrap(),
+            "https://example.r2.cloudflarestorage.com"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_endpoints() {
+        for endpoint in [
+            "",
+            "example.r2.cloudflarestorage.com",
+            "ftp://example.com",
+            "https://",
+            "https://example.com/?x=1",
+            "not a uri",
+        ] {
+            assert!(
+                matches!(
+                    validate_endpoint(endpoint.to_string()),
+                    Err(R2Error::InvalidEndpoint(_))
+                ),
+                "{endpoint:?} should be rejected"
+            );
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Envelope encryption for [`EncryptedBucket`](crate::EncryptedBucket).
+//!
+//! Every object gets its own random AES-256-GCM data key. The body is encrypted with the data
+//! key, and the data key is encrypted ("wrapped") with the master key. The wrapped key and the
+//! nonce ride along in the object's metadata, so R2 only ever sees ciphertext and a key it can't
+//! unwrap.
+//!
+//! Both the body and the wrapped key are bound to the object key (and the format version) as
+//! associated data, so whoever can write to the bucket can't swap two objects' ciphertext and
+//! metadata around without it being noticed. The flip side is that an encrypted object can't be
+//! copied to another key with a plain server-side copy, it has to be downloaded and re-uploaded.
+
+use crate::R2Error;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::collections::HashMap;
+use std::fmt;
+
+const CIPHER_METADATA: &str = "r2client-cipher";
+const WRAPPED_KEY_METADATA: &str = "r2client-wrapped-key";
+const NONCE_METADATA: &str = "r2client-nonce";
+const VERSION_METADATA: &str = "r2client-envelope-version";
+const CIPHER: &str = "AES-256-GCM";
+/// Bumped whenever what gets encrypted (or authenticated) changes.
+const VERSION: &str = "1";
+const NONCE_LEN: usize = 12;
+
+/// The key that wraps every object's data key. Keep it somewhere safe (a KMS, a secrets
+/// manager...), without it nothing encrypted under it can be read back.
+///
+/// Like [`crate::SseCustomerKey`], it never shows up in `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterKey {
+    key: [u8; 32],
+}
+
+impl MasterKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Fails with [`R2Error::InvalidOptions`] unless the key is exactly 32 bytes.
+    pub fn from_bytes(key: &[u8]) -> Result<Self, R2Error> {
+        let key = key.try_into().map_err(|_| {
+            R2Error::InvalidOptions(format!(
+                "master keys have to be 32 bytes long, got {}",
+                key.len()
+            ))
+        })?;
+        Ok(Self::new(key))
+    }
+
+    pub fn from_base64(key: &str) -> Result<Self, R2Error> {
+        let key = BASE64
+            .decode(key.trim())
+            .map_err(|e| R2Error::InvalidOptions(format!("master key isn't valid base64 ({e})")))?;
+        Self::from_bytes(&key)
+    }
+
+    /// A fresh random key, for when you're setting things up for the first time.
+    pub fn generate() -> Self {
+        Self::new(Aes256Gcm::generate_key(OsRng).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.key))
+    }
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+/// The associated data for `purpose` ("body" or "data key") of the object at `key`. It ties
+/// each ciphertext to its purpose and its object, so neither can be passed off as another one.
+fn aad(purpose: &str, key: &str) -> Vec<u8> {
+    format!("r2client envelope v{VERSION} {purpose}\n{key}").into_bytes()
+}
+
+/// Encrypts `plaintext`, to be stored at `key`, under a new data key.
+/// Returns the ciphertext and the metadata needed to decrypt it again.
+pub(crate) fn seal(
+    master_key: &MasterKey,
+    key: &str,
+    plaintext: &[u8],
+) -> Result<(Vec<u8>, HashMap<String, String>), R2Error> {
+    let data_key = Aes256Gcm::generate_key(OsRng);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(&data_key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &aad("body", key),
+            },
+        )
+        .map_err(|_| R2Error::Encryption("couldn't encrypt the object body".to_string()))?;
+
+    let wrap_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let wrapped_key = master_key
+        .cipher()
+        .encrypt(
+            &wrap_nonce,
+            Payload {
+                msg: data_key.as_slice(),
+                aad: &aad("data key", key),
+            },
+        )
+        .map_err(|_| R2Error::Encryption("couldn't wrap the data key".to_string()))?;
+
+    let metadata = HashMap::from([
+        (CIPHER_METADATA.to_string(), CIPHER.to_string()),
+        (VERSION_METADATA.to_string(), VERSION.to_string()),
+        (
+            WRAPPED_KEY_METADATA.to_string(),
+            BASE64.encode([wrap_nonce.as_slice(), &wrapped_key].concat()),
+        ),
+        (NONCE_METADATA.to_string(), BASE64.encode(nonce)),
+    ]);
+    Ok((ciphertext, metadata))
+}
+
+/// Reverses [`seal`] using the metadata R2 sent back with the object.
+pub(crate) fn open(
+    master_key: &MasterKey,
+    key: &str,
+    ciphertext: &[u8],
+    metadata: &HashMap<String, String>,
+) -> Result<Vec<u8>, R2Error> {
+    let field = |name: &str| {
+        metadata.get(name).ok_or_else(|| {
+            R2Error::Encryption(format!(
+                "\"{key}\" is missing its {name} metadata, was it uploaded through an EncryptedBucket?"
+            ))
+        })
+    };
+    let cipher = field(CIPHER_METADATA)?;
+    if cipher != CIPHER {
+        return Err(R2Error::Encryption(format!(
+            "\"{key}\" was encrypted with unsupported cipher {cipher:?}"
+        )));
+    }
+    let version = field(VERSION_METADATA)?;
+    if version != VERSION {
+        return Err(R2Error::Encryption(format!(
+            "\"{key}\" was encrypted with unsupported envelope version {version:?}"
+        )));
+    }
+    let decode = |name: &str| {
+        BASE64
+            .decode(field(name)?)
+            .map_err(|_| R2Error::Encryption(format!("\"{key}\" has malformed {name} metadata")))
+    };
+    let wrapped_key = decode(WRAPPED_KEY_METADATA)?;
+    let nonce = decode(NONCE_METADATA)?;
+    if wrapped_key.len() <= NONCE_LEN || nonce.len() != NONCE_LEN {
+        return Err(R2Error::Encryption(format!(
+            "\"{key}\" has malformed encryption metadata"
+        )));
+    }
+
+    let (wrap_nonce, wrapped_key) = wrapped_key.split_at(NONCE_LEN);
+    let data_key = master_key
+        .cipher()
+        .decrypt(
+            Nonce::from_slice(wrap_nonce),
+            Payload {
+                msg: wrapped_key,
+                aad: &aad("data key", key),
+            },
+        )
+        .map_err(|_| {
+            R2Error::Encryption(format!(
+                "couldn't unwrap the data key for \"{key}\" (wrong master key, or moved from another key?)"
+            ))
+        })?;
+    if data_key.len() != 32 {
+        return Err(R2Error::Encryption(format!(
+            "\"{key}\" has a data key of the wrong size"
+        )));
+    }
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &aad("body", key),
+            },
+        )
+        .map_err(|_| {
+            R2Error::Encryption(format!(
+                "couldn't decrypt \"{key}\", the object has been tampered with"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let master_key = MasterKey::generate();
+        let (ciphertext, metadata) = seal(&master_key, "key", b"top secret").unwrap();
+        assert_ne!(ciphertext, b"top secret");
+        assert_eq!(metadata[CIPHER_METADATA], CIPHER);
+        assert_eq!(
+            open(&master_key, "key", &ciphertext, &metadata).unwrap(),
+            b"top secret"
+        );
+    }
+
+    #[test]
+    fn unique_data_keys() {
+        let master_key = MasterKey::generate();
+        let (first, first_metadata) = seal(&master_key, "key", b"same").unwrap();
+        let (second, second_metadata) = seal(&master_key, "key", b"same").unwrap();
+        assert_ne!(first, second);
+        assert_ne!(
+            first_metadata[WRAPPED_KEY_METADATA],
+            second_metadata[WRAPPED_KEY_METADATA]
+        );
+    }
+
+    #[test]
+    fn wrong_master_key() {
+        let (ciphertext, metadata) = seal(&MasterKey::generate(), "key", b"top secret").unwrap();
+        assert!(matches!(
+            open(&MasterKey::generate(), "key", &ciphertext, &metadata),
+            Err(R2Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn swapped_objects() {
+        let master_key = MasterKey::generate();
+        let (ciphertext, metadata) = seal(&master_key, "a", b"meant for a").unwrap();
+        assert!(open(&master_key, "a", &ciphertext, &metadata).is_ok());
+        // Moved (with its metadata) to another key by someone who can write to the bucket
+        assert!(matches!(
+            open(&master_key, "b", &ciphertext, &metadata),
+            Err(R2Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_version() {
+        let master_key = MasterKey::generate();
+        let (ciphertext, mut metadata) = seal(&master_key, "key", b"top secret").unwrap();
+        metadata.insert(VERSION_METADATA.to_string(), "0".to_string());
+        assert!(open(&master_key, "key", &ciphertext, &metadata).is_err());
+    }
+
+    #[test]
+    fn tampered_body() {
+        let master_key = MasterKey::generate();
+        let (mut ciphertext, metadata) = seal(&master_key, "key", b"top secret").unwrap();
+        ciphertext[0] ^= 1;
+        assert!(open(&master_key, "key", &ciphertext, &metadata).is_err());
+    }
+
+    #[test]
+    fn missing_metadata() {
+        let master_key = MasterKey::generate();
+        let (ciphertext, mut metadata) = seal(&master_key, "key", b"top secret").unwrap();
+        metadata.remove(NONCE_METADATA);
+        assert!(open(&master_key, "key", &ciphertext, &metadata).is_err());
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let master_key = MasterKey::new([9; 32]);
+        assert!(!format!("{master_key:?}").contains('9'));
+    }
+}
@@ -10,8 +10,22 @@ pub enum R2Error {
     Xml(#[from] xmltree::ParseError),
     #[error("Missing environment varibles: {0}")]
     Env(String),
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+    #[error("Invalid options: {0}")]
+    InvalidOptions(String),
+    #[error("Invalid endpoint: {0}")]
+    InvalidEndpoint(String),
+    #[error("Integrity check failed for \"{0}\": expected {1}, got {2}")]
+    IntegrityMismatch(String, String, String),
     #[error("Request failed during operation {0}: {1}\n{2}")]
     FailedRequest(String, http::StatusCode, String),
+    #[error("Operation was cancelled")]
+    Cancelled,
+    #[error("Operation didn't finish before its deadline")]
+    DeadlineExceeded,
+    #[error("No recorded response for {0}")]
+    FixtureMissing(String),
 }
 
 pub type Result = std::result::Result<(), R2Error>;
@@ -0,0 +1,52 @@
+//! Keeping arbitrary text (object keys, response bodies) on a single line of the files that
+//! mirror checkpoints and fixtures are written to.
+
+/// Escapes backslashes, newlines and carriage returns, so `text` fits on one line (and a
+/// trailing `\r` isn't taken for part of a CRLF line ending when it's read back).
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// The other way round from [`escape`].
+pub(crate) fn unescape(line: &str) -> String {
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        for key in [
+            "plain",
+            "a\\nb",
+            "a\nb",
+            "trailing\\",
+            "\\\\n",
+            "crlf\r\n",
+            "trailing\r",
+            "a\\rb",
+        ] {
+            assert_eq!(unescape(&escape(key)), key);
+            // Whatever it contains, it reads back as the one line it was written as
+            assert_eq!(escape(key).lines().collect::<Vec<_>>(), [escape(key)]);
+        }
+    }
+}
@@ -0,0 +1,668 @@
+//! [`FileStorage`], a bucket kept in a directory, so apps can be developed locally against the
+//! same API they use with R2.
+
+use crate::dir::{local_path, walk};
+use crate::storage::{Upload, etag, metadata, no_such_key, now, serve, wrong_sse_customer_key};
+use crate::{
+    CopyOptions, GetObjectOutput, GetOptions, MimeRegistry, ObjectInfo, ObjectMetadata,
+    PutObjectOutput, PutOptions, R2Error, SseCustomerKey, Storage,
+};
+use bytes::Bytes;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Added to an object's file name for the sidecar its metadata is kept in.
+const SIDECAR_SUFFIX: &str = ".r2meta";
+/// Added to the names of files that are still being written, before they're renamed into place.
+const PARTIAL_SUFFIX: &str = ".r2partial";
+/// Where the sidecar keeps the MD5 of the SSE-C key an object was stored with (never the key).
+const SSE_CUSTOMER_KEY_MD5: &str = "x-amz-server-side-encryption-customer-key-md5";
+
+/// A bucket kept in a directory on disk, for running locally what normally runs against R2.
+///
+/// Objects are plain files at their key under the root (`notes/a.txt` is `root/notes/a.txt`), so
+/// the directory can be browsed and files can be dropped into it by hand. Everything else R2
+/// would keep about an object (content type, metadata, ETag, ...) goes in a sidecar next to it,
+/// `a.txt.r2meta`, as the `name: value` headers it gets served with. Files without a sidecar are
+/// served as if they'd been uploaded without any options.
+///
+/// Otherwise it behaves like [`MemoryStorage`](crate::MemoryStorage): ETags are the quoted MD5
+/// of the stored body, listings are sorted by key, missing keys fail with a 404
+/// [`R2Error::FailedRequest`] and SSE-C objects can only be read with their key (only its MD5 is
+/// written down).
+///
+/// Keys have to map to a path safely, so ones with empty, `.` or `..` segments or backslashes are
+/// refused with [`R2Error::InvalidOptions`], as are segments ending in `.r2meta` or `.r2partial`.
+/// And it's still a filesystem: `a` and `a/b` can't both exist.
+///
+/// The async methods do their file I/O in place too, it's meant for development and tests, not
+/// for serving traffic.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    root: PathBuf,
+    mime_registry: MimeRegistry,
+}
+
+impl FileStorage {
+    /// Keeps objects under `root`, which is created by the first upload if it doesn't exist.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            mime_registry: MimeRegistry::default(),
+        }
+    }
+
+    /// Infers content types with `mime_registry` instead of the default one, like
+    /// [`R2Client::with_mime_registry`](crate::R2Client::with_mime_registry).
+    pub fn with_mime_registry(mut self, mime_registry: MimeRegistry) -> Self {
+        self.mime_registry = mime_registry;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the object at `key` is kept.
+    fn path(&self, key: &str) -> Result<PathBuf, R2Error> {
+        if is_reserved(key) {
+            return Err(R2Error::InvalidOptions(format!(
+                "\"{key}\" can't be stored, {SIDECAR_SUFFIX} and {PARTIAL_SUFFIX} are reserved"
+            )));
+        }
+        local_path(&self.root, key)
+    }
+
+    fn put(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let path = self.path(key)?;
+        let mut upload = Upload::new(key, body, options, &self.mime_registry)?;
+        if let Some(sse_customer_key) = &options.sse_customer_key {
+            upload
+                .headers
+                .push((SSE_CUSTOMER_KEY_MD5.to_string(), sse_customer_key.key_md5()));
+        }
+        write(&path, &upload.body, &upload.headers)?;
+        Ok(upload.output)
+    }
+
+    fn get(&self, key: &str, options: &GetOptions) -> Result<GetObjectOutput, R2Error> {
+        options.interrupt().check()?;
+        let operation = || format!("getting object \"{key}\" from \"{}\"", self.root.display());
+        let (path, headers) = self.object(key, options.sse_customer_key.as_ref(), operation)?;
+        let body = match fs::read(&path) {
+            Ok(body) => body,
+            Err(e) if is_missing(&e) => return Err(no_such_key(operation())),
+            Err(e) => return Err(e.into()),
+        };
+        let mut metadata = metadata(headers)?;
+        // In case the file was replaced since its headers were read
+        metadata.content_length = Some(body.len() as u64);
+        serve(body.into(), metadata, options)
+    }
+
+    fn head(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        let (_, headers) = self.object(key, None, || {
+            format!("heading object \"{key}\" in \"{}\"", self.root.display())
+        })?;
+        metadata(headers)
+    }
+
+    fn copy(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let destination = self.path(key)?;
+        let operation = || {
+            format!(
+                "copying \"{source_key}\" to \"{key}\" in \"{}\"",
+                self.root.display()
+            )
+        };
+        let (source, headers) = self.object(
+            source_key,
+            options.source_sse_customer_key.as_ref(),
+            operation,
+        )?;
+        let body = match fs::read(&source) {
+            Ok(body) => body,
+            Err(e) if is_missing(&e) => return Err(no_such_key(operation())),
+            Err(e) => return Err(e.into()),
+        };
+        let mut headers: Vec<_> = headers
+            .into_iter()
+            .filter(|(name, _)| {
+                !matches!(
+                    name.as_str(),
+                    "content-length" | "last-modified" | SSE_CUSTOMER_KEY_MD5
+                )
+            })
+            .collect();
+        headers.push(("last-modified".to_string(), httpdate::fmt_http_date(now())));
+        if let Some(sse_customer_key) = &options.sse_customer_key {
+            headers.push((SSE_CUSTOMER_KEY_MD5.to_string(), sse_customer_key.key_md5()));
+        }
+        write(&destination, &body, &headers)?;
+        Ok(PutObjectOutput {
+            etag: header(&headers, "etag").map(str::to_owned),
+            checksum: None,
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<(), R2Error> {
+        let path = self.path(key)?;
+        remove(&sidecar_path(&path))?;
+        if path.is_file() {
+            remove(&path)?;
+        }
+        // Directories are only there for the keys in them, the way prefixes are
+        let mut dir = path.parent();
+        while let Some(parent) = dir
+            && parent.starts_with(&self.root)
+            && parent != self.root
+            && fs::remove_dir(parent).is_ok()
+        {
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        // Only the directory the prefix ends in has to be walked
+        let dir = &prefix[..prefix.rfind('/').map_or(0, |index| index + 1)];
+        let root = match dir.strip_suffix('/') {
+            Some(dir) => match local_path(&self.root, dir) {
+                Ok(root) => root,
+                // No key that could be stored is under it
+                Err(_) => return Ok(Vec::new()),
+            },
+            None => self.root.clone(),
+        };
+        let files = match walk(&root) {
+            Ok(walk) => walk.files,
+            Err(e) if is_missing(&e) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut objects = Vec::new();
+        for file in files {
+            let key = format!("{dir}{}", file.relative);
+            if !key.starts_with(prefix) || is_reserved(&key) {
+                continue;
+            }
+            let Some(headers) = self.headers(&file.path, &key)? else {
+                continue;
+            };
+            let metadata = metadata(headers)?;
+            objects.push(ObjectInfo {
+                key,
+                size: file.size,
+                etag: metadata.etag,
+                last_modified: metadata.last_modified,
+            });
+        }
+        Ok(objects)
+    }
+
+    /// Where the object at `key` is and the headers it's served with, as long as it was stored
+    /// with `sse_customer_key` (or without one if that's `None`), failing the way R2 would
+    /// otherwise.
+    fn object(
+        &self,
+        key: &str,
+        sse_customer_key: Option<&SseCustomerKey>,
+        operation: impl FnOnce() -> String,
+    ) -> Result<(PathBuf, Vec<(String, String)>), R2Error> {
+        let path = self.path(key)?;
+        let Some(headers) = self.headers(&path, key)? else {
+            return Err(no_such_key(operation()));
+        };
+        let key_md5 = sse_customer_key.map(SseCustomerKey::key_md5);
+        if header(&headers, SSE_CUSTOMER_KEY_MD5) != key_md5.as_deref() {
+            return Err(wrong_sse_customer_key(operation()));
+        }
+        Ok((path, headers))
+    }
+
+    /// The headers the file at `path` is served with, from its sidecar or made up like R2 would
+    /// for an upload without options if it doesn't have one. `None` if there's no such file.
+    fn headers(&self, path: &Path, key: &str) -> Result<Option<Vec<(String, String)>>, R2Error> {
+        let file = match fs::metadata(path) {
+            Ok(file) if file.is_file() => file,
+            Ok(_) => return Ok(None),
+            Err(e) if is_missing(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut headers = match fs::read_to_string(sidecar_path(path)) {
+            Ok(sidecar) => sidecar
+                .lines()
+                .filter_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    Some((name.trim().to_owned(), value.trim().to_owned()))
+                })
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let body = fs::read(path)?;
+                let last_modified = file.modified().unwrap_or_else(|_| SystemTime::now());
+                vec![
+                    (
+                        "content-type".to_string(),
+                        self.mime_registry.infer(key, &body).to_owned(),
+                    ),
+                    ("etag".to_string(), etag(&body)),
+                    (
+                        "last-modified".to_string(),
+                        httpdate::fmt_http_date(last_modified),
+                    ),
+                ]
+            }
+            Err(e) => return Err(e.into()),
+        };
+        headers.push(("content-length".to_string(), file.len().to_string()));
+        Ok(Some(headers))
+    }
+}
+
+/// Whether `key` has a segment that could be mistaken for one of the files kept next to objects.
+fn is_reserved(key: &str) -> bool {
+    key.split('/')
+        .any(|segment| segment.ends_with(SIDECAR_SUFFIX) || segment.ends_with(PARTIAL_SUFFIX))
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(SIDECAR_SUFFIX);
+    sidecar.into()
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Whether `e` means there's nothing at a path, including when something on the way there is a
+/// file instead of a directory (or the other way round).
+fn is_missing(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::IsADirectory
+    )
+}
+
+/// Writes an object and then its sidecar, each replacing whatever was there at once.
+fn write(path: &Path, body: &[u8], headers: &[(String, String)]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let sidecar: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\n"))
+        .collect();
+    replace(path, body)?;
+    replace(&sidecar_path(path), sidecar.as_bytes())
+}
+
+/// Writes `contents` next to `path` and renames it into place, so nobody reads half of it.
+fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(format!(
+        ".{}-{}{PARTIAL_SUFFIX}",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let partial = PathBuf::from(partial);
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, path).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if !is_missing(&e) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl Storage for FileStorage {
+    async fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.put(key, body, options)
+    }
+
+    async fn get_object(
+        &self,
+        key: &str,
+        options: &GetOptions,
+    ) -> Result<GetObjectOutput, R2Error> {
+        self.get(key, options)
+    }
+
+    async fn head_object(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        self.head(key)
+    }
+
+    async fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.copy(source_key, key, options)
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<(), R2Error> {
+        self.delete(key)
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        self.list(prefix)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl crate::sync::Storage for FileStorage {
+    fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.put(key, body, options)
+    }
+
+    fn get_object(&self, key: &str, options: &GetOptions) -> Result<GetObjectOutput, R2Error> {
+        self.get(key, options)
+    }
+
+    fn head_object(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        self.head(key)
+    }
+
+    fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.copy(source_key, key, options)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), R2Error> {
+        self.delete(key)
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        self.list(prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use http::StatusCode;
+    use std::collections::HashMap;
+
+    fn status(error: R2Error) -> StatusCode {
+        match error {
+            R2Error::FailedRequest(_, status, _) => status,
+            error => panic!("expected a failed request, got {error}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = TempDir::new("file-storage-round-trip");
+        let storage = &FileStorage::new(dir.path());
+        let options = PutOptions {
+            cache_control: Some("max-age=60".to_string()),
+            metadata: HashMap::from([("Owner".to_string(), "pyrite".to_string())]),
+            ..Default::default()
+        };
+        let output = storage
+            .put_object("notes/hello.txt", Bytes::from("hello world"), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            output.etag.as_deref(),
+            Some("\"5eb63bbbe01eeed093cb22bb8f5acdc3\"")
+        );
+        let path = storage.root().join("notes").join("hello.txt");
+        assert_eq!(fs::read(&path).unwrap(), b"hello world");
+        assert!(sidecar_path(&path).is_file());
+
+        // Everything is on disk, so another storage on the same directory sees the same object
+        let reopened = FileStorage::new(storage.root());
+        let object = reopened
+            .get_object("notes/hello.txt", &GetOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(object.body, "hello world");
+        assert_eq!(object.metadata.etag, output.etag);
+        assert_eq!(object.metadata.content_length, Some(11));
+        assert_eq!(object.metadata.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(object.metadata.cache_control.as_deref(), Some("max-age=60"));
+        assert_eq!(object.metadata.metadata["owner"], "pyrite");
+        assert_eq!(
+            reopened.head_object("notes/hello.txt").await.unwrap(),
+            object.metadata
+        );
+    }
+
+    #[tokio::test]
+    async fn unsafe_keys() {
+        let dir = TempDir::new("file-storage-unsafe-keys");
+        // Nothing should get as far as creating the root
+        let storage = &FileStorage::new(dir.path().join("root"));
+        for key in [
+            "../escape",
+            "a/../../b",
+            "a//b",
+            "/a",
+            "a\\b",
+            "",
+            "a.r2meta",
+            "a.r2meta/b",
+        ] {
+            let result = storage
+                .put_object(key, Bytes::from("x"), &PutOptions::default())
+                .await;
+            assert!(
+                matches!(result, Err(R2Error::InvalidOptions(_))),
+                "{key:?} was stored"
+            );
+        }
+        assert!(!storage.root().exists());
+    }
+
+    #[tokio::test]
+    async fn missing_keys() {
+        let dir = TempDir::new("file-storage-missing-keys");
+        let storage = &FileStorage::new(dir.path());
+        storage
+            .put_object("a/b", Bytes::from("b"), &PutOptions::default())
+            .await
+            .unwrap();
+        for key in ["nope", "a", "a/b/c"] {
+            let error = storage
+                .get_object(key, &GetOptions::default())
+                .await
+                .unwrap_err();
+            assert_eq!(status(error), StatusCode::NOT_FOUND, "{key}");
+            let error = storage.head_object(key).await.unwrap_err();
+            assert_eq!(status(error), StatusCode::NOT_FOUND, "{key}");
+            storage.delete_object(key).await.unwrap();
+        }
+        assert_eq!(storage.list_objects("").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn listing() {
+        let dir = TempDir::new("file-storage-listing");
+        let storage = &FileStorage::new(dir.path());
+        for key in ["b", "a/b", "ab", "a/a", "a.txt", "a/c/d"] {
+            storage
+                .put_object(key, Bytes::from(key), &PutOptions::default())
+                .await
+                .unwrap();
+        }
+        let keys = |objects: &[ObjectInfo]| -> Vec<String> {
+            objects.iter().map(|object| object.key.clone()).collect()
+        };
+        // Sidecars never show up
+        assert_eq!(
+            keys(&storage.list_objects("").await.unwrap()),
+            ["a.txt", "a/a", "a/b", "a/c/d", "ab", "b"]
+        );
+        assert_eq!(
+            keys(&storage.list_objects("a/").await.unwrap()),
+            ["a/a", "a/b", "a/c/d"]
+        );
+        assert_eq!(keys(&storage.list_objects("a/c").await.unwrap()), ["a/c/d"]);
+        assert!(storage.list_objects("c/").await.unwrap().is_empty());
+        assert!(storage.list_objects("a/../").await.unwrap().is_empty());
+
+        let listing = storage.list_objects_delimited("", "/").await.unwrap();
+        assert_eq!(keys(&listing.objects), ["a.txt", "ab", "b"]);
+        assert_eq!(listing.common_prefixes, ["a/"]);
+        let listing = storage.list_objects_delimited("a/", "/").await.unwrap();
+        assert_eq!(keys(&listing.objects), ["a/a", "a/b"]);
+        assert_eq!(listing.common_prefixes, ["a/c/"]);
+        let object = &listing.objects[1];
+        assert_eq!(object.size, 3);
+        assert_eq!(object.etag.as_deref(), Some(etag(b"a/b").as_str()));
+        assert!(object.last_modified.is_some());
+    }
+
+    #[tokio::test]
+    async fn files_without_sidecars() {
+        let dir = TempDir::new("file-storage-without-sidecars");
+        let storage = &FileStorage::new(dir.path());
+        fs::create_dir_all(storage.root().join("site")).unwrap();
+        fs::write(storage.root().join("site").join("index.html"), "<html>").unwrap();
+
+        let object = storage
+            .get_object("site/index.html", &GetOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(object.body, "<html>");
+        assert_eq!(object.metadata.content_type.as_deref(), Some("text/html"));
+        assert_eq!(object.metadata.etag, Some(etag(b"<html>")));
+        assert_eq!(
+            storage.list_objects("site/").await.unwrap()[0].etag,
+            object.metadata.etag
+        );
+    }
+
+    #[tokio::test]
+    async fn copy_and_delete() {
+        let dir = TempDir::new("file-storage-copy-and-delete");
+        let storage = &FileStorage::new(dir.path());
+        let options = PutOptions {
+            content_type: Some("application/x-custom".to_string()),
+            ..Default::default()
+        };
+        let put = storage
+            .put_object("deep/down/a", Bytes::from("data"), &options)
+            .await
+            .unwrap();
+        let copied = storage
+            .copy_object("deep/down/a", "b", &CopyOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(copied.etag, put.etag);
+        assert_eq!(
+            storage
+                .head_object("b")
+                .await
+                .unwrap()
+                .content_type
+                .as_deref(),
+            Some("application/x-custom")
+        );
+
+        storage.delete_object("deep/down/a").await.unwrap();
+        // The directories it was in went with it, the root stays
+        assert!(!storage.root().join("deep").exists());
+        assert!(storage.root().is_dir());
+        let keys: Vec<_> = storage
+            .list_objects("")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        assert_eq!(keys, ["b"]);
+        let error = storage
+            .copy_object("deep/down/a", "c", &CopyOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status(error), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn sse_customer_keys() {
+        let dir = TempDir::new("file-storage-sse-customer-keys");
+        let storage = &FileStorage::new(dir.path());
+        let key = SseCustomerKey::new([7; 32]);
+        let options = PutOptions {
+            sse_customer_key: Some(key.clone()),
+            ..Default::default()
+        };
+        storage
+            .put_object("secret", Bytes::from("shh"), &options)
+            .await
+            .unwrap();
+        let sidecar = fs::read_to_string(sidecar_path(&storage.root().join("secret"))).unwrap();
+        assert!(sidecar.contains(&key.key_md5()));
+        assert!(!sidecar.contains("x-amz-server-side-encryption-customer-key:"));
+
+        let error = storage
+            .get_object("secret", &GetOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status(error), StatusCode::BAD_REQUEST);
+        let with_key = GetOptions {
+            sse_customer_key: Some(key),
+            ..Default::default()
+        };
+        assert_eq!(
+            storage.get_object("secret", &with_key).await.unwrap().body,
+            "shh"
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn blocking() {
+        use crate::sync::Storage;
+
+        let dir = TempDir::new("file-storage-blocking");
+        let storage = &FileStorage::new(dir.path());
+        Storage::put_object(storage, "a/b", Bytes::from("b"), &PutOptions::default()).unwrap();
+        let listing = Storage::list_objects_delimited(storage, "", "/").unwrap();
+        assert_eq!(listing.common_prefixes, ["a/"]);
+        assert_eq!(
+            Storage::get_object(storage, "a/b", &GetOptions::default())
+                .unwrap()
+                .body,
+            "b"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use crate::R2Error;
+use crate::escape::{escape, unescape};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use reqwest::Url;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Headers that give away credentials, they're written down as `REDACTED`.
+const REDACTED_HEADERS: [&str; 4] = [
+    "authorization",
+    "x-amz-security-token",
+    "x-amz-server-side-encryption-customer-key",
+    "x-amz-copy-source-server-side-encryption-customer-key",
+];
+/// Query parameters that are different every time a URL is presigned, they're normalised to
+/// `REDACTED` so requests still match when replayed.
+const SIGNATURE_PARAMS: [&str; 4] = [
+    "X-Amz-Credential",
+    "X-Amz-Date",
+    "X-Amz-Security-Token",
+    "X-Amz-Signature",
+];
+const REDACTED: &str = "REDACTED";
+/// Pins down the request body, unless it's `UNSIGNED-PAYLOAD`.
+const CONTENT_SHA256: &str = "x-amz-content-sha256";
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// HTTP traffic of a client, recorded to a fixture file once (against R2 itself) and replayed
+/// from it afterwards, so tests are deterministic and don't need credentials or a network.
+///
+/// Set it with [`R2Client::with_fixtures`](crate::R2Client::with_fixtures). When replaying,
+/// requests are answered with the first recorded response to the same method, path, query and
+/// `x-amz-content-sha256` that hasn't been used yet, so the same request made twice gets the two
+/// responses it got back then (recorded retries included). Presigned URL signatures are
+/// normalised before matching, and a request nothing was recorded for (a PUT of a different
+/// body, say) fails with [`R2Error::FixtureMissing`].
+///
+/// The file is plain text, one exchange after the other:
+///
+/// ```text
+/// > PUT /bucket/hello.txt
+/// > authorization: REDACTED
+/// > x-amz-content-sha256: 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824
+/// < 200
+/// < etag: "5d41402abc4b2a76b9719d911017c592"
+/// ```
+///
+/// `>` lines are the request and its headers, `<` lines the response status and headers, and a
+/// `|` line the response body (with backslashes and newlines escaped) or a `*` line the body in
+/// base64 when it isn't text. Credentials and SSE-C keys are redacted, and request bodies are
+/// left out since `x-amz-content-sha256` already pins them down.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    path: PathBuf,
+    mode: Arc<Mode>,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record(Mutex<File>),
+    /// Every recorded exchange, and whether it's been replayed already.
+    Replay(Mutex<Vec<(Exchange, bool)>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Exchange {
+    method: String,
+    /// The path and normalised query.
+    target: String,
+    request_headers: Vec<(String, String)>,
+    status: u16,
+    response_headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl Fixtures {
+    /// Records every exchange to `path`, replacing whatever was there.
+    pub fn record(path: impl Into<PathBuf>) -> Result<Self, R2Error> {
+        let path = path.into();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&path)?;
+        Ok(Self {
+            path,
+            mode: Arc::new(Mode::Record(Mutex::new(file))),
+        })
+    }
+
+    /// Replays the exchanges recorded to `path`.
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self, R2Error> {
+        let path = path.into();
+        let exchanges = parse(&std::fs::read_to_string(&path)?)?;
+        Ok(Self {
+            path,
+            mode: Arc::new(Mode::Replay(Mutex::new(
+                exchanges
+                    .into_iter()
+                    .map(|exchange| (exchange, false))
+                    .collect(),
+            ))),
+        })
+    }
+
+    /// Replays `path` if it's there, records to it otherwise, so the first run of a test talks
+    /// to R2 and every run after that doesn't.
+    pub fn once(path: impl Into<PathBuf>) -> Result<Self, R2Error> {
+        let path = path.into();
+        if path.exists() {
+            Self::replay(path)
+        } else {
+            Self::record(path)
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(*self.mode, Mode::Replay(_))
+    }
+
+    /// Sends `request`, or answers it from the fixtures. The outer error is for fixture problems,
+    /// the inner one for the request itself, which the caller may retry.
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Result<reqwest::Response>, R2Error> {
+        let (client, request) = request.build_split();
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return Ok(Err(e)),
+        };
+        if self.is_replaying() {
+            return Ok(Ok(self
+                .replay_exchange(request.method(), request.url(), request.headers())?
+                .into()));
+        }
+        let (method, url, headers) = (
+            request.method().clone(),
+            request.url().clone(),
+            request.headers().clone(),
+        );
+        let response = match client.execute(request).await {
+            Ok(response) => response,
+            Err(e) => return Ok(Err(e)),
+        };
+        let (status, response_headers) = (response.status(), response.headers().clone());
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(e) => return Ok(Err(e)),
+        };
+        let exchange = Exchange::new(&method, &url, &headers, status, &response_headers, body);
+        self.record_exchange(&exchange)?;
+        Ok(Ok(exchange.response().into()))
+    }
+
+    /// The blocking version of [`Fixtures::send`].
+    #[cfg(feature = "sync")]
+    pub(crate) fn send_blocking(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> Result<reqwest::Result<reqwest::blocking::Response>, R2Error> {
+        let (client, request) = request.build_split();
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return Ok(Err(e)),
+        };
+        if self.is_replaying() {
+            return Ok(Ok(self
+                .replay_exchange(request.method(), request.url(), request.headers())?
+                .into()));
+        }
+        let (method, url, headers) = (
+            request.method().clone(),
+            request.url().clone(),
+            request.headers().clone(),
+        );
+        let response = match client.execute(request) {
+            Ok(response) => response,
+            Err(e) => return Ok(Err(e)),
+        };
+        let (status, response_headers) = (response.status(), response.headers().clone());
+        let body = match response.bytes() {
+            Ok(body) => body,
+            Err(e) => return Ok(Err(e)),
+        };
+        let exchange = Exchange::new(&method, &url, &headers, status, &response_headers, body);
+        self.record_exchange(&exchange)?;
+        Ok(Ok(exchange.response().into()))
+    }
+
+    fn replay_exchange(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &HeaderMap,
+    ) -> Result<http::Response<Bytes>, R2Error> {
+        let Mode::Replay(exchanges) = &*self.mode else {
+            unreachable!("only called when replaying");
+        };
+        let target = target(url);
+        let payload = headers
+            .get(CONTENT_SHA256)
+            .and_then(|value| value.to_str().ok());
+        let mut exchanges = exchanges.lock().unwrap_or_else(PoisonError::into_inner);
+        let (exchange, replayed) = exchanges
+            .iter_mut()
+            .find(|(exchange, replayed)| !replayed && exchange.matches(method, &target, payload))
+            .ok_or_else(|| {
+                R2Error::FixtureMissing(format!(
+                    "{method} {target} ({CONTENT_SHA256}: {}) in {}",
+                    payload.unwrap_or("none"),
+                    self.path.display()
+                ))
+            })?;
+        *replayed = true;
+        Ok(exchange.response())
+    }
+
+    /// Writes `exchange` down right away, so it survives the test failing halfway.
+    fn record_exchange(&self, exchange: &Exchange) -> io::Result<()> {
+        let Mode::Record(file) = &*self.mode else {
+            unreachable!("only called when recording");
+        };
+        let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(exchange.to_string().as_bytes())
+    }
+}
+
+impl Exchange {
+    fn new(
+        method: &Method,
+        url: &Url,
+        request_headers: &HeaderMap,
+        status: StatusCode,
+        response_headers: &HeaderMap,
+        body: Bytes,
+    ) -> Self {
+        Self {
+            method: method.to_string(),
+            target: target(url),
+            request_headers: redacted(request_headers),
+            status: status.as_u16(),
+            response_headers: redacted(response_headers),
+            body,
+        }
+    }
+
+    /// Whether this is what was recorded for a request, which has to carry the same body unless
+    /// the recorded one went unsigned.
+    fn matches(&self, method: &Method, target: &str, payload: Option<&str>) -> bool {
+        let recorded = self
+            .request_headers
+            .iter()
+            .find(|(name, _)| name == CONTENT_SHA256)
+            .map(|(_, value)| value.as_str());
+        self.method == method.as_str()
+            && self.target == target
+            && match recorded {
+                None | Some(UNSIGNED_PAYLOAD) => true,
+                recorded => recorded == payload,
+            }
+    }
+
+    fn response(&self) -> http::Response<Bytes> {
+        let mut response = http::Response::new(self.body.clone());
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        for (name, value) in &self.response_headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        response
+    }
+}
+
+impl std::fmt::Display for Exchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "> {} {}", self.method, self.target)?;
+        for (name, value) in &self.request_headers {
+            writeln!(f, "> {name}: {value}")?;
+        }
+        writeln!(f, "< {}", self.status)?;
+        for (name, value) in &self.response_headers {
+            writeln!(f, "< {name}: {value}")?;
+        }
+        match std::str::from_utf8(&self.body) {
+            _ if self.body.is_empty() => Ok(()),
+            Ok(text) => writeln!(f, "| {}", escape(text)),
+            Err(_) => writeln!(f, "* {}", BASE64.encode(&self.body)),
+        }
+    }
+}
+
+/// The path and query of `url`, with presigned URL signatures normalised.
+fn target(url: &Url) -> String {
+    let Some(query) = url.query() else {
+        return url.path().to_owned();
+    };
+    let query: Vec<_> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SIGNATURE_PARAMS.contains(&name) => format!("{name}={REDACTED}"),
+            _ => pair.to_owned(),
+        })
+        .collect();
+    format!("{}?{}", url.path(), query.join("&"))
+}
+
+fn redacted(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or(REDACTED)
+            };
+            (name.as_str().to_owned(), value.to_owned())
+        })
+        .collect()
+}
+
+fn parse(contents: &str) -> io::Result<Vec<Exchange>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected line in fixture file: {line:?}"),
+        )
+    };
+    let header = |line: &str| {
+        line.split_once(": ")
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .ok_or_else(|| invalid(line))
+    };
+    #[derive(PartialEq)]
+    enum Section {
+        Request,
+        Response,
+        /// After the body, only a new request can come next
+        Done,
+    }
+    let mut exchanges: Vec<Exchange> = Vec::new();
+    let mut section = Section::Done;
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let (marker, rest) = line.split_at_checked(2).ok_or_else(|| invalid(line))?;
+        match (marker, exchanges.last_mut()) {
+            ("> ", Some(exchange)) if section == Section::Request => {
+                exchange.request_headers.push(header(rest)?);
+            }
+            ("> ", _) => {
+                let (method, target) = rest.split_once(' ').ok_or_else(|| invalid(line))?;
+                exchanges.push(Exchange {
+                    method: method.to_owned(),
+                    target: target.to_owned(),
+                    request_headers: Vec::new(),
+                    status: 0,
+                    response_headers: Vec::new(),
+                    body: Bytes::new(),
+                });
+                section = Section::Request;
+            }
+            ("< ", Some(exchange)) if section == Section::Request => {
+                exchange.status = rest.parse().map_err(|_| invalid(line))?;
+                section = Section::Response;
+            }
+            ("< ", Some(exchange)) if section == Section::Response => {
+                exchange.response_headers.push(header(rest)?);
+            }
+            ("| ", Some(exchange)) if section == Section::Response => {
+                exchange.body = Bytes::from(unescape(rest));
+                section = Section::Done;
+            }
+            ("* ", Some(exchange)) if section == Section::Response => {
+                exchange.body = BASE64.decode(rest).map_err(|_| invalid(line))?.into();
+                section = Section::Done;
+            }
+            _ => return Err(invalid(line)),
+        }
+    }
+    Ok(exchanges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(target: &str, status: u16, body: &[u8]) -> Exchange {
+        Exchange {
+            method: "GET".to_string(),
+            target: target.to_owned(),
+            request_headers: vec![("authorization".to_string(), REDACTED.to_string())],
+            status,
+            response_headers: vec![("etag".to_string(), "\"abc\"".to_string())],
+            body: Bytes::copy_from_slice(body),
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let url = Url::parse(
+            "https://example.com/bucket/a%20key?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=20240101T000000Z&X-Amz-Signature=abc&x-id=GetObject",
+        )
+        .unwrap();
+        assert_eq!(
+            target(&url),
+            "/bucket/a%20key?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=REDACTED&X-Amz-Signature=REDACTED&x-id=GetObject"
+        );
+        let url = Url::parse("https://example.com/bucket/key").unwrap();
+        assert_eq!(target(&url), "/bucket/key");
+    }
+
+    #[test]
+    fn redacting() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("AWS4-HMAC-SHA256 ..."),
+        );
+        headers.insert(
+            "x-amz-server-side-encryption-customer-key",
+            HeaderValue::from_static("c2VjcmV0"),
+        );
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        let mut redacted = redacted(&headers);
+        redacted.sort();
+        assert_eq!(
+            redacted,
+            [
+                ("authorization".to_string(), REDACTED.to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+                (
+                    "x-amz-server-side-encryption-customer-key".to_string(),
+                    REDACTED.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_matches_payloads() {
+        let put = |hash: &str| Exchange {
+            method: "PUT".to_string(),
+            request_headers: vec![(CONTENT_SHA256.to_string(), hash.to_owned())],
+            ..exchange("/bucket/key", 200, b"")
+        };
+        let fixtures = Fixtures {
+            path: PathBuf::from("fixtures.txt"),
+            mode: Arc::new(Mode::Replay(Mutex::new(vec![
+                (put("aaaa"), false),
+                (put(UNSIGNED_PAYLOAD), false),
+            ]))),
+        };
+        let url = Url::parse("https://example.com/bucket/key").unwrap();
+        let sent = |hash: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_SHA256, HeaderValue::from_static(hash));
+            fixtures.replay_exchange(&Method::PUT, &url, &headers)
+        };
+
+        // A different body skips the first exchange, whose body was signed, not the second one
+        assert!(sent("bbbb").is_ok());
+        assert!(matches!(sent("bbbb"), Err(R2Error::FixtureMissing(_))));
+        assert!(sent("aaaa").is_ok());
+    }
+
+    #[test]
+    fn file_roundtrip() {
+        let exchanges = vec![
+            exchange("/bucket/text", 200, b"<a>\r\nline\\two</a>\r"),
+            exchange("/bucket/binary", 200, &[0, 159, 146, 150, b'\r']),
+            exchange("/bucket/?list-type=2", 404, b""),
+        ];
+        let contents: String = exchanges.iter().map(ToString::to_string).collect();
+        assert_eq!(parse(&contents).unwrap(), exchanges);
+        assert!(parse("> GET /a\n| body before a response\n").is_err());
+        assert!(parse("< 200\n").is_err());
+    }
+}
@@ -1,12 +1,65 @@
+mod cancel;
+mod checksum;
+mod compression;
+mod dir;
+mod dirsync;
+mod endpoint;
+#[cfg(feature = "encryption")]
+mod envelope;
 mod error;
+mod escape;
+mod file_storage;
+mod fixtures;
+mod listing;
 mod mimetypes;
+mod mirror;
+mod multipart;
+mod object;
+mod options;
+mod progress;
+mod provider;
+mod ratelimit;
+mod retry;
+mod sse;
+mod storage;
 // Should r2client::Result be r2client::R2Result just in case someone does a glob import or
 // something? Or should that be left to the user of the library to use the "as" keyword?
+pub use cancel::CancellationToken;
+pub use checksum::{Checksum, ChecksumAlgorithm};
+pub use compression::Compression;
+pub use dir::{DirReport, FileReport};
+pub use dirsync::{Comparison, SyncAction, SyncOutcome, SyncReason, SyncReport};
+#[cfg(feature = "encryption")]
+pub use envelope::MasterKey;
+pub use endpoint::{AddressingStyle, Jurisdiction, r2_endpoint};
 pub use error::{R2Error, Result};
+pub use file_storage::FileStorage;
+pub use fixtures::Fixtures;
+pub use mimetypes::MimeRegistry;
+pub use mirror::{MirrorMethod, MirrorOutcome, MirrorReport};
+pub use multipart::{CompletedPart, DEFAULT_PART_SIZE, MAX_PARTS, MIN_PART_SIZE, MultipartUpload};
+pub use object::{GetObjectOutput, Listing, ObjectInfo, ObjectMetadata, PutObjectOutput};
+pub use options::{
+    CopyOptions, DirOptions, GetOptions, MirrorOptions, PutOptions, SyncOptions,
+};
+pub use progress::{PartProgress, Progress, ProgressObserver};
+pub use provider::Provider;
+pub use ratelimit::RateLimiter;
+pub use retry::{Jitter, RetryPolicy, RetryableError};
+pub use sse::SseCustomerKey;
+pub use storage::{MemoryStorage, Storage};
 
 mod _async;
 #[cfg(feature = "async")]
 pub use _async::{R2Bucket, R2Client};
+#[cfg(all(feature = "async", feature = "encryption"))]
+pub use _async::EncryptedBucket;
+#[cfg(feature = "object_store")]
+pub use _async::R2Store;
 
 #[cfg(feature = "sync")]
 pub mod sync;
+#[cfg(any(test, feature = "test-support"))]
+mod temp_dir;
+#[cfg(feature = "test-support")]
+pub mod test_support;
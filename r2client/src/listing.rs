@@ -0,0 +1,254 @@
+//! Listing the objects in a bucket (ListObjectsV2), a page at a time.
+
+use crate::{Listing, ObjectInfo, R2Error};
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+
+/// One page of a listing, and where the next one starts if there is one.
+#[derive(Debug)]
+pub(crate) struct ListPage {
+    pub(crate) objects: Vec<ObjectInfo>,
+    /// What the keys under a delimiter were rolled up into, only there if one was given.
+    pub(crate) common_prefixes: Vec<String>,
+    pub(crate) next_continuation_token: Option<String>,
+}
+
+/// With a `delimiter`, keys that have it after `prefix` are rolled up into common prefixes
+/// instead of being listed, like subdirectories are.
+pub(crate) fn list_query(
+    prefix: &str,
+    delimiter: Option<&str>,
+    continuation_token: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut query = vec![("list-type".to_string(), "2".to_string())];
+    if !prefix.is_empty() {
+        query.push(("prefix".to_string(), prefix.to_owned()));
+    }
+    if let Some(delimiter) = delimiter {
+        query.push(("delimiter".to_string(), delimiter.to_owned()));
+    }
+    if let Some(token) = continuation_token {
+        query.push(("continuation-token".to_string(), token.to_owned()));
+    }
+    query
+}
+
+/// Parses a `<ListBucketResult>`.
+pub(crate) fn parse_list_page(xml: &str) -> Result<ListPage, R2Error> {
+    let root = xmltree::Element::parse(xml.as_bytes())?;
+    let text = |element: &xmltree::Element, name: &str| {
+        element
+            .get_child(name)
+            .and_then(|child| child.get_text())
+            .map(|text| text.into_owned())
+    };
+    let elements = |name: &'static str| {
+        root.children
+            .iter()
+            .filter_map(|child| child.as_element())
+            .filter(move |element| element.name == name)
+    };
+    let objects = elements("Contents")
+        .filter_map(|contents| {
+            Some(ObjectInfo {
+                key: text(contents, "Key")?,
+                size: text(contents, "Size")
+                    .and_then(|size| size.parse().ok())
+                    .unwrap_or_default(),
+                etag: text(contents, "ETag"),
+                last_modified: text(contents, "LastModified")
+                    .and_then(|date| parse_timestamp(&date)),
+            })
+        })
+        .collect();
+    let common_prefixes = elements("CommonPrefixes")
+        .filter_map(|common_prefix| text(common_prefix, "Prefix"))
+        .collect();
+    let truncated = text(&root, "IsTruncated").is_some_and(|truncated| truncated == "true");
+    Ok(ListPage {
+        objects,
+        common_prefixes,
+        next_continuation_token: text(&root, "NextContinuationToken").filter(|_| truncated),
+    })
+}
+
+/// Rolls `objects` (sorted by key, all starting with `prefix`) up at `delimiter` the way R2
+/// would, for storages that can only list everything under a prefix. An empty delimiter rolls
+/// nothing up.
+pub(crate) fn roll_up(objects: Vec<ObjectInfo>, prefix: &str, delimiter: &str) -> Listing {
+    let mut listing = Listing::default();
+    for object in objects {
+        let rolled_up = object.key[prefix.len()..]
+            .find(delimiter)
+            .filter(|_| !delimiter.is_empty());
+        match rolled_up {
+            Some(index) => {
+                let common_prefix = &object.key[..prefix.len() + index + delimiter.len()];
+                // Keys sharing a prefix are next to each other once sorted
+                if listing.common_prefixes.last().map(String::as_str) != Some(common_prefix) {
+                    listing.common_prefixes.push(common_prefix.to_owned());
+                }
+            }
+            None => listing.objects.push(object),
+        }
+    }
+    listing
+}
+
+/// Parses the ISO 8601 timestamps listings use, e.g. `2009-10-12T17:50:30.000Z`.
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(SystemTime::from)
+}
+
+/// Formats a time the way listings do, the other way round from [`parse_timestamp`].
+#[cfg_attr(not(feature = "test-support"), allow(dead_code))]
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parsing_a_page() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>photos/</Prefix>
+  <KeyCount>2</KeyCount>
+  <MaxKeys>1000</MaxKeys>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+  <Contents>
+    <Key>photos/2006/January/sample.jpg</Key>
+    <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+    <ETag>"bf1d737a4d46a19f3bced6905cc8b902"</ETag>
+    <Size>142863</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Contents>
+  <Contents>
+    <Key>photos/2006/February/sample.jpg</Key>
+    <Size>0</Size>
+  </Contents>
+</ListBucketResult>"#;
+        let page = parse_list_page(xml).unwrap();
+        assert_eq!(page.objects.len(), 2);
+        assert_eq!(
+            page.objects[0],
+            ObjectInfo {
+                key: "photos/2006/January/sample.jpg".to_string(),
+                size: 142863,
+                etag: Some("\"bf1d737a4d46a19f3bced6905cc8b902\"".to_string()),
+                last_modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1255369830)),
+            }
+        );
+        assert_eq!(page.objects[1].last_modified, None);
+        assert!(page.common_prefixes.is_empty());
+        assert_eq!(
+            page.next_continuation_token.as_deref(),
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=")
+        );
+
+        let last_page = parse_list_page(
+            "<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>",
+        )
+        .unwrap();
+        assert!(last_page.objects.is_empty());
+        assert_eq!(last_page.next_continuation_token, None);
+    }
+
+    #[test]
+    fn common_prefixes() {
+        let xml = r#"<ListBucketResult>
+  <Prefix>photos/</Prefix>
+  <Delimiter>/</Delimiter>
+  <IsTruncated>false</IsTruncated>
+  <Contents><Key>photos/cover.jpg</Key><Size>3</Size></Contents>
+  <CommonPrefixes><Prefix>photos/2006/</Prefix></CommonPrefixes>
+  <CommonPrefixes><Prefix>photos/2007/</Prefix></CommonPrefixes>
+</ListBucketResult>"#;
+        let page = parse_list_page(xml).unwrap();
+        assert_eq!(page.objects.len(), 1);
+        assert_eq!(page.common_prefixes, ["photos/2006/", "photos/2007/"]);
+    }
+
+    #[test]
+    fn rolling_up() {
+        let objects = [
+            "photos/a.jpg",
+            "photos/2006/a.jpg",
+            "photos/2006/b.jpg",
+            "photos/2007/c/d.jpg",
+        ]
+        .map(|key| ObjectInfo {
+            key: key.to_string(),
+            size: 0,
+            etag: None,
+            last_modified: None,
+        });
+        let listing = roll_up(objects.to_vec(), "photos/", "/");
+        assert_eq!(listing.objects, [objects[0].clone()]);
+        assert_eq!(listing.common_prefixes, ["photos/2006/", "photos/2007/"]);
+
+        let listing = roll_up(objects[1..].to_vec(), "photos/2", "/c/");
+        assert_eq!(listing.objects.len(), 2);
+        assert_eq!(listing.common_prefixes, ["photos/2007/c/"]);
+        assert_eq!(roll_up(objects.to_vec(), "", "").objects.len(), 4);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(
+            parse_timestamp("1970-01-01T00:00:00Z"),
+            Some(SystemTime::UNIX_EPOCH)
+        );
+        assert_eq!(
+            parse_timestamp("2024-02-29T12:00:00.5Z"),
+            Some(
+                SystemTime::UNIX_EPOCH
+                    + Duration::from_secs(1709208000)
+                    + Duration::from_millis(500)
+            )
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+        // Out of range, and not ASCII where the fraction is
+        assert_eq!(parse_timestamp("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-02-30T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-01-40T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-01-01T24:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-01-01T00:00:00.éZ"), None);
+    }
+
+    #[test]
+    fn formatting_timestamps() {
+        for timestamp in [
+            "1970-01-01T00:00:00.000Z",
+            "2024-02-29T12:00:00.500Z",
+            "2009-10-12T17:50:30.000Z",
+            "2100-03-01T23:59:59.999Z",
+        ] {
+            assert_eq!(
+                format_timestamp(parse_timestamp(timestamp).unwrap()),
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn query() {
+        assert_eq!(
+            list_query("", None, None),
+            vec![("list-type".to_string(), "2".to_string())]
+        );
+        assert_eq!(list_query("a/", None, Some("token")).len(), 3);
+        assert!(
+            list_query("a/", Some("/"), None).contains(&("delimiter".to_string(), "/".to_string()))
+        );
+    }
+}
@@ -1,88 +1,427 @@
-pub fn get_mimetype(key: &str) -> &'static str {
-    match key {
-        // Image formats
-        ".png" => "image/png",
-        ".jpg" | ".jpeg" => "image/jpeg",
-        ".gif" => "image/gif",
-        ".svg" => "image/svg+xml",
-        ".ico" => "image/x-icon",
-        ".webp" => "image/webp",
-
-        // Audio formats
-        ".m4a" => "audio/x-m4a",
-        ".mp3" => "audio/mpeg",
-        ".wav" => "audio/wav",
-        ".ogg" => "audio/ogg",
-
-        // Video formats
-        ".mp4" => "video/mp4",
-        ".avi" => "video/x-msvideo",
-        ".mov" => "video/quicktime",
-        ".flv" => "video/x-flv",
-        ".wmv" => "video/x-ms-wmv",
-        ".webm" => "video/webm",
-
-        // Document formats
-        ".pdf" => "application/pdf",
-        ".doc" => "application/msword",
-        ".docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
-        ".ppt" => "application/vnd.ms-powerpoint",
-        ".pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
-        ".xls" => "application/vnd.ms-excel",
-        ".xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-        ".txt" => "text/plain",
-
-        // Web formats
-        ".html" => "text/html",
-        ".css" => "text/css",
-        ".js" => "application/javascript",
-        ".json" => "application/json",
-        ".xml" => "application/xml",
-
-        // Other formats
-        ".csv" => "text/csv",
-        ".zip" => "application/zip",
-        ".tar" => "application/x-tar",
-        ".gz" => "application/gzip",
-        ".rar" => "application/vnd.rar",
-        ".7z" => "application/x-7z-compressed",
-        ".eps" => "application/postscript",
-        ".sql" => "application/sql",
-        ".java" => "text/x-java-source",
-        _ => "application/octet-stream",
+use std::collections::{HashMap, HashSet};
+
+const DEFAULT_MIMETYPE: &str = "application/octet-stream";
+
+/// The extensions [`MimeRegistry::new`] starts out with.
+const DEFAULT_MIMETYPES: &[(&str, &str)] = &[
+    // Image formats
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("jfif", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("jxl", "image/jxl"),
+    ("psd", "image/vnd.adobe.photoshop"),
+    // Audio formats
+    ("m4a", "audio/x-m4a"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    ("weba", "audio/webm"),
+    // Video formats
+    ("mp4", "video/mp4"),
+    ("m4v", "video/x-m4v"),
+    ("avi", "video/x-msvideo"),
+    ("mov", "video/quicktime"),
+    ("flv", "video/x-flv"),
+    ("wmv", "video/x-ms-wmv"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("ogv", "video/ogg"),
+    ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),
+    ("ts", "video/mp2t"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("3gp", "video/3gpp"),
+    // Document formats
+    ("pdf", "application/pdf"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("rtf", "application/rtf"),
+    ("epub", "application/epub+zip"),
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("rst", "text/x-rst"),
+    ("tex", "application/x-tex"),
+    // Web formats
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("cjs", "application/javascript"),
+    ("map", "application/json"),
+    ("json", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("wasm", "application/wasm"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Data formats
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("ini", "text/plain"),
+    ("ndjson", "application/x-ndjson"),
+    ("jsonl", "application/jsonl"),
+    ("parquet", "application/vnd.apache.parquet"),
+    ("avro", "application/avro"),
+    ("arrow", "application/vnd.apache.arrow.file"),
+    ("sqlite", "application/vnd.sqlite3"),
+    ("db", "application/vnd.sqlite3"),
+    ("sql", "application/sql"),
+    ("ics", "text/calendar"),
+    ("vcf", "text/vcard"),
+    // Archives
+    ("zip", "application/zip"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("tar.gz", "application/x-compressed-tar"),
+    ("tgz", "application/x-compressed-tar"),
+    ("tar.bz2", "application/x-bzip-compressed-tar"),
+    ("tbz2", "application/x-bzip-compressed-tar"),
+    ("tar.xz", "application/x-xz-compressed-tar"),
+    ("txz", "application/x-xz-compressed-tar"),
+    ("tar.zst", "application/x-zstd-compressed-tar"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("br", "application/x-brotli"),
+    ("rar", "application/vnd.rar"),
+    ("7z", "application/x-7z-compressed"),
+    ("jar", "application/java-archive"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("deb", "application/vnd.debian.binary-package"),
+    ("rpm", "application/x-rpm"),
+    ("dmg", "application/x-apple-diskimage"),
+    ("iso", "application/x-iso9660-image"),
+    // Source code
+    ("java", "text/x-java-source"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("go", "text/x-go"),
+    ("sh", "application/x-sh"),
+    // Other formats
+    ("eps", "application/postscript"),
+    ("ps", "application/postscript"),
+    ("exe", "application/vnd.microsoft.portable-executable"),
+    ("bin", "application/octet-stream"),
+];
+
+/// Maps file extensions to content types, which is how uploads get their content type when
+/// it's neither passed in nor sniffed from the content.
+///
+/// [`MimeRegistry::new`] (and `Default`) comes with a big table of common types, and anything
+/// in it can be overridden or removed. Extensions registered (or overridden) on a registry are
+/// taken at their word, even over what the content gets sniffed as. Give the result to
+/// [`R2Client::with_mime_registry`](crate::R2Client::with_mime_registry) to use it for uploads.
+///
+/// ```
+/// # use r2client::MimeRegistry;
+/// let mut registry = MimeRegistry::new();
+/// registry.register("gltf", "model/gltf+json");
+/// assert_eq!(registry.from_path("scene.gltf"), "model/gltf+json");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeRegistry {
+    mimetypes: HashMap<String, String>,
+    /// Extensions that were registered on purpose, rather than coming from the default table.
+    registered: HashSet<String>,
+}
+
+impl MimeRegistry {
+    /// A registry with the default table.
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        for (extension, mimetype) in DEFAULT_MIMETYPES {
+            registry
+                .mimetypes
+                .insert(normalize_extension(extension), mimetype.to_string());
+        }
+        registry
+    }
+
+    /// A registry that doesn't know any extensions, so everything is `application/octet-stream`
+    /// unless it's sniffed or registered.
+    pub fn empty() -> Self {
+        Self {
+            mimetypes: HashMap::new(),
+            registered: HashSet::new(),
+        }
+    }
+
+    /// Maps `extension` (with or without the leading '.') to `mimetype`, returning whatever it
+    /// mapped to before. Extensions are case-insensitive, and can be compound ("tar.gz").
+    pub fn register(&mut self, extension: &str, mimetype: impl Into<String>) -> Option<String> {
+        let extension = normalize_extension(extension);
+        self.registered.insert(extension.clone());
+        self.mimetypes.insert(extension, mimetype.into())
+    }
+
+    /// Same as [`MimeRegistry::register`], for chaining.
+    pub fn with(mut self, extension: &str, mimetype: impl Into<String>) -> Self {
+        self.register(extension, mimetype);
+        self
+    }
+
+    pub fn unregister(&mut self, extension: &str) -> Option<String> {
+        let extension = normalize_extension(extension);
+        self.registered.remove(&extension);
+        self.mimetypes.remove(&extension)
+    }
+
+    /// The content type for `extension`, if it's known.
+    pub fn get(&self, extension: &str) -> Option<&str> {
+        self.mimetypes
+            .get(&normalize_extension(extension))
+            .map(String::as_str)
+    }
+
+    /// The content type for a file path (or object key), going by the extension of its file
+    /// name. The longest registered extension wins, so "a.tar.gz" is "tar.gz" rather than "gz".
+    pub fn from_path(&self, file_path: &str) -> &str {
+        self.lookup(file_path)
+            .map_or(DEFAULT_MIMETYPE, |(_, mimetype)| mimetype)
+    }
+
+    /// The longest known extension of `file_path` (normalised) and its content type.
+    fn lookup(&self, file_path: &str) -> Option<(String, &str)> {
+        extensions(file_path).find_map(|extension| {
+            let extension = normalize_extension(extension);
+            let mimetype = self.mimetypes.get(&extension)?;
+            Some((extension, mimetype.as_str()))
+        })
+    }
+
+    /// Picks the content type for `data` about to be stored under `file_path`.
+    ///
+    /// An extension registered on this registry wins outright. Otherwise sniffing the content
+    /// wins, unless it only found a generic container and the extension is more specific. The
+    /// extension is the fallback for anything that can't be sniffed, and signatures that are too
+    /// short to be sure of are only trusted when there's no extension to go by (a .csv can start
+    /// with "BZh" too).
+    pub fn infer<'a>(&'a self, file_path: &str, data: &[u8]) -> &'a str {
+        let found = self.lookup(file_path);
+        if let Some((extension, mimetype)) = &found
+            && self.registered.contains(extension)
+        {
+            return mimetype;
+        }
+        let from_extension = found.map_or(DEFAULT_MIMETYPE, |(_, mimetype)| mimetype);
+        match sniff_mimetype(data) {
+            Some(sniffed)
+                if CONTAINER_MIMETYPES.contains(&sniffed) && from_extension != DEFAULT_MIMETYPE =>
+            {
+                from_extension
+            }
+            // Unmatched containers are still better off as the generic type, except for the
+            // Office one which nothing knows what to do with
+            Some("application/x-ole-storage") => DEFAULT_MIMETYPE,
+            Some(sniffed) => sniffed,
+            None if from_extension == DEFAULT_MIMETYPE => {
+                sniff_weak_mimetype(data).unwrap_or(DEFAULT_MIMETYPE)
+            }
+            None => from_extension,
+        }
+    }
+}
+
+impl Default for MimeRegistry {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-pub fn get_mimetype_from_fp(file_path: &str) -> &str {
-    // Sorry I just really wanted to get it done in a one liner.
-    // This splits a filepath based off ".", in reverse order, so that the first element will
-    // be the file extension (e.g. "~/.config/test.jpeg" becomes "jpeg")
-    // This is formated back to ".jpeg" because it's how the match statement is working.
-    // I could very easily change it but idk it was an interesting thing.
-    //
-    // Hey, so maybe you should change the match statement to not care about the '.'?
-    // Then again this is just being used for this project, so I guess it doesn't really matter
-    get_mimetype(&format!(
-        ".{}",
-        file_path
-            .rsplit(".")
-            .next()
-            .unwrap_or("time_to_be_an_octet_stream_lmao")
-    ))
+/// Every extension of the last path component, longest first ("a.tar.gz" gives "tar.gz" and
+/// then "gz"). Leading dots mark hidden files rather than extensions, so ".bashrc" has none.
+fn extensions(file_path: &str) -> impl Iterator<Item = &str> {
+    let file_name = file_path.rsplit(['/', '\\']).next().unwrap_or(file_path);
+    let name = file_name.trim_start_matches('.');
+    name.match_indices('.')
+        .map(move |(i, _)| &name[i + 1..])
+        .filter(|extension| !extension.is_empty())
 }
 
+fn normalize_extension(extension: &str) -> String {
+    extension.trim_start_matches('.').to_lowercase()
+}
+
+/// Guesses the content type from the first few bytes of a file, for when the name doesn't say.
+/// `None` means none of the signatures matched, which is the case for every text format.
+pub(crate) fn sniff_mimetype(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"II*\0", "image/tiff"),
+        (b"MM\0*", "image/tiff"),
+        (b"\0\0\x01\0", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"PK\x05\x06", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"\xfd7zXZ\0", "application/x-xz"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (
+            b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1",
+            "application/x-ole-storage",
+        ),
+        (b"\0asm", "application/wasm"),
+        (b"PAR1", "application/vnd.apache.parquet"),
+        (b"SQLite format 3\0", "application/vnd.sqlite3"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+    ];
+    if let Some((_, mimetype)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return Some(mimetype);
+    }
+
+    // RIFF containers say what's inside at bytes 8..12
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        return match &data[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    // So do ISO base media files (MP4 and friends), with the "major brand" right after "ftyp"
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some(match &data[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            b"M4A " => "audio/x-m4a",
+            b"qt  " => "video/quicktime",
+            _ => "video/mp4",
+        });
+    }
+    if data.get(257..262) == Some(b"ustar") {
+        return Some("application/x-tar");
+    }
+    None
+}
+
+/// Signatures of 2 to 4 bytes that plenty of text files start with too, so they only count
+/// when the extension doesn't say anything.
+fn sniff_weak_mimetype(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"%!PS", "application/postscript"),
+        (b"BZh", "application/x-bzip2"),
+        (b"ID3", "audio/mpeg"),
+        (b"\xff\xfb", "audio/mpeg"),
+        (b"\xff\xf3", "audio/mpeg"),
+        (b"\xff\xf2", "audio/mpeg"),
+    ];
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+        .map(|(_, mimetype)| *mimetype)
+}
+
+/// Formats that are really containers for something more specific (a .docx is a ZIP, a .m4v is
+/// an MP4...). When one of these is sniffed, the extension usually knows better.
+const CONTAINER_MIMETYPES: [&str; 5] = [
+    "application/zip",
+    "application/gzip",
+    "application/x-ole-storage",
+    "video/mp4",
+    "video/webm",
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn get_mimetype_from_fp(file_path: &str) -> String {
+        MimeRegistry::new().from_path(file_path).to_owned()
+    }
+
+    fn infer_mimetype(file_path: &str, data: &[u8]) -> String {
+        MimeRegistry::new().infer(file_path, data).to_owned()
+    }
+
     #[test]
     fn match_mime_test() {
-        assert_eq!(get_mimetype(".tar"), "application/x-tar");
+        assert_eq!(MimeRegistry::new().get(".tar"), Some("application/x-tar"));
+        assert_eq!(MimeRegistry::new().get("wasm"), Some("application/wasm"));
     }
 
     #[test]
     fn default_mime_test() {
-        assert_eq!(get_mimetype(".bf"), "application/octet-stream");
+        assert_eq!(MimeRegistry::new().get(".bf"), None);
+        assert_eq!(get_mimetype_from_fp("file.bf"), "application/octet-stream");
+    }
+
+    #[test]
+    fn register_and_override() {
+        let mut registry = MimeRegistry::new().with(".gltf", "model/gltf+json");
+        assert_eq!(registry.from_path("scene.gltf"), "model/gltf+json");
+        assert_eq!(
+            registry.register("JS", "text/javascript").as_deref(),
+            Some("application/javascript")
+        );
+        assert_eq!(registry.from_path("app.js"), "text/javascript");
+        registry.unregister("gltf");
+        assert_eq!(registry.from_path("scene.gltf"), "application/octet-stream");
+        assert_eq!(
+            MimeRegistry::empty().from_path("a.png"),
+            "application/octet-stream"
+        );
     }
 
     #[test]
@@ -102,6 +441,38 @@ mod tests {
         )
     }
 
+    #[test]
+    fn extension_handling() {
+        assert_eq!(get_mimetype_from_fp("FILE.PNG"), "image/png");
+        assert_eq!(get_mimetype_from_fp("photos/IMG_0001.JpEg"), "image/jpeg");
+        // The directory's dots don't count
+        assert_eq!(
+            get_mimetype_from_fp("/home/me/.config/noext"),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            get_mimetype_from_fp("C:\\Users\\me\\v1.2\\README"),
+            "application/octet-stream"
+        );
+        assert_eq!(get_mimetype_from_fp(".bashrc"), "application/octet-stream");
+        assert_eq!(get_mimetype_from_fp(".hidden.json"), "application/json");
+        assert_eq!(
+            get_mimetype_from_fp("trailing."),
+            "application/octet-stream"
+        );
+        // Compound extensions
+        assert_eq!(
+            get_mimetype_from_fp("backups/site.2024.TAR.GZ"),
+            "application/x-compressed-tar"
+        );
+        assert_eq!(get_mimetype_from_fp("data.json.gz"), "application/gzip");
+        assert_eq!(get_mimetype_from_fp("my.holiday.photo.png"), "image/png");
+        assert_eq!(
+            extensions("a/b.c/archive.tar.gz").collect::<Vec<_>>(),
+            ["tar.gz", "gz"]
+        );
+    }
+
     #[test]
     fn no_ext() {
         assert_eq!(
@@ -109,4 +480,80 @@ mod tests {
             "application/octet-stream"
         )
     }
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(
+            sniff_mimetype(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Some("image/png")
+        );
+        assert_eq!(sniff_mimetype(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(
+            sniff_mimetype(b"RIFF\x24\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(
+            sniff_mimetype(b"\0\0\0\x20ftypisom\0\0\x02\0"),
+            Some("video/mp4")
+        );
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff_mimetype(&tar), Some("application/x-tar"));
+        assert_eq!(sniff_mimetype(b"just some text"), None);
+        assert_eq!(sniff_mimetype(b""), None);
+    }
+
+    #[test]
+    fn inferring() {
+        // No extension, sniffing saves the day
+        assert_eq!(
+            infer_mimetype("/tmp/.tmpA8f3k", b"\xff\xd8\xff\xe0\0\x10JFIF"),
+            "image/jpeg"
+        );
+        // Sniffing beats a wrong extension
+        assert_eq!(infer_mimetype("photo.txt", b"GIF89a"), "image/gif");
+        // but a ZIP is more specifically a .docx
+        assert_eq!(
+            infer_mimetype("report.docx", b"PK\x03\x04\x14\0"),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+        assert_eq!(
+            infer_mimetype("archive", b"PK\x03\x04\x14\0"),
+            "application/zip"
+        );
+        // Text can't be sniffed
+        assert_eq!(infer_mimetype("data.csv", b"id,name\n"), "text/csv");
+        // and short signatures don't overrule the extension
+        assert_eq!(infer_mimetype("names.csv", b"BZh,Anna\n"), "text/csv");
+        assert_eq!(
+            infer_mimetype("notes.txt", b"ID3 tags are..."),
+            "text/plain"
+        );
+        assert_eq!(infer_mimetype("song", b"ID3\x04\0"), "audio/mpeg");
+        assert_eq!(
+            infer_mimetype("print-job", b"%!PS-Adobe-3.0"),
+            "application/postscript"
+        );
+        assert_eq!(
+            infer_mimetype("data", b"id,name\n"),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn registered_extensions_beat_sniffing() {
+        // A format that embeds a PNG preview at the start
+        let mut registry = MimeRegistry::new().with("thumbs", "application/x-thumbs");
+        assert_eq!(
+            registry.infer("album.thumbs", b"\x89PNG\r\n\x1a\n"),
+            "application/x-thumbs"
+        );
+        // Overriding a default counts as registering it
+        registry.register("gif", "image/x-custom-gif");
+        assert_eq!(registry.infer("a.gif", b"GIF89a"), "image/x-custom-gif");
+        // Default mappings still lose to sniffing
+        assert_eq!(registry.infer("photo.txt", b"GIF89a"), "image/gif");
+        registry.unregister("gif");
+        assert_eq!(registry.infer("a.gif", b"GIF89a"), "image/gif");
+    }
 }
@@ -0,0 +1,333 @@
+//! Copying the objects of one bucket into another, possibly on another account or provider.
+
+use crate::escape::{escape, unescape};
+use crate::{MirrorOptions, ObjectInfo, ObjectMetadata, PutOptions, R2Error};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+/// The biggest object `CopyObject` takes.
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// How objects get from one bucket to the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorMethod {
+    /// `CopyObject`, which never sends the data anywhere. Only possible when both buckets can be
+    /// reached with the same credentials on the same endpoint, and for objects up to 5 GiB.
+    ServerSideCopy,
+    /// Downloaded from the source and uploaded to the destination again, in parts if it's big.
+    Streamed,
+}
+
+impl MirrorMethod {
+    /// How `object` is copied on a run that uses `self`. Objects too big for `CopyObject` are
+    /// streamed even when the rest are copied server-side.
+    pub(crate) fn for_object(self, object: &ObjectInfo) -> Self {
+        match self {
+            MirrorMethod::ServerSideCopy if object.size > MAX_COPY_SIZE => MirrorMethod::Streamed,
+            method => method,
+        }
+    }
+}
+
+/// How one object's copy went.
+#[derive(Debug)]
+pub struct MirrorOutcome {
+    pub key: String,
+    pub destination_key: String,
+    pub result: Result<(), R2Error>,
+}
+
+/// Every object a mirror run tried to copy, sorted by key.
+#[derive(Debug)]
+pub struct MirrorReport {
+    /// How the run copied objects, apart from any too big for a server-side copy.
+    pub method: MirrorMethod,
+    pub copied: Vec<MirrorOutcome>,
+    /// Objects that were skipped since the destination already had them.
+    pub unchanged: usize,
+}
+
+impl MirrorReport {
+    pub(crate) fn new(
+        method: MirrorMethod,
+        mut copied: Vec<MirrorOutcome>,
+        unchanged: usize,
+    ) -> Self {
+        copied.sort_by(|a, b| a.key.cmp(&b.key));
+        Self {
+            method,
+            copied,
+            unchanged,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.copied.iter().all(|outcome| outcome.result.is_ok())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &MirrorOutcome> {
+        self.copied.iter().filter(|outcome| outcome.result.is_err())
+    }
+}
+
+/// An object that has to be copied, and where to.
+#[derive(Debug)]
+pub(crate) struct MirrorJob {
+    pub(crate) source: ObjectInfo,
+    pub(crate) destination_key: String,
+}
+
+/// Where the destination side of the listing starts.
+pub(crate) fn destination_prefix(options: &MirrorOptions) -> &str {
+    options
+        .destination_prefix
+        .as_deref()
+        .unwrap_or(&options.prefix)
+}
+
+/// The objects in `source` that the destination doesn't have yet (or has an older version of),
+/// plus how many it already has.
+pub(crate) fn plan_mirror(
+    source: Vec<ObjectInfo>,
+    destination: Vec<ObjectInfo>,
+    options: &MirrorOptions,
+    checkpoint: &Checkpoint,
+) -> (Vec<MirrorJob>, usize) {
+    let destination: HashMap<_, _> = destination
+        .into_iter()
+        .map(|object| (object.key, object.etag))
+        .collect();
+    let mut unchanged = 0;
+    let jobs = source
+        .into_iter()
+        .filter_map(|object| {
+            let relative = object.key.strip_prefix(options.prefix.as_str())?;
+            let destination_key = format!("{}{relative}", destination_prefix(options));
+            let copied = match (&object.etag, destination.get(&destination_key)) {
+                // Streamed and multipart copies end up with a different ETag than the source,
+                // which is what the checkpoint is there to remember
+                (Some(etag), Some(destination_etag)) => {
+                    destination_etag.as_ref() == Some(etag)
+                        || checkpoint.contains(&object.key, etag)
+                }
+                _ => false,
+            };
+            if copied {
+                unchanged += 1;
+                None
+            } else {
+                Some(MirrorJob {
+                    source: object,
+                    destination_key,
+                })
+            }
+        })
+        .collect();
+    (jobs, unchanged)
+}
+
+/// Everything about the source object that should carry over to its streamed copy.
+pub(crate) fn put_options(metadata: &ObjectMetadata) -> PutOptions {
+    PutOptions {
+        content_type: metadata.content_type.clone(),
+        cache_control: metadata.cache_control.clone(),
+        content_disposition: metadata.content_disposition.clone(),
+        content_encoding: metadata.content_encoding.clone(),
+        content_language: metadata.content_language.clone(),
+        expires: metadata.expires,
+        metadata: metadata.metadata.clone(),
+        ..Default::default()
+    }
+}
+
+/// The start of a checkpoint file's first line.
+const HEADER: &str = "r2client mirror checkpoint v1";
+
+/// The objects a mirror has already copied (and the source ETag they had back then), kept in a
+/// file so an interrupted run can pick up where it left off.
+///
+/// The file starts with a header naming the destination bucket and prefix, since a checkpoint
+/// says nothing about any other destination. After that, it has a line per copied object, the
+/// ETag and the key separated by a tab, with backslashes and line breaks in keys escaped.
+#[derive(Debug, Default)]
+pub(crate) struct Checkpoint {
+    copied: HashMap<String, String>,
+    file: Option<Mutex<File>>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint at `path` (if there's one already) and keeps it open for appending.
+    /// Without a path, nothing is remembered.
+    ///
+    /// A checkpoint that was written for another destination is refused, rather than letting it
+    /// skip objects that were never copied there.
+    pub(crate) fn open(
+        path: Option<&Path>,
+        destination_bucket: &str,
+        destination_prefix: &str,
+    ) -> Result<Self, R2Error> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let header = format!(
+            "{HEADER}\t{destination_bucket}\t{}",
+            escape(destination_prefix)
+        );
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = contents.lines();
+        let copied = match lines.next() {
+            None => HashMap::new(),
+            Some(line) if line == header => lines
+                .filter_map(|line| {
+                    let (etag, key) = line.split_once('\t')?;
+                    Some((unescape(key), etag.to_owned()))
+                })
+                .collect(),
+            Some(_) => {
+                return Err(R2Error::InvalidOptions(format!(
+                    "{} is a checkpoint for mirroring somewhere else than {destination_bucket}/{destination_prefix}",
+                    path.display()
+                )));
+            }
+        };
+        let mut file = File::options().create(true).append(true).open(path)?;
+        if contents.is_empty() {
+            writeln!(file, "{header}")?;
+        }
+        Ok(Self {
+            copied,
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    pub(crate) fn contains(&self, key: &str, etag: &str) -> bool {
+        self.copied.get(key).is_some_and(|copied| copied == etag)
+    }
+
+    /// Writes `object` down as copied, right away, so it survives the process being killed.
+    pub(crate) fn record(&self, object: &ObjectInfo) -> io::Result<()> {
+        let (Some(file), Some(etag)) = (&self.file, &object.etag) else {
+            return Ok(());
+        };
+        let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        writeln!(file, "{etag}\t{}", escape(&object.key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn object(key: &str, etag: &str) -> ObjectInfo {
+        ObjectInfo {
+            key: key.to_owned(),
+            size: 1,
+            etag: Some(etag.to_owned()),
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn planning() {
+        let source = vec![
+            object("logs/same", "\"a\""),
+            object("logs/changed", "\"b\""),
+            object("logs/new", "\"c\""),
+        ];
+        let destination = vec![
+            object("backup/same", "\"a\""),
+            object("backup/changed", "\"old\""),
+        ];
+        let options = MirrorOptions {
+            prefix: "logs/".to_string(),
+            destination_prefix: Some("backup/".to_string()),
+            ..Default::default()
+        };
+        let (jobs, unchanged) = plan_mirror(source, destination, &options, &Checkpoint::default());
+        let keys: Vec<_> = jobs
+            .iter()
+            .map(|job| (job.source.key.as_str(), job.destination_key.as_str()))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("logs/changed", "backup/changed"),
+                ("logs/new", "backup/new")
+            ]
+        );
+        assert_eq!(unchanged, 1);
+    }
+
+    #[test]
+    fn checkpoint_resumes() {
+        let dir = TempDir::new("mirror");
+        let path = dir.path().join("checkpoint");
+        let weird = object("multi\nline\\key", "\"abc-2\"");
+        {
+            let checkpoint = Checkpoint::open(Some(&path), "backup", "").unwrap();
+            checkpoint.record(&object("plain", "\"def\"")).unwrap();
+            checkpoint.record(&weird).unwrap();
+        }
+        let checkpoint = Checkpoint::open(Some(&path), "backup", "").unwrap();
+        // Nothing it says is true of any other destination
+        let other_bucket = Checkpoint::open(Some(&path), "elsewhere", "");
+        let other_prefix = Checkpoint::open(Some(&path), "backup", "logs/");
+        assert!(matches!(other_bucket, Err(R2Error::InvalidOptions(_))));
+        assert!(matches!(other_prefix, Err(R2Error::InvalidOptions(_))));
+        assert!(checkpoint.contains("plain", "\"def\""));
+        assert!(checkpoint.contains("multi\nline\\key", "\"abc-2\""));
+        assert!(!checkpoint.contains("plain", "\"changed\""));
+
+        // A copied object whose ETag doesn't match the destination's is still left alone
+        let destination = vec![object("multi\nline\\key", "\"something-else\"")];
+        let (jobs, unchanged) = plan_mirror(
+            vec![weird],
+            destination,
+            &MirrorOptions::default(),
+            &checkpoint,
+        );
+        assert!(jobs.is_empty());
+        assert_eq!(unchanged, 1);
+    }
+
+    #[test]
+    fn big_objects_are_streamed() {
+        let mut object = object("big", "\"a\"");
+        object.size = MAX_COPY_SIZE;
+        assert_eq!(
+            MirrorMethod::ServerSideCopy.for_object(&object),
+            MirrorMethod::ServerSideCopy
+        );
+        object.size += 1;
+        assert_eq!(
+            MirrorMethod::ServerSideCopy.for_object(&object),
+            MirrorMethod::Streamed
+        );
+        assert_eq!(
+            MirrorMethod::Streamed.for_object(&object),
+            MirrorMethod::Streamed
+        );
+    }
+
+    #[test]
+    fn metadata_carries_over() {
+        let metadata = ObjectMetadata {
+            content_type: Some("image/png".to_string()),
+            cache_control: Some("max-age=60".to_string()),
+            metadata: HashMap::from([("owner".to_string(), "pyrite".to_string())]),
+            ..Default::default()
+        };
+        let options = put_options(&metadata);
+        assert_eq!(options.content_type.as_deref(), Some("image/png"));
+        assert_eq!(options.cache_control.as_deref(), Some("max-age=60"));
+        assert_eq!(options.metadata["owner"], "pyrite");
+    }
+}
@@ -0,0 +1,161 @@
+//! Multipart uploads, for objects too big to upload (or retry) in one go.
+
+use crate::R2Error;
+use bytes::Bytes;
+use std::io::{self, Read};
+
+/// Every part but the last has to be at least this big.
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// A sensible part size when you don't have a reason to pick another one.
+pub const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+/// Uploads can't have more parts than this.
+pub const MAX_PARTS: u32 = 10_000;
+
+/// A multipart upload that's been started and not yet completed or aborted.
+///
+/// Parts that were uploaded keep costing storage until the upload is completed or aborted, so
+/// don't just drop it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartUpload {
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+}
+
+impl MultipartUpload {
+    pub(crate) fn upload_id_query(&self) -> Vec<(String, String)> {
+        vec![("uploadId".to_string(), self.upload_id.clone())]
+    }
+
+    pub(crate) fn part_query(&self, part_number: u32) -> Vec<(String, String)> {
+        vec![
+            ("partNumber".to_string(), part_number.to_string()),
+            ("uploadId".to_string(), self.upload_id.clone()),
+        ]
+    }
+}
+
+/// A part that's been uploaded, which has to be passed back when completing the upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletedPart {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+/// How many parts a `total_size` byte upload takes.
+/// Fails with [`R2Error::InvalidOptions`] if the part size is too small or makes for too many.
+pub(crate) fn part_count(total_size: u64, part_size: u64) -> Result<u32, R2Error> {
+    if part_size < MIN_PART_SIZE {
+        return Err(R2Error::InvalidOptions(format!(
+            "parts have to be at least {MIN_PART_SIZE} bytes, not {part_size}"
+        )));
+    }
+    // An empty file is still one (empty) part
+    let parts = total_size.div_ceil(part_size).max(1);
+    u32::try_from(parts)
+        .ok()
+        .filter(|&parts| parts <= MAX_PARTS)
+        .ok_or_else(|| {
+            R2Error::InvalidOptions(format!(
+                "{total_size} bytes in {part_size} byte parts is {parts} parts, the limit is {MAX_PARTS}"
+            ))
+        })
+}
+
+/// Reads the next part (which is shorter than `part_size` only at the end of the file).
+pub(crate) fn read_part(reader: &mut impl Read, part_size: u64) -> io::Result<Bytes> {
+    let mut part = Vec::with_capacity(part_size as usize);
+    reader.take(part_size).read_to_end(&mut part)?;
+    Ok(part.into())
+}
+
+/// Pulls the upload ID out of an `<InitiateMultipartUploadResult>`.
+pub(crate) fn parse_upload_id(xml: &str) -> Result<Option<String>, R2Error> {
+    let root = xmltree::Element::parse(xml.as_bytes())?;
+    Ok(root
+        .get_child("UploadId")
+        .and_then(|upload_id| upload_id.get_text())
+        .map(|upload_id| upload_id.into_owned()))
+}
+
+/// The `<CompleteMultipartUpload>` body listing every part, in order.
+pub(crate) fn complete_body(parts: &[CompletedPart]) -> String {
+    let mut parts = parts.to_vec();
+    parts.sort_by_key(|part| part.part_number);
+    let parts: String = parts
+        .iter()
+        .map(|part| {
+            format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                part.part_number,
+                escape_xml(&part.etag)
+            )
+        })
+        .collect();
+    format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>")
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting_parts() {
+        assert_eq!(part_count(0, MIN_PART_SIZE).unwrap(), 1);
+        assert_eq!(part_count(MIN_PART_SIZE, MIN_PART_SIZE).unwrap(), 1);
+        assert_eq!(part_count(MIN_PART_SIZE + 1, MIN_PART_SIZE).unwrap(), 2);
+        assert!(part_count(10, 1024).is_err());
+        assert!(part_count(MIN_PART_SIZE * 10_001, MIN_PART_SIZE).is_err());
+    }
+
+    #[test]
+    fn reading_parts() {
+        let mut file = io::Cursor::new(vec![1; 25]);
+        assert_eq!(read_part(&mut file, 10).unwrap().len(), 10);
+        assert_eq!(read_part(&mut file, 10).unwrap().len(), 10);
+        assert_eq!(read_part(&mut file, 10).unwrap().len(), 5);
+        assert!(read_part(&mut file, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn upload_id() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Bucket>bucket</Bucket>
+  <Key>key</Key>
+  <UploadId>VXBsb2FkIElE</UploadId>
+</InitiateMultipartUploadResult>"#;
+        assert_eq!(
+            parse_upload_id(xml).unwrap().as_deref(),
+            Some("VXBsb2FkIElE")
+        );
+        assert_eq!(
+            parse_upload_id("<InitiateMultipartUploadResult/>").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn complete_body_is_sorted() {
+        let parts = [
+            CompletedPart {
+                part_number: 2,
+                etag: "\"b\"".to_string(),
+            },
+            CompletedPart {
+                part_number: 1,
+                etag: "\"a\"".to_string(),
+            },
+        ];
+        assert_eq!(
+            complete_body(&parts),
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag></Part><Part><PartNumber>2</PartNumber><ETag>\"b\"</ETag></Part></CompleteMultipartUpload>"
+        );
+    }
+}
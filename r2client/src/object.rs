@@ -0,0 +1,134 @@
+use crate::checksum::Checksum;
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// What R2 hands back after a successful upload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PutObjectOutput {
+    pub etag: Option<String>,
+    /// The checksum that was sent (and verified by R2), if [`crate::PutOptions::checksum`] was set.
+    pub checksum: Option<Checksum>,
+}
+
+impl PutObjectOutput {
+    pub(crate) fn from_response(headers: &HeaderMap, checksum: Option<Checksum>) -> Self {
+        Self {
+            etag: header_str(headers, "etag").map(str::to_owned),
+            checksum,
+        }
+    }
+}
+
+/// The headers R2 sends back about an object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectMetadata {
+    pub content_length: Option<u64>,
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub expires: Option<SystemTime>,
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    /// User metadata (the `x-amz-meta-*` headers), keyed without the prefix.
+    pub metadata: HashMap<String, String>,
+}
+
+impl ObjectMetadata {
+    pub(crate) fn from_response(headers: &HeaderMap) -> Self {
+        let metadata = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.as_str().strip_prefix(USER_METADATA_PREFIX)?;
+                Some((name.to_owned(), value.to_str().ok()?.to_owned()))
+            })
+            .collect();
+        Self {
+            content_length: header_str(headers, "content-length").and_then(|len| len.parse().ok()),
+            content_type: header_str(headers, "content-type").map(str::to_owned),
+            cache_control: header_str(headers, "cache-control").map(str::to_owned),
+            content_disposition: header_str(headers, "content-disposition").map(str::to_owned),
+            content_encoding: header_str(headers, "content-encoding").map(str::to_owned),
+            content_language: header_str(headers, "content-language").map(str::to_owned),
+            expires: header_str(headers, "expires")
+                .and_then(|date| httpdate::parse_http_date(date).ok()),
+            etag: header_str(headers, "etag").map(str::to_owned),
+            last_modified: header_str(headers, "last-modified")
+                .and_then(|date| httpdate::parse_http_date(date).ok()),
+            metadata,
+        }
+    }
+}
+
+/// An object's body along with its metadata.
+#[derive(Debug, Clone)]
+pub struct GetObjectOutput {
+    pub body: Bytes,
+    pub metadata: ObjectMetadata,
+}
+
+/// An object as it shows up in a bucket listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// A listing with a delimiter, where keys that have it after the prefix are rolled up into
+/// common prefixes instead of being listed, like subdirectories are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Listing {
+    pub objects: Vec<ObjectInfo>,
+    /// Each ends with the delimiter, e.g. `photos/2024/` for `photos/2024/a.jpg` listed under
+    /// `photos/` with `/`.
+    pub common_prefixes: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn metadata_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-length", HeaderValue::from_static("11"));
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        headers.insert(
+            "etag",
+            HeaderValue::from_static("\"5eb63bbbe01eeed093cb22bb8f5acdc3\""),
+        );
+        headers.insert(
+            "last-modified",
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        headers.insert("cache-control", HeaderValue::from_static("max-age=60"));
+        headers.insert("x-amz-meta-owner", HeaderValue::from_static("pyrite"));
+
+        let metadata = ObjectMetadata::from_response(&headers);
+        assert_eq!(metadata.content_length, Some(11));
+        assert_eq!(metadata.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(
+            metadata.etag.as_deref(),
+            Some("\"5eb63bbbe01eeed093cb22bb8f5acdc3\"")
+        );
+        assert_eq!(
+            metadata.last_modified,
+            Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1445412480))
+        );
+        assert_eq!(metadata.cache_control.as_deref(), Some("max-age=60"));
+        assert_eq!(metadata.expires, None);
+        assert_eq!(metadata.metadata["owner"], "pyrite");
+    }
+}
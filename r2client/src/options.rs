@@ -0,0 +1,486 @@
+use crate::cancel::Interrupt;
+use crate::checksum::{Checksum, ChecksumAlgorithm};
+use crate::dirsync::Comparison;
+use crate::{
+    CancellationToken, Compression, ProgressObserver, R2Error, RateLimiter, SseCustomerKey,
+};
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// Extra (name, value) headers to sign and send with a request.
+pub(crate) type Headers = Vec<(String, String)>;
+
+/// Optional knobs for uploads (`upload_file_with_options`, `put_object` and multipart uploads).
+///
+/// Everything defaults to off, which behaves exactly like a plain `upload_file`.
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    /// Overrides the content type that would otherwise be inferred from the file name.
+    pub content_type: Option<String>,
+    /// Stored and served back as `Cache-Control`, e.g. `public, max-age=31536000, immutable`.
+    pub cache_control: Option<String>,
+    /// Stored and served back as `Content-Disposition`, e.g. `attachment; filename="report.pdf"`.
+    pub content_disposition: Option<String>,
+    /// Stored and served back as `Content-Encoding`, for bodies you've already encoded yourself.
+    /// Can't be combined with [`PutOptions::compression`], which sets it on its own.
+    pub content_encoding: Option<String>,
+    /// Stored and served back as `Content-Language`, e.g. `en-US`.
+    pub content_language: Option<String>,
+    /// Stored and served back as `Expires`.
+    pub expires: Option<SystemTime>,
+    /// Sends a checksum of the body along with it, so the upload is rejected if it gets mangled.
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Encrypts the object at rest with your own key (SSE-C).
+    pub sse_customer_key: Option<SseCustomerKey>,
+    /// Compresses the body before uploading it and sets `Content-Encoding` to match.
+    /// Checksums are computed over the compressed bytes, since that's what R2 receives.
+    pub compression: Option<Compression>,
+    /// User metadata, sent as `x-amz-meta-<name>` headers.
+    /// Names are case-insensitive and come back lowercased in [`crate::ObjectMetadata`].
+    pub metadata: HashMap<String, String>,
+    /// Gets told how much of the body has been sent (and about every finished part, for
+    /// multipart uploads).
+    pub progress: Option<Arc<dyn ProgressObserver>>,
+    /// Caps how fast the body (or every part of it) is sent, on top of the client's limit.
+    pub rate_limit: Option<RateLimiter>,
+    /// Stops the upload when cancelled, failing it with [`crate::R2Error::Cancelled`].
+    pub cancellation: Option<CancellationToken>,
+    /// When the whole upload (every part of it, for multipart uploads) has to be done by,
+    /// failing it with [`crate::R2Error::DeadlineExceeded`] otherwise.
+    pub deadline: Option<Instant>,
+}
+
+impl PutOptions {
+    /// Headers to sign and send along with `payload`, plus the checksum if one was requested.
+    pub(crate) fn headers(&self, payload: &[u8]) -> Result<(Headers, Option<Checksum>), R2Error> {
+        let mut headers = self.object_headers()?;
+        let checksum = self.checksum.map(|algorithm| algorithm.compute(payload));
+        if let Some(checksum) = &checksum {
+            headers.push(checksum.header());
+        }
+        Ok((headers, checksum))
+    }
+
+    /// Headers for starting a multipart upload. Checksums and compression work on the whole body
+    /// at once, so they can't be used with one.
+    pub(crate) fn multipart_headers(&self) -> Result<Headers, R2Error> {
+        if self.checksum.is_some() || self.compression.is_some() {
+            return Err(R2Error::InvalidOptions(
+                "checksum and compression aren't supported for multipart uploads".to_string(),
+            ));
+        }
+        self.object_headers()
+    }
+
+    /// Headers for each part of a multipart upload, which only need the SSE-C key (if any).
+    pub(crate) fn part_headers(&self) -> Headers {
+        self.sse_customer_key
+            .as_ref()
+            .map(SseCustomerKey::headers)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn interrupt(&self) -> Interrupt {
+        Interrupt::new(self.cancellation.as_ref(), self.deadline)
+    }
+
+    /// Everything that describes the object, which is everything but the checksum.
+    fn object_headers(&self) -> Result<Headers, R2Error> {
+        let mut headers = Vec::new();
+        if self.content_encoding.is_some() && self.compression.is_some() {
+            return Err(R2Error::InvalidOptions(
+                "content_encoding and compression can't both be set".to_string(),
+            ));
+        }
+        for (name, value) in [
+            ("cache-control", &self.cache_control),
+            ("content-disposition", &self.content_disposition),
+            ("content-encoding", &self.content_encoding),
+            ("content-language", &self.content_language),
+        ] {
+            if let Some(value) = value {
+                if !is_header_value(value) {
+                    return Err(R2Error::InvalidOptions(format!(
+                        "{name} has to be printable ASCII, got {value:?}"
+                    )));
+                }
+                headers.push((name.to_string(), value.clone()));
+            }
+        }
+        if let Some(expires) = self.expires {
+            headers.push(("expires".to_string(), httpdate::fmt_http_date(expires)));
+        }
+        for (name, value) in &self.metadata {
+            let valid_name = !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+            if !valid_name {
+                return Err(R2Error::InvalidOptions(format!(
+                    "metadata name {name:?} can only have ASCII letters, digits, '-' and '_'"
+                )));
+            }
+            if !is_header_value(value) {
+                return Err(R2Error::InvalidOptions(format!(
+                    "metadata value for {name:?} has to be printable ASCII"
+                )));
+            }
+            headers.push((format!("x-amz-meta-{}", name.to_lowercase()), value.clone()));
+        }
+        if let Some(key) = &self.sse_customer_key {
+            headers.extend(key.headers());
+        }
+        if let Some(compression) = self.compression {
+            headers.push((
+                "content-encoding".to_string(),
+                compression.content_encoding().to_string(),
+            ));
+        }
+        Ok(headers)
+    }
+}
+
+fn is_header_value(value: &str) -> bool {
+    value.bytes().all(|b| b == b' ' || b.is_ascii_graphic())
+}
+
+/// Optional knobs for downloads (`download_file_with_options` and `get_object`).
+#[derive(Debug, Clone)]
+pub struct GetOptions {
+    /// Checks the downloaded bytes against the object's `x-amz-checksum-*` headers, or its ETag
+    /// for objects that weren't uploaded in parts. On by default.
+    ///
+    /// A mismatch fails with [`crate::R2Error::IntegrityMismatch`], and `download_file` deletes
+    /// whatever it wrote.
+    pub verify_integrity: bool,
+    /// The key the object was uploaded with, if it was encrypted with SSE-C.
+    pub sse_customer_key: Option<SseCustomerKey>,
+    /// Undoes the object's stored `Content-Encoding` (see [`PutOptions::compression`]).
+    /// Off by default, so you get exactly the bytes that are stored.
+    pub decompress: bool,
+    /// Overrides the `Content-Disposition` R2 responds with, e.g. `attachment; filename="a.pdf"`
+    /// to have browsers save the file under a friendlier name.
+    pub response_content_disposition: Option<String>,
+    /// Overrides the `Content-Type` R2 responds with.
+    pub response_content_type: Option<String>,
+    /// Overrides the `Cache-Control` R2 responds with.
+    pub response_cache_control: Option<String>,
+    /// Gets told how much of the body has been received.
+    pub progress: Option<Arc<dyn ProgressObserver>>,
+    /// Caps how fast the body is received, on top of the client's limit.
+    pub rate_limit: Option<RateLimiter>,
+    /// Stops the download when cancelled, failing it with [`crate::R2Error::Cancelled`].
+    pub cancellation: Option<CancellationToken>,
+    /// When the download has to be done by, failing it with
+    /// [`crate::R2Error::DeadlineExceeded`] otherwise.
+    pub deadline: Option<Instant>,
+}
+
+impl Default for GetOptions {
+    fn default() -> Self {
+        Self {
+            verify_integrity: true,
+            sse_customer_key: None,
+            decompress: false,
+            response_content_disposition: None,
+            response_content_type: None,
+            response_cache_control: None,
+            progress: None,
+            rate_limit: None,
+            cancellation: None,
+            deadline: None,
+        }
+    }
+}
+
+impl GetOptions {
+    pub(crate) fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if self.verify_integrity {
+            // Otherwise the x-amz-checksum-* headers aren't sent back
+            headers.push(("x-amz-checksum-mode".to_string(), "ENABLED".to_string()));
+        }
+        if let Some(key) = &self.sse_customer_key {
+            headers.extend(key.headers());
+        }
+        headers
+    }
+
+    /// The `response-*` query parameters for whichever response headers are overridden.
+    pub(crate) fn query(&self) -> Vec<(String, String)> {
+        [
+            ("response-cache-control", &self.response_cache_control),
+            (
+                "response-content-disposition",
+                &self.response_content_disposition,
+            ),
+            ("response-content-type", &self.response_content_type),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.clone()?)))
+        .collect()
+    }
+
+    /// What to decompress the response body with, if anything.
+    pub(crate) fn decompression(
+        &self,
+        response_headers: &HeaderMap,
+    ) -> Result<Option<Compression>, R2Error> {
+        if !self.decompress {
+            return Ok(None);
+        }
+        Compression::from_content_encoding(
+            response_headers
+                .get("content-encoding")
+                .and_then(|encoding| encoding.to_str().ok()),
+        )
+    }
+
+    /// SSE-C objects get a random ETag instead of the MD5 of their content.
+    pub(crate) fn etag_is_md5(&self) -> bool {
+        self.sse_customer_key.is_none()
+    }
+
+    pub(crate) fn interrupt(&self) -> Interrupt {
+        Interrupt::new(self.cancellation.as_ref(), self.deadline)
+    }
+}
+
+/// Optional knobs for `upload_dir` and `download_prefix`.
+///
+/// Globs are matched against paths relative to the directory (or keys relative to the prefix),
+/// always with `/` separators. `*` matches across directories too, so `*.jpg` picks up every
+/// JPEG no matter how deep it is.
+#[derive(Debug, Clone)]
+pub struct DirOptions {
+    /// Only transfer what matches at least one of these. Everything is included when empty.
+    pub include: Vec<String>,
+    /// Skip whatever matches any of these, even if it's included.
+    pub exclude: Vec<String>,
+    /// How many files are transferred at the same time. 4 by default, 0 is treated as 1.
+    pub concurrency: usize,
+    /// Used for every file uploaded by `upload_dir`.
+    pub put: PutOptions,
+    /// Used for every object downloaded by `download_prefix`.
+    pub get: GetOptions,
+}
+
+impl Default for DirOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            concurrency: 4,
+            put: PutOptions::default(),
+            get: GetOptions::default(),
+        }
+    }
+}
+
+/// Optional knobs for `sync_dir_to_prefix` and `sync_prefix_to_dir`.
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    pub comparison: Comparison,
+    /// Deletes whatever is on the destination side but not on the source side. Only things the
+    /// globs include are ever deleted, excluded ones are left alone.
+    pub delete_extraneous: bool,
+    /// Works out (and reports) what would be done, without doing any of it.
+    pub dry_run: bool,
+    /// Globs, concurrency, and the options every upload or download is made with. Compressed
+    /// objects can't be compared with their files, so [`PutOptions::compression`] and
+    /// [`GetOptions::decompress`] are refused with [`crate::R2Error::InvalidOptions`].
+    pub dir: DirOptions,
+}
+
+/// Optional knobs for `copy_object`.
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    /// The key the source object was encrypted with, if it uses SSE-C.
+    pub source_sse_customer_key: Option<SseCustomerKey>,
+    /// Encrypts the copy with this key (SSE-C), which doesn't have to match the source's.
+    pub sse_customer_key: Option<SseCustomerKey>,
+}
+
+impl CopyOptions {
+    pub(crate) fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(key) = &self.source_sse_customer_key {
+            headers.extend(key.copy_source_headers());
+        }
+        if let Some(key) = &self.sse_customer_key {
+            headers.extend(key.headers());
+        }
+        headers
+    }
+}
+
+/// Optional knobs for `mirror`.
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// Only objects under this prefix are mirrored. Everything is when empty.
+    pub prefix: String,
+    /// Swaps `prefix` for this one in the destination keys. They're kept as they are when unset.
+    pub destination_prefix: Option<String>,
+    /// How many objects are copied at the same time. 4 by default, 0 is treated as 1.
+    pub concurrency: usize,
+    /// Remembers every object copied in this file, so a run that got interrupted can pick up
+    /// where it left off. Without it, streamed copies of multipart objects (whose ETags never
+    /// match the source's) are copied all over again on every run. A checkpoint belongs to one
+    /// destination bucket and prefix, mirroring anywhere else with it fails.
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            destination_prefix: None,
+            concurrency: 4,
+            checkpoint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_checksum_by_default() {
+        let (headers, checksum) = PutOptions::default().headers(b"hello world").unwrap();
+        assert!(headers.is_empty());
+        assert!(checksum.is_none());
+    }
+
+    #[test]
+    fn checksum_header() {
+        let options = PutOptions {
+            checksum: Some(ChecksumAlgorithm::Sha256),
+            ..Default::default()
+        };
+        let (headers, checksum) = options.headers(b"hello world").unwrap();
+        let checksum = checksum.unwrap();
+        assert_eq!(
+            headers,
+            vec![("x-amz-checksum-sha256".to_string(), checksum.value.clone())]
+        );
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha256);
+    }
+
+    #[test]
+    fn metadata_headers() {
+        let options = PutOptions {
+            metadata: HashMap::from([("Owner".to_string(), "pyrite".to_string())]),
+            ..Default::default()
+        };
+        let (headers, _) = options.headers(b"").unwrap();
+        assert_eq!(
+            headers,
+            vec![("x-amz-meta-owner".to_string(), "pyrite".to_string())]
+        );
+
+        let bad_name = PutOptions {
+            metadata: HashMap::from([("not ok".to_string(), "value".to_string())]),
+            ..Default::default()
+        };
+        assert!(bad_name.headers(b"").is_err());
+        let bad_value = PutOptions {
+            metadata: HashMap::from([("name".to_string(), "line\nbreak".to_string())]),
+            ..Default::default()
+        };
+        assert!(bad_value.headers(b"").is_err());
+    }
+
+    #[test]
+    fn http_headers() {
+        let options = PutOptions {
+            cache_control: Some("public, max-age=3600".to_string()),
+            content_disposition: Some("attachment; filename=\"report.pdf\"".to_string()),
+            content_language: Some("en-US".to_string()),
+            expires: Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1445412480)),
+            ..Default::default()
+        };
+        let (headers, _) = options.headers(b"").unwrap();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(header("cache-control"), Some("public, max-age=3600"));
+        assert_eq!(
+            header("content-disposition"),
+            Some("attachment; filename=\"report.pdf\"")
+        );
+        assert_eq!(header("content-language"), Some("en-US"));
+        assert_eq!(header("expires"), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(header("content-encoding"), None);
+
+        let bad_value = PutOptions {
+            cache_control: Some("max-age=1\r\nx-evil: 1".to_string()),
+            ..Default::default()
+        };
+        assert!(bad_value.headers(b"").is_err());
+    }
+
+    #[test]
+    fn multipart_headers() {
+        let options = PutOptions {
+            cache_control: Some("no-cache".to_string()),
+            sse_customer_key: Some(SseCustomerKey::new([3; 32])),
+            ..Default::default()
+        };
+        let headers = options.multipart_headers().unwrap();
+        assert!(headers.iter().any(|(name, _)| name == "cache-control"));
+        assert_eq!(
+            options.part_headers(),
+            SseCustomerKey::new([3; 32]).headers()
+        );
+
+        let with_checksum = PutOptions {
+            checksum: Some(ChecksumAlgorithm::Crc32),
+            ..Default::default()
+        };
+        assert!(with_checksum.multipart_headers().is_err());
+    }
+
+    #[test]
+    fn response_overrides() {
+        assert!(GetOptions::default().query().is_empty());
+        let options = GetOptions {
+            response_content_disposition: Some("attachment; filename=\"a.pdf\"".to_string()),
+            response_content_type: Some("application/pdf".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.query(),
+            vec![
+                (
+                    "response-content-disposition".to_string(),
+                    "attachment; filename=\"a.pdf\"".to_string()
+                ),
+                (
+                    "response-content-type".to_string(),
+                    "application/pdf".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn content_encoding_conflicts_with_compression() {
+        let options = PutOptions {
+            content_encoding: Some("br".to_string()),
+            compression: Some(Compression::Gzip),
+            ..Default::default()
+        };
+        assert!(matches!(
+            options.headers(b""),
+            Err(R2Error::InvalidOptions(_))
+        ));
+    }
+}
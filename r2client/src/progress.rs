@@ -0,0 +1,274 @@
+//! Progress reporting (and throttling) for uploads and downloads.
+
+use crate::cancel::Interrupt;
+use crate::ratelimit::Throttle;
+use bytes::Bytes;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+/// Bodies are handed to the connection (or reported as received) in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Gets told how a transfer is coming along, e.g. to drive a progress bar.
+///
+/// Any `Fn(Progress)` closure is a `ProgressObserver`, implement the trait yourself if you also
+/// want to hear about the parts of multipart uploads.
+///
+/// Observers are called from whichever thread is doing the transfer, so keep them quick.
+pub trait ProgressObserver: Send + Sync {
+    /// Called every time another chunk of the body has been sent or received.
+    fn on_progress(&self, progress: Progress);
+
+    /// Called after each part of a multipart upload has been uploaded. Does nothing by default.
+    fn on_part_complete(&self, _part: PartProgress) {}
+}
+
+impl<F> ProgressObserver for F
+where
+    F: Fn(Progress) + Send + Sync,
+{
+    fn on_progress(&self, progress: Progress) {
+        self(progress)
+    }
+}
+
+impl fmt::Debug for dyn ProgressObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressObserver")
+    }
+}
+
+/// How far along a transfer is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub bytes_transferred: u64,
+    /// `None` when the server didn't say how big a download is.
+    pub total_bytes: Option<u64>,
+}
+
+impl Progress {
+    /// Between 0 and 1, if the total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_bytes.map(|total| match total {
+            0 => 1.0,
+            total => self.bytes_transferred as f64 / total as f64,
+        })
+    }
+}
+
+/// A part of a multipart upload that's done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartProgress {
+    pub part_number: u32,
+    pub part_bytes: u64,
+    pub parts_completed: u32,
+    pub total_parts: u32,
+}
+
+/// Reports the progress of one transfer to its observer. A transfer can span several requests
+/// (the parts of a multipart upload), which is what the offset is for.
+#[derive(Debug, Clone)]
+pub(crate) struct ProgressTracker {
+    observer: Arc<dyn ProgressObserver>,
+    offset: u64,
+    total_bytes: Option<u64>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(observer: &Arc<dyn ProgressObserver>, total_bytes: Option<u64>) -> Self {
+        Self {
+            observer: Arc::clone(observer),
+            offset: 0,
+            total_bytes,
+        }
+    }
+
+    /// The same transfer, for a request that starts `offset` bytes into it.
+    pub(crate) fn at_offset(&self, offset: u64) -> Self {
+        Self {
+            offset,
+            ..self.clone()
+        }
+    }
+
+    /// `bytes_transferred` counts from the tracker's offset.
+    pub(crate) fn report(&self, bytes_transferred: u64) {
+        self.observer.on_progress(Progress {
+            bytes_transferred: self.offset + bytes_transferred,
+            total_bytes: self.total_bytes,
+        });
+    }
+
+    pub(crate) fn part_complete(&self, part: PartProgress) {
+        self.observer.on_part_complete(part);
+    }
+}
+
+/// The body of a request, along with whoever wants to hear about it being sent, how fast it's
+/// allowed to go, and what can stop it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Payload {
+    pub(crate) bytes: Bytes,
+    pub(crate) progress: Option<ProgressTracker>,
+    pub(crate) throttle: Throttle,
+    pub(crate) interrupt: Interrupt,
+}
+
+impl Payload {
+    pub(crate) fn new(bytes: Bytes, progress: Option<ProgressTracker>, throttle: Throttle) -> Self {
+        Self {
+            bytes,
+            progress,
+            throttle,
+            interrupt: Interrupt::default(),
+        }
+    }
+
+    pub(crate) fn with_interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Whether the body has to be sent chunk by chunk, rather than all at once.
+    pub(crate) fn is_streamed(&self) -> bool {
+        self.progress.is_some() || !self.throttle.is_empty()
+    }
+
+    /// The body as a stream of chunks, each one waiting its turn under the throttle and
+    /// reported as it's handed over.
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) fn stream(self) -> impl futures_util::Stream<Item = io::Result<Bytes>> + Send {
+        use futures_util::StreamExt;
+
+        let len = self.bytes.len();
+        futures_util::stream::iter((0..len).step_by(CHUNK_SIZE)).then(move |start| {
+            let end = (start + CHUNK_SIZE).min(len);
+            let chunk = self.bytes.slice(start..end);
+            let delay = self.throttle.delay(chunk.len());
+            let progress = self.progress.clone();
+            async move {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                if let Some(progress) = progress {
+                    progress.report(end as u64);
+                }
+                Ok(chunk)
+            }
+        })
+    }
+}
+
+impl From<Bytes> for Payload {
+    fn from(bytes: Bytes) -> Self {
+        Self::new(bytes, None, Throttle::default())
+    }
+}
+
+/// The blocking counterpart of [`Payload::stream`].
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+pub(crate) struct PayloadReader {
+    payload: Payload,
+    position: usize,
+}
+
+impl PayloadReader {
+    #[cfg_attr(not(feature = "sync"), allow(dead_code))]
+    pub(crate) fn new(payload: Payload) -> Self {
+        Self {
+            payload,
+            position: 0,
+        }
+    }
+}
+
+impl Read for PayloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = &self.payload.bytes[self.position..];
+        let read = remaining.len().min(buf.len()).min(CHUNK_SIZE);
+        if read == 0 {
+            return Ok(0);
+        }
+        // The error itself gets swallowed by reqwest, `send` checks the interrupt again to
+        // report why the request really failed
+        self.payload.interrupt.check().map_err(io::Error::other)?;
+        let delay = self.payload.throttle.delay(read);
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+        buf[..read].copy_from_slice(&remaining[..read]);
+        self.position += read;
+        if let Some(progress) = &self.payload.progress {
+            progress.report(self.position as u64);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn recorder() -> (Arc<Mutex<Vec<Progress>>>, Arc<dyn ProgressObserver>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let observer: Arc<dyn ProgressObserver> = {
+            let seen = Arc::clone(&seen);
+            Arc::new(move |progress| seen.lock().unwrap().push(progress))
+        };
+        (seen, observer)
+    }
+
+    #[test]
+    fn reader_reports_every_chunk() {
+        let (seen, observer) = recorder();
+        let body = Bytes::from(vec![7; CHUNK_SIZE * 2 + 10]);
+        let tracker = ProgressTracker::new(&observer, Some(body.len() as u64 + 100));
+        let mut reader = PayloadReader::new(Payload::new(
+            body.clone(),
+            Some(tracker.at_offset(100)),
+            Throttle::default(),
+        ));
+        let mut read = Vec::new();
+        let mut buf = vec![0; CHUNK_SIZE * 4];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(read, body);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0].bytes_transferred, 100 + CHUNK_SIZE as u64);
+        assert_eq!(seen[2].bytes_transferred, seen[2].total_bytes.unwrap());
+        assert_eq!(seen[2].fraction(), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn stream_reports_every_chunk() {
+        use futures_util::StreamExt;
+
+        let (seen, observer) = recorder();
+        let body = Bytes::from(vec![1; CHUNK_SIZE + 1]);
+        let tracker = ProgressTracker::new(&observer, Some(body.len() as u64));
+        let payload = Payload::new(body, Some(tracker), Throttle::default());
+        let chunks: Vec<_> = payload.stream().collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            seen.lock().unwrap().last().unwrap().bytes_transferred,
+            CHUNK_SIZE as u64 + 1
+        );
+    }
+
+    #[test]
+    fn unknown_total() {
+        let progress = Progress {
+            bytes_transferred: 10,
+            total_bytes: None,
+        };
+        assert_eq!(progress.fraction(), None);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::R2Error;
+use crate::endpoint::{AddressingStyle, Jurisdiction, r2_endpoint};
+
+/// Presets for the S3-compatible services this client has been pointed at.
+///
+/// Nothing past endpoint, region and addressing style is provider specific, so anything that
+/// speaks SigV4-signed S3 should work through [`Provider::Generic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provider {
+    /// Cloudflare R2. Always signs with the "auto" region.
+    R2 {
+        account_id: String,
+        jurisdiction: Jurisdiction,
+    },
+    /// Amazon S3 itself, using the regional endpoint (`https://s3.<region>.amazonaws.com`).
+    AwsS3 { region: String },
+    /// A MinIO server, e.g. `http://localhost:9000`.
+    MinIo { endpoint: String },
+    /// Anything else (Garage, Ceph RGW, SeaweedFS...), where you know the endpoint and region.
+    Generic { endpoint: String, region: String },
+}
+
+impl Provider {
+    /// Fails for R2 when the account ID isn't one, see [`r2_endpoint`].
+    pub fn endpoint(&self) -> Result<String, R2Error> {
+        match self {
+            Provider::R2 {
+                account_id,
+                jurisdiction,
+            } => r2_endpoint(account_id, *jurisdiction),
+            Provider::AwsS3 { region } => Ok(format!("https://s3.{region}.amazonaws.com")),
+            Provider::MinIo { endpoint } | Provider::Generic { endpoint, .. } => {
+                Ok(endpoint.clone())
+            }
+        }
+    }
+
+    pub fn region(&self) -> &str {
+        match self {
+            Provider::R2 { .. } => "auto",
+            Provider::AwsS3 { region } | Provider::Generic { region, .. } => region,
+            // MinIO's default when no region is configured server-side
+            Provider::MinIo { .. } => "us-east-1",
+        }
+    }
+
+    /// AWS is phasing out path-style requests, everyone else handles them fine (and MinIO
+    /// needs extra DNS setup for virtual-hosted ones).
+    pub fn addressing_style(&self) -> AddressingStyle {
+        match self {
+            Provider::AwsS3 { .. } => AddressingStyle::VirtualHosted,
+            _ => AddressingStyle::Path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn r2_preset() {
+        let provider = Provider::R2 {
+            account_id: "023e105f4ecef8ad9ca31a8372d0c353".to_string(),
+            jurisdiction: Jurisdiction::Default,
+        };
+        assert_eq!(
+            provider.endpoint().unwrap(),
+            "https://023e105f4ecef8ad9ca31a8372d0c353.r2.cloudflarestorage.com"
+        );
+        assert_eq!(provider.region(), "auto");
+        assert_eq!(provider.addressing_style(), AddressingStyle::Path);
+    }
+
+    #[test]
+    fn aws_preset() {
+        let provider = Provider::AwsS3 {
+            region: "eu-west-2".to_string(),
+        };
+        assert_eq!(
+            provider.endpoint().unwrap(),
+            "https://s3.eu-west-2.amazonaws.com"
+        );
+        assert_eq!(provider.region(), "eu-west-2");
+        assert_eq!(provider.addressing_style(), AddressingStyle::VirtualHosted);
+    }
+
+    #[test]
+    fn minio_and_generic_presets() {
+        let minio = Provider::MinIo {
+            endpoint: "http://localhost:9000".to_string(),
+        };
+        assert_eq!(minio.endpoint().unwrap(), "http://localhost:9000");
+        assert_eq!(minio.region(), "us-east-1");
+
+        let garage = Provider::Generic {
+            endpoint: "http://localhost:3900".to_string(),
+            region: "garage".to_string(),
+        };
+        assert_eq!(garage.region(), "garage");
+        assert_eq!(garage.addressing_style(), AddressingStyle::Path);
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Caps how many bytes per second go through it, across every transfer it's used for.
+///
+/// Clones share the same budget, so one limiter handed to several transfers (or set on the
+/// client with [`R2Client::with_rate_limiter`](crate::R2Client::with_rate_limiter)) keeps all of
+/// them together under the limit, even when they run at the same time. Up to a second's worth of
+/// unused budget can be saved up, so short bursts go out at full speed.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// Goes negative when more was taken than there was, which is paid back by waiting.
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// A limit of 0 is treated as 1 byte per second, since nothing would ever get through.
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        Self {
+            bytes_per_second,
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                available: bytes_per_second as f64,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Takes `bytes` out of the budget, returning how long to wait before sending them.
+    pub(crate) fn reserve(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_second as f64;
+        // A panic while holding the lock can't leave the bucket in a broken state
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.available = (bucket.available + refill).min(rate);
+        bucket.last_refill = now;
+        bucket.available -= bytes as f64;
+        if bucket.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.available / rate)
+        }
+    }
+}
+
+/// Every limiter a transfer has to stay under (the client's and its own).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Throttle(Vec<RateLimiter>);
+
+impl Throttle {
+    pub(crate) fn new<'a>(limiters: impl IntoIterator<Item = &'a RateLimiter>) -> Self {
+        Self(limiters.into_iter().cloned().collect())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// How long to wait before `bytes` can go out, which is up to the slowest limiter.
+    pub(crate) fn delay(&self, bytes: usize) -> Duration {
+        self.0
+            .iter()
+            .map(|limiter| limiter.reserve(bytes))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roughly(actual: Duration, expected: Duration) -> bool {
+        actual.abs_diff(expected) < Duration::from_millis(50)
+    }
+
+    #[test]
+    fn burst_then_wait() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert!(roughly(limiter.reserve(500), Duration::from_millis(500)));
+        assert!(roughly(limiter.reserve(500), Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn clones_share_the_budget() {
+        let limiter = RateLimiter::new(1000);
+        let other = limiter.clone();
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        assert!(roughly(other.reserve(1000), Duration::from_secs(1)));
+        // A separate limiter has its own budget
+        assert_eq!(RateLimiter::new(1000).reserve(1000), Duration::ZERO);
+    }
+
+    #[test]
+    fn slowest_limiter_wins() {
+        let fast = RateLimiter::new(1_000_000);
+        let slow = RateLimiter::new(100);
+        let throttle = Throttle::new([&fast, &slow]);
+        assert_eq!(throttle.delay(100), Duration::ZERO);
+        assert!(roughly(throttle.delay(100), Duration::from_secs(1)));
+        assert_eq!(Throttle::default().delay(usize::MAX), Duration::ZERO);
+    }
+}
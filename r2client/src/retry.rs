@@ -0,0 +1,211 @@
+use http::StatusCode;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+/// How much randomness gets mixed into the backoff delay.
+///
+/// See https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/ for why you'd
+/// want any of this in the first place (tl;dr: a bunch of clients retrying in lockstep is bad).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jitter {
+    /// Sleep exactly the exponential delay.
+    None,
+    /// Sleep anywhere between zero and the exponential delay.
+    Full,
+    /// Sleep half the exponential delay plus a random amount up to the other half.
+    Equal,
+}
+
+/// Kinds of transport errors (as opposed to HTTP statuses) that can be retried.
+///
+/// Only the request itself is retried, up to the response headers. A body that breaks off
+/// halfway through isn't, since that would take resuming it where it stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryableError {
+    /// Couldn't connect to the endpoint at all.
+    Connect,
+    /// The request or response timed out.
+    Timeout,
+    /// The request failed mid-flight, e.g. the connection was reset.
+    Request,
+}
+
+/// Decides whether, and how long after, a failed request should be sent again.
+///
+/// Every attempt is re-signed, so `x-amz-date` is always fresh, and the payload is kept around
+/// as cheaply clonable bytes, so each attempt sends the full body from the start.
+///
+/// ```
+/// use r2client::{Jitter, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy {
+///     max_attempts: 5,
+///     base_delay: Duration::from_millis(250),
+///     jitter: Jitter::Equal,
+///     ..Default::default()
+/// };
+/// assert!(policy.retries_status(http::StatusCode::SERVICE_UNAVAILABLE));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. `1` (or `0`) disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every retry after that.
+    pub base_delay: Duration,
+    /// Upper bound for a single delay, applied before jitter.
+    pub max_delay: Duration,
+    pub jitter: Jitter,
+    /// Statuses that are worth another shot.
+    /// R2 and S3 answer throttling ("SlowDown") with a 503, so that one is in the default list.
+    pub retryable_statuses: Vec<StatusCode>,
+    pub retryable_errors: Vec<RetryableError>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(20),
+            jitter: Jitter::Full,
+            retryable_statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retryable_errors: vec![
+                RetryableError::Connect,
+                RetryableError::Timeout,
+                RetryableError::Request,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    pub fn retries_error(&self, error: &reqwest::Error) -> bool {
+        let kind = if error.is_connect() {
+            RetryableError::Connect
+        } else if error.is_timeout() {
+            RetryableError::Timeout
+        } else if error.is_request() {
+            RetryableError::Request
+        } else {
+            return false;
+        };
+        self.retryable_errors.contains(&kind)
+    }
+
+    /// Whether another attempt is allowed after `attempt` (1-based) attempts have been made.
+    pub fn has_attempts_left(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// How long to wait after the `attempt`th (1-based) attempt failed.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(random_fraction()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random_fraction()),
+        }
+    }
+}
+
+/// Returns something in [0, 1).
+/// Pulling in a whole RNG crate for backoff jitter felt like overkill, and std's `RandomState`
+/// is randomly seeded anyway.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_jitter() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: Jitter::None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exponential_delay() {
+        let policy = no_jitter();
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = no_jitter();
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let full = RetryPolicy {
+            jitter: Jitter::Full,
+            ..no_jitter()
+        };
+        let equal = RetryPolicy {
+            jitter: Jitter::Equal,
+            ..no_jitter()
+        };
+        for _ in 0..100 {
+            assert!(full.delay(2) <= Duration::from_millis(200));
+            let delay = equal.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn attempts() {
+        let policy = RetryPolicy::default();
+        assert!(policy.has_attempts_left(2));
+        assert!(!policy.has_attempts_left(3));
+        assert!(!RetryPolicy::none().has_attempts_left(1));
+    }
+
+    #[test]
+    fn default_statuses() {
+        let policy = RetryPolicy::default();
+        assert!(policy.retries_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(policy.retries_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!policy.retries_status(StatusCode::NOT_FOUND));
+        assert!(!policy.retries_status(StatusCode::FORBIDDEN));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::R2Error;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use md5::{Digest, Md5};
+use reqwest::header::HeaderMap;
+use std::fmt;
+
+const KEY_HEADER: &str = "x-amz-server-side-encryption-customer-key";
+const COPY_SOURCE_KEY_HEADER: &str = "x-amz-copy-source-server-side-encryption-customer-key";
+
+/// A 256-bit key for SSE-C (server-side encryption with customer-provided keys).
+///
+/// R2 encrypts the object with it and throws it away, so the exact same key has to be passed
+/// again to download, head or copy the object. Lose it and the object is gone for good.
+///
+/// The key never shows up in `Debug` output, and the headers carrying it are marked sensitive so
+/// they don't end up in trace logs either.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SseCustomerKey {
+    key: [u8; 32],
+}
+
+impl SseCustomerKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Fails with [`R2Error::InvalidOptions`] unless the key is exactly 32 bytes.
+    pub fn from_bytes(key: &[u8]) -> Result<Self, R2Error> {
+        let key = key.try_into().map_err(|_| {
+            R2Error::InvalidOptions(format!(
+                "SSE-C keys have to be 32 bytes long, got {}",
+                key.len()
+            ))
+        })?;
+        Ok(Self::new(key))
+    }
+
+    /// For keys that are stored base64 encoded, like the ones `openssl rand -base64 32` makes.
+    pub fn from_base64(key: &str) -> Result<Self, R2Error> {
+        let key = BASE64
+            .decode(key.trim())
+            .map_err(|e| R2Error::InvalidOptions(format!("SSE-C key isn't valid base64 ({e})")))?;
+        Self::from_bytes(&key)
+    }
+
+    /// Base64 encoded MD5 of the key, which R2 uses to check the key made it over intact.
+    pub fn key_md5(&self) -> String {
+        BASE64.encode(Md5::digest(self.key))
+    }
+
+    fn headers_with_prefix(&self, prefix: &str) -> Vec<(String, String)> {
+        vec![
+            (format!("{prefix}-algorithm"), "AES256".to_string()),
+            (format!("{prefix}-key"), BASE64.encode(self.key)),
+            (format!("{prefix}-key-md5"), self.key_md5()),
+        ]
+    }
+
+    /// Headers for an object being uploaded, downloaded or headed (or the destination of a copy).
+    pub(crate) fn headers(&self) -> Vec<(String, String)> {
+        self.headers_with_prefix("x-amz-server-side-encryption-customer")
+    }
+
+    /// Headers for the source object of a copy.
+    pub(crate) fn copy_source_headers(&self) -> Vec<(String, String)> {
+        self.headers_with_prefix("x-amz-copy-source-server-side-encryption-customer")
+    }
+}
+
+impl fmt::Debug for SseCustomerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseCustomerKey")
+            .field("key", &"<redacted>")
+            .field("key_md5", &self.key_md5())
+            .finish()
+    }
+}
+
+/// Marks the headers carrying raw SSE-C keys as sensitive, which makes `HeaderMap`'s `Debug`
+/// print "Sensitive" instead of the key.
+pub(crate) fn mark_sensitive(headers: &mut HeaderMap) {
+    for name in [KEY_HEADER, COPY_SOURCE_KEY_HEADER] {
+        if let Some(value) = headers.get_mut(name) {
+            value.set_sensitive(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> SseCustomerKey {
+        SseCustomerKey::new([7; 32])
+    }
+
+    #[test]
+    fn headers() {
+        let headers = key().headers();
+        assert_eq!(
+            headers[0],
+            (
+                "x-amz-server-side-encryption-customer-algorithm".to_string(),
+                "AES256".to_string()
+            )
+        );
+        assert_eq!(headers[1].0, KEY_HEADER);
+        assert_eq!(BASE64.decode(&headers[1].1).unwrap(), [7; 32]);
+        assert_eq!(headers[2].1, key().key_md5());
+        assert_eq!(key().copy_source_headers()[1].0, COPY_SOURCE_KEY_HEADER);
+    }
+
+    #[test]
+    fn key_length() {
+        assert!(SseCustomerKey::from_bytes(&[0; 16]).is_err());
+        assert!(SseCustomerKey::from_base64(&BASE64.encode([1; 32])).is_ok());
+        assert!(SseCustomerKey::from_base64("not base64!").is_err());
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let debug = format!("{:?}", key());
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains(&BASE64.encode([7; 32])));
+    }
+
+    #[test]
+    fn sensitive_headers() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in key().headers() {
+            headers.insert(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        mark_sensitive(&mut headers);
+        assert!(!format!("{headers:?}").contains(&BASE64.encode([7; 32])));
+    }
+}
@@ -0,0 +1,672 @@
+//! [`Storage`], the object operations every bucket has, so code can be written against R2 and
+//! tested against [`MemoryStorage`] without credentials.
+
+use crate::compression::{Compression, decompress};
+use crate::listing::roll_up;
+use crate::{
+    CopyOptions, GetObjectOutput, GetOptions, Listing, MimeRegistry, ObjectInfo, ObjectMetadata,
+    PutObjectOutput, PutOptions, R2Error, SseCustomerKey,
+};
+use bytes::Bytes;
+use http::StatusCode;
+use md5::{Digest, Md5};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// The object operations of a bucket.
+///
+/// [`R2Bucket`](crate::R2Bucket) implements it against R2, [`MemoryStorage`] against a map in
+/// memory and [`FileStorage`](crate::FileStorage) against a directory, so code that's generic
+/// over it can be tested offline or run locally. The blocking API has its own `sync::Storage`
+/// with the same methods.
+///
+/// ```
+/// use r2client::{MemoryStorage, PutOptions, R2Error, Storage};
+///
+/// async fn publish(storage: &impl Storage, report: &str) -> Result<(), R2Error> {
+///     let body = report.to_owned().into();
+///     storage.put_object("reports/latest.txt", body, &PutOptions::default()).await?;
+///     Ok(())
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let storage = MemoryStorage::new();
+/// publish(&storage, "all good").await.unwrap();
+/// assert_eq!(storage.list_objects("reports/").await.unwrap().len(), 1);
+/// # }
+/// ```
+pub trait Storage: Send + Sync {
+    fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> impl Future<Output = Result<PutObjectOutput, R2Error>> + Send;
+
+    fn get_object(
+        &self,
+        key: &str,
+        options: &GetOptions,
+    ) -> impl Future<Output = Result<GetObjectOutput, R2Error>> + Send;
+
+    fn head_object(
+        &self,
+        key: &str,
+    ) -> impl Future<Output = Result<ObjectMetadata, R2Error>> + Send;
+
+    /// Copies an object within the same bucket.
+    fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> impl Future<Output = Result<PutObjectOutput, R2Error>> + Send;
+
+    /// Deleting a key that doesn't exist isn't an error.
+    fn delete_object(&self, key: &str) -> impl Future<Output = Result<(), R2Error>> + Send;
+
+    /// Every object whose key starts with `prefix` (every object, for ""), sorted by key.
+    fn list_objects(
+        &self,
+        prefix: &str,
+    ) -> impl Future<Output = Result<Vec<ObjectInfo>, R2Error>> + Send;
+
+    /// Like [`list_objects`](Self::list_objects), with the keys that have `delimiter` after
+    /// `prefix` rolled up into common prefixes. By default that's done on the full listing.
+    fn list_objects_delimited(
+        &self,
+        prefix: &str,
+        delimiter: &str,
+    ) -> impl Future<Output = Result<Listing, R2Error>> + Send {
+        async move { Ok(roll_up(self.list_objects(prefix).await?, prefix, delimiter)) }
+    }
+}
+
+/// What errors say the objects of a [`MemoryStorage`] are in.
+const BUCKET: &str = "memory";
+const NO_SUCH_KEY: &str =
+    "<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>";
+const WRONG_SSE_CUSTOMER_KEY: &str = "<Error><Code>InvalidRequest</Code><Message>The object was \
+    stored using a form of SSE-C, which needs the same key to be provided.</Message></Error>";
+
+/// A bucket that only exists in memory, for tests.
+///
+/// It behaves like R2 wherever code is likely to notice: ETags are the quoted MD5 of the stored
+/// body, listings are sorted by key and match prefixes byte for byte, missing keys fail with a
+/// 404 [`R2Error::FailedRequest`], SSE-C objects can only be read with their key, and upload
+/// options are validated and applied (compression included) the same way. Progress observers and
+/// rate limits are ignored, there's nothing to wait for.
+///
+/// Clones share the same objects.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    objects: Arc<Mutex<BTreeMap<String, StoredObject>>>,
+    mime_registry: MimeRegistry,
+}
+
+/// An object the way R2 keeps it. [`MockServer`](crate::test_support::MockServer) keeps its
+/// objects like this too, so the two can't disagree on what gets stored.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredObject {
+    pub(crate) body: Bytes,
+    /// What it's served with, all but `Content-Length`.
+    pub(crate) headers: Vec<(String, String)>,
+    /// The same, parsed.
+    pub(crate) metadata: ObjectMetadata,
+    pub(crate) sse_customer_key: Option<SseCustomerKey>,
+}
+
+impl StoredObject {
+    /// Fails if `headers` (which should include the ETag and `Last-Modified`) aren't valid.
+    pub(crate) fn new(
+        body: Bytes,
+        headers: Vec<(String, String)>,
+        sse_customer_key: Option<SseCustomerKey>,
+    ) -> Result<Self, R2Error> {
+        let mut with_length = headers.clone();
+        with_length.push(("content-length".to_string(), body.len().to_string()));
+        Ok(Self {
+            metadata: metadata(with_length)?,
+            body,
+            headers,
+            sse_customer_key,
+        })
+    }
+
+    /// A copy made now, with the same ETag but encrypted with `sse_customer_key`, and with
+    /// `headers` instead of the object's own if there are any.
+    pub(crate) fn copy(
+        &self,
+        headers: Option<Vec<(String, String)>>,
+        sse_customer_key: Option<SseCustomerKey>,
+    ) -> Result<Self, R2Error> {
+        let mut headers = headers.unwrap_or_else(|| self.headers.clone());
+        headers.retain(|(name, _)| name != "etag" && name != "last-modified");
+        headers.extend(
+            self.metadata
+                .etag
+                .clone()
+                .map(|etag| ("etag".to_string(), etag)),
+        );
+        headers.push(("last-modified".to_string(), httpdate::fmt_http_date(now())));
+        Self::new(self.body.clone(), headers, sse_customer_key)
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Infers content types with `mime_registry` instead of the default one, like
+    /// [`R2Client::with_mime_registry`](crate::R2Client::with_mime_registry).
+    pub fn with_mime_registry(mut self, mime_registry: MimeRegistry) -> Self {
+        self.mime_registry = mime_registry;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects().is_empty()
+    }
+
+    fn objects(&self) -> MutexGuard<'_, BTreeMap<String, StoredObject>> {
+        self.objects.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn put(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let upload = Upload::new(key, body, options, &self.mime_registry)?;
+        let object = StoredObject::new(
+            upload.body,
+            upload.headers,
+            options.sse_customer_key.clone(),
+        )?;
+        self.insert(key, object);
+        Ok(upload.output)
+    }
+
+    fn get(&self, key: &str, options: &GetOptions) -> Result<GetObjectOutput, R2Error> {
+        options.interrupt().check()?;
+        let object = self.object(key, options.sse_customer_key.as_ref(), || {
+            format!("getting object \"{key}\" from bucket \"{BUCKET}\"")
+        })?;
+        serve(object.body, object.metadata, options)
+    }
+
+    fn head(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        let object = self.object(key, None, || {
+            format!("heading object \"{key}\" in bucket \"{BUCKET}\"")
+        })?;
+        Ok(object.metadata)
+    }
+
+    fn copy(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let object = self
+            .object(source_key, options.source_sse_customer_key.as_ref(), || {
+                format!(
+                    "copying \"{source_key}\" from bucket \"{BUCKET}\" to \"{key}\" in bucket \"{BUCKET}\""
+                )
+            })?
+            .copy(None, options.sse_customer_key.clone())?;
+        let etag = object.metadata.etag.clone();
+        self.insert(key, object);
+        Ok(PutObjectOutput {
+            etag,
+            checksum: None,
+        })
+    }
+
+    pub(crate) fn delete(&self, key: &str) {
+        self.objects().remove(key);
+    }
+
+    pub(crate) fn list(&self, prefix: &str) -> Vec<ObjectInfo> {
+        self.objects()
+            .range(prefix.to_owned()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| ObjectInfo {
+                key: key.clone(),
+                size: object.body.len() as u64,
+                etag: object.metadata.etag.clone(),
+                last_modified: object.metadata.last_modified,
+            })
+            .collect()
+    }
+
+    pub(crate) fn insert(&self, key: &str, object: StoredObject) {
+        self.objects().insert(key.to_owned(), object);
+    }
+
+    /// The object at `key`, whatever it was encrypted with.
+    pub(crate) fn stored(&self, key: &str) -> Option<StoredObject> {
+        self.objects().get(key).cloned()
+    }
+
+    /// The object at `key`, as long as it was stored with `sse_customer_key` (or without one if
+    /// that's `None`), failing the way R2 would otherwise.
+    pub(crate) fn object(
+        &self,
+        key: &str,
+        sse_customer_key: Option<&SseCustomerKey>,
+        operation: impl FnOnce() -> String,
+    ) -> Result<StoredObject, R2Error> {
+        let Some(object) = self.stored(key) else {
+            return Err(no_such_key(operation()));
+        };
+        if object.sse_customer_key.as_ref() != sse_customer_key {
+            return Err(wrong_sse_customer_key(operation()));
+        }
+        Ok(object)
+    }
+}
+
+/// An upload the way R2 would store it: compressed if it was asked to be, with the headers it
+/// gets served back with (all but `Content-Length`).
+pub(crate) struct Upload {
+    pub(crate) body: Bytes,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) output: PutObjectOutput,
+}
+
+impl Upload {
+    pub(crate) fn new(
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+        mime_registry: &MimeRegistry,
+    ) -> Result<Self, R2Error> {
+        options.interrupt().check()?;
+        let body = match options.compression {
+            Some(compression) => Bytes::from(compression.compress(&body)?),
+            None => body,
+        };
+        let (mut headers, checksum) = options.headers(&body)?;
+        // R2 never sends the SSE-C key back, so there's no reason to keep it around
+        headers.retain(|(name, _)| !name.starts_with("x-amz-server-side-encryption-customer"));
+        let content_type = options
+            .content_type
+            .as_deref()
+            .unwrap_or_else(|| mime_registry.infer(key, &body));
+        let etag = etag(&body);
+        headers.extend([
+            ("content-type".to_string(), content_type.to_owned()),
+            ("etag".to_string(), etag.clone()),
+            ("last-modified".to_string(), httpdate::fmt_http_date(now())),
+        ]);
+        Ok(Self {
+            body,
+            headers,
+            output: PutObjectOutput {
+                etag: Some(etag),
+                checksum,
+            },
+        })
+    }
+}
+
+/// A stored object the way R2 would hand it out for `options`: with the response headers it
+/// asks for and decompressed if it should be.
+pub(crate) fn serve(
+    body: Bytes,
+    mut metadata: ObjectMetadata,
+    options: &GetOptions,
+) -> Result<GetObjectOutput, R2Error> {
+    for (field, value) in [
+        (&mut metadata.content_type, &options.response_content_type),
+        (
+            &mut metadata.content_disposition,
+            &options.response_content_disposition,
+        ),
+        (&mut metadata.cache_control, &options.response_cache_control),
+    ] {
+        if value.is_some() {
+            field.clone_from(value);
+        }
+    }
+    let compression = if options.decompress {
+        Compression::from_content_encoding(metadata.content_encoding.as_deref())?
+    } else {
+        None
+    };
+    let body = match compression {
+        Some(_) => decompress(compression, &body)?.into(),
+        None => body,
+    };
+    Ok(GetObjectOutput { body, metadata })
+}
+
+/// What R2 fails `operation` with when the key doesn't exist.
+pub(crate) fn no_such_key(operation: String) -> R2Error {
+    R2Error::FailedRequest(operation, StatusCode::NOT_FOUND, NO_SUCH_KEY.to_string())
+}
+
+/// What R2 fails `operation` with when an SSE-C object is read without its key (or the other way
+/// round).
+pub(crate) fn wrong_sse_customer_key(operation: String) -> R2Error {
+    R2Error::FailedRequest(
+        operation,
+        StatusCode::BAD_REQUEST,
+        WRONG_SSE_CUSTOMER_KEY.to_string(),
+    )
+}
+
+/// The ETag R2 gives objects that weren't uploaded in parts, the quoted MD5 of the body.
+pub(crate) fn etag(body: &[u8]) -> String {
+    format!("\"{:x}\"", Md5::digest(body))
+}
+
+/// Parses the headers an upload would've been sent with, so stored metadata comes out exactly
+/// like R2 would send it back (lowercased metadata names, dates to the second, ...).
+pub(crate) fn metadata(headers: Vec<(String, String)>) -> Result<ObjectMetadata, R2Error> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let value = HeaderValue::from_str(&value)
+            .map_err(|_| R2Error::InvalidOptions(format!("{name} isn't a valid header value")))?;
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| R2Error::InvalidOptions(format!("{name} isn't a valid header name")))?;
+        map.append(name, value);
+    }
+    Ok(ObjectMetadata::from_response(&map))
+}
+
+/// Now, to the second, since that's all `Last-Modified` has room for.
+pub(crate) fn now() -> SystemTime {
+    let since_epoch = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    SystemTime::UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())
+}
+
+impl Storage for MemoryStorage {
+    async fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.put(key, body, options)
+    }
+
+    async fn get_object(
+        &self,
+        key: &str,
+        options: &GetOptions,
+    ) -> Result<GetObjectOutput, R2Error> {
+        self.get(key, options)
+    }
+
+    async fn head_object(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        self.head(key)
+    }
+
+    async fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.copy(source_key, key, options)
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<(), R2Error> {
+        self.delete(key);
+        Ok(())
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        Ok(self.list(prefix))
+    }
+}
+
+#[cfg(feature = "sync")]
+impl crate::sync::Storage for MemoryStorage {
+    fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.put(key, body, options)
+    }
+
+    fn get_object(&self, key: &str, options: &GetOptions) -> Result<GetObjectOutput, R2Error> {
+        self.get(key, options)
+    }
+
+    fn head_object(&self, key: &str) -> Result<ObjectMetadata, R2Error> {
+        self.head(key)
+    }
+
+    fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        self.copy(source_key, key, options)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), R2Error> {
+        self.delete(key);
+        Ok(())
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        Ok(self.list(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CancellationToken;
+    use std::collections::HashMap;
+
+    fn status(error: R2Error) -> StatusCode {
+        match error {
+            R2Error::FailedRequest(_, status, _) => status,
+            error => panic!("expected a failed request, got {error}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let storage = MemoryStorage::new();
+        let options = PutOptions {
+            metadata: HashMap::from([("Owner".to_string(), "pyrite".to_string())]),
+            ..Default::default()
+        };
+        let output = storage
+            .put_object("notes/hello.txt", Bytes::from("hello world"), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            output.etag.as_deref(),
+            Some("\"5eb63bbbe01eeed093cb22bb8f5acdc3\"")
+        );
+
+        let object = storage
+            .get_object("notes/hello.txt", &GetOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(object.body, "hello world");
+        assert_eq!(object.metadata.etag, output.etag);
+        assert_eq!(object.metadata.content_length, Some(11));
+        assert_eq!(object.metadata.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(object.metadata.metadata["owner"], "pyrite");
+        assert_eq!(
+            storage.head_object("notes/hello.txt").await.unwrap(),
+            object.metadata
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_keys() {
+        let storage = MemoryStorage::new();
+        let error = storage
+            .get_object("nope", &GetOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status(error), StatusCode::NOT_FOUND);
+        let error = storage.head_object("nope").await.unwrap_err();
+        assert_eq!(status(error), StatusCode::NOT_FOUND);
+        storage.delete_object("nope").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn listing() {
+        let storage = MemoryStorage::new();
+        for key in ["b", "a/b", "ab", "a/a", "a"] {
+            storage
+                .put_object(key, Bytes::from(key), &PutOptions::default())
+                .await
+                .unwrap();
+        }
+        let keys = |objects: Vec<ObjectInfo>| -> Vec<String> {
+            objects.into_iter().map(|object| object.key).collect()
+        };
+        assert_eq!(
+            keys(storage.list_objects("").await.unwrap()),
+            ["a", "a/a", "a/b", "ab", "b"]
+        );
+        assert_eq!(
+            keys(storage.list_objects("a/").await.unwrap()),
+            ["a/a", "a/b"]
+        );
+        assert_eq!(
+            keys(storage.list_objects("a").await.unwrap()),
+            ["a", "a/a", "a/b", "ab"]
+        );
+        assert!(storage.list_objects("c").await.unwrap().is_empty());
+        assert_eq!(storage.list_objects("a/b").await.unwrap()[0].size, 3);
+    }
+
+    #[tokio::test]
+    async fn copy_and_delete() {
+        let storage = MemoryStorage::new();
+        let put = storage
+            .put_object("a", Bytes::from("data"), &PutOptions::default())
+            .await
+            .unwrap();
+        let copied = storage
+            .copy_object("a", "b", &CopyOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(copied.etag, put.etag);
+        storage.delete_object("a").await.unwrap();
+        assert_eq!(storage.len(), 1);
+        let error = storage
+            .copy_object("a", "c", &CopyOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status(error), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn sse_customer_keys() {
+        let storage = MemoryStorage::new();
+        let key = SseCustomerKey::new([7; 32]);
+        let options = PutOptions {
+            sse_customer_key: Some(key.clone()),
+            ..Default::default()
+        };
+        storage
+            .put_object("secret", Bytes::from("shh"), &options)
+            .await
+            .unwrap();
+        let error = storage
+            .get_object("secret", &GetOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status(error), StatusCode::BAD_REQUEST);
+        let with_key = GetOptions {
+            sse_customer_key: Some(key),
+            ..Default::default()
+        };
+        assert_eq!(
+            storage.get_object("secret", &with_key).await.unwrap().body,
+            "shh"
+        );
+    }
+
+    #[tokio::test]
+    async fn options_are_validated() {
+        let storage = MemoryStorage::new();
+        let invalid = PutOptions {
+            metadata: HashMap::from([("not valid".to_string(), "x".to_string())]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            storage.put_object("a", Bytes::new(), &invalid).await,
+            Err(R2Error::InvalidOptions(_))
+        ));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancelled = PutOptions {
+            cancellation: Some(token),
+            ..Default::default()
+        };
+        assert!(matches!(
+            storage.put_object("a", Bytes::new(), &cancelled).await,
+            Err(R2Error::Cancelled)
+        ));
+        assert!(storage.is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_overrides() {
+        let storage = MemoryStorage::new();
+        storage
+            .put_object("a.txt", Bytes::from("a"), &PutOptions::default())
+            .await
+            .unwrap();
+        let options = GetOptions {
+            response_content_type: Some("application/octet-stream".to_string()),
+            ..Default::default()
+        };
+        let object = storage.get_object("a.txt", &options).await.unwrap();
+        assert_eq!(
+            object.metadata.content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn blocking() {
+        use crate::sync::Storage;
+
+        fn archive(storage: &impl Storage, key: &str) -> Result<(), R2Error> {
+            storage.copy_object(key, &format!("archive/{key}"), &CopyOptions::default())?;
+            storage.delete_object(key)
+        }
+
+        let storage = MemoryStorage::new();
+        Storage::put_object(&storage, "a", Bytes::from("a"), &PutOptions::default()).unwrap();
+        archive(&storage, "a").unwrap();
+        let keys: Vec<_> = Storage::list_objects(&storage, "")
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        assert_eq!(keys, ["archive/a"]);
+    }
+}
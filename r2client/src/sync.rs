@@ -1,4 +1,15 @@
+#[cfg(feature = "encryption")]
+mod encrypted_bucket;
 mod r2bucket;
 mod r2client;
+mod storage;
+#[cfg(feature = "encryption")]
+pub use encrypted_bucket::EncryptedBucket;
 pub use r2bucket::R2Bucket;
 pub use r2client::R2Client;
+pub use storage::Storage;
+
+/// The same directory-backed bucket as the async API's, it implements both traits.
+pub use crate::FileStorage;
+/// The same in-memory bucket as the async API's, it implements both traits.
+pub use crate::MemoryStorage;
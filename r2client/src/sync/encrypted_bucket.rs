@@ -0,0 +1,90 @@
+use crate::envelope::{self, MasterKey};
+use crate::sync::R2Bucket;
+use crate::{GetObjectOutput, GetOptions, PutObjectOutput, PutOptions, R2Error};
+
+/// Wraps an [`R2Bucket`] so objects are encrypted before they're uploaded and decrypted after
+/// they're downloaded. R2 never sees the plaintext, or any key that could decrypt it.
+///
+/// See [`MasterKey`] for how the keys work. Objects uploaded through here can only be read back
+/// through an `EncryptedBucket` with the same master key.
+#[derive(Debug)]
+pub struct EncryptedBucket {
+    bucket: R2Bucket,
+    master_key: MasterKey,
+}
+
+impl EncryptedBucket {
+    pub fn new(bucket: R2Bucket, master_key: MasterKey) -> Self {
+        Self { bucket, master_key }
+    }
+
+    /// The underlying bucket, for listing and deleting (which don't need any keys).
+    pub fn bucket(&self) -> &R2Bucket {
+        &self.bucket
+    }
+
+    /// The content type (sniffed from the plaintext or inferred from the key if not set)
+    /// describes the plaintext.
+    pub fn put_object(
+        &self,
+        r2_file_key: &str,
+        plaintext: &[u8],
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let (ciphertext, metadata) = envelope::seal(&self.master_key, r2_file_key, plaintext)?;
+        let mut options = options.clone();
+        options.content_type.get_or_insert_with(|| {
+            self.bucket
+                .client
+                .mime_registry()
+                .infer(r2_file_key, plaintext)
+                .to_owned()
+        });
+        options.metadata.extend(metadata);
+        self.bucket.put_object(r2_file_key, ciphertext, &options)
+    }
+
+    pub fn upload_file(
+        &self,
+        local_file_path: &str,
+        r2_file_key: &str,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let plaintext = std::fs::read(local_file_path)?;
+        let options = PutOptions {
+            content_type: Some(
+                self.bucket
+                    .client
+                    .mime_registry()
+                    .infer(local_file_path, &plaintext)
+                    .to_owned(),
+            ),
+            ..Default::default()
+        };
+        self.put_object(r2_file_key, &plaintext, &options)
+    }
+
+    /// Integrity checks (if enabled) run against the ciphertext, and on top of that GCM
+    /// refuses to decrypt anything that's been tampered with.
+    pub fn get_object(
+        &self,
+        r2_file_key: &str,
+        options: &GetOptions,
+    ) -> Result<GetObjectOutput, R2Error> {
+        let mut output = self.bucket.get_object(r2_file_key, options)?;
+        let plaintext = envelope::open(
+            &self.master_key,
+            r2_file_key,
+            &output.body,
+            &output.metadata.metadata,
+        )?;
+        output.metadata.content_length = Some(plaintext.len() as u64);
+        output.body = plaintext.into();
+        Ok(output)
+    }
+
+    pub fn download_file(&self, r2_file_key: &str, local_path: &str) -> Result<(), R2Error> {
+        let output = self.get_object(r2_file_key, &GetOptions::default())?;
+        std::fs::write(local_path, output.body)?;
+        Ok(())
+    }
+}
@@ -1,16 +1,42 @@
-use crate::R2Error;
-use crate::mimetypes::get_mimetype_from_fp;
+use crate::cancel::Interrupt;
+use crate::checksum::ChecksumVerifier;
+use crate::compression::{DecodingWriter, decompress};
+use crate::dir::{Filter, Walk, dir_prefix, local_path, map_concurrently, walk};
+use crate::dirsync::{plan_download, plan_upload};
+use crate::endpoint::{AddressingStyle, Jurisdiction, object_url, validate_endpoint};
+use crate::listing::{ListPage, list_query, parse_list_page};
+use crate::mirror::{Checkpoint, destination_prefix, plan_mirror, put_options};
+use crate::multipart::{complete_body, parse_upload_id, part_count, read_part};
+use crate::object::{GetObjectOutput, Listing, ObjectInfo, ObjectMetadata, PutObjectOutput};
+use crate::options::{CopyOptions, DirOptions, GetOptions, MirrorOptions, PutOptions, SyncOptions};
+use crate::progress::{Payload, PayloadReader, ProgressTracker};
+use crate::ratelimit::Throttle;
+use crate::{
+    CompletedPart, DEFAULT_PART_SIZE, DirReport, FileReport, Fixtures, MimeRegistry, MirrorMethod,
+    MirrorOutcome, MirrorReport, MultipartUpload, PartProgress, Provider, R2Error, RateLimiter,
+    RetryPolicy, SyncAction, SyncOutcome, SyncReport,
+};
 use aws_sigv4::SigV4Credentials;
+use bytes::Bytes;
 use http::Method;
 use log::trace;
 use reqwest::header::HeaderMap;
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct R2Client {
     sigv4: SigV4Credentials,
     endpoint: String,
+    addressing_style: AddressingStyle,
+    retry_policy: RetryPolicy,
+    mime_registry: MimeRegistry,
+    rate_limiter: Option<RateLimiter>,
+    fixtures: Option<Fixtures>,
+    http: reqwest::blocking::Client,
 }
 impl R2Client {
     fn get_env() -> Result<(String, String, String), R2Error> {
@@ -23,17 +49,128 @@ impl R2Client {
     pub fn new() -> Self {
         let (access_key, secret_key, endpoint) = Self::get_env().unwrap();
 
-        Self {
-            sigv4: SigV4Credentials::new("s3", "auto", access_key, secret_key),
-            endpoint,
-        }
+        Self::from_credentials(access_key, secret_key, endpoint)
+            .expect("R2_ENDPOINT should be a valid endpoint URI")
     }
 
-    pub fn from_credentials(access_key: String, secret_key: String, endpoint: String) -> Self {
-        Self {
+    /// Fails with [`R2Error::InvalidEndpoint`] if the endpoint isn't an http(s) URI with a host.
+    /// Signs for R2's "auto" region, use [`R2Client::with_region`] or [`R2Client::from_provider`]
+    /// for anything else.
+    pub fn from_credentials(
+        access_key: String,
+        secret_key: String,
+        endpoint: String,
+    ) -> Result<Self, R2Error> {
+        Ok(Self {
             sigv4: SigV4Credentials::new("s3", "auto", access_key, secret_key),
-            endpoint,
-        }
+            endpoint: validate_endpoint(endpoint)?,
+            addressing_style: AddressingStyle::default(),
+            retry_policy: RetryPolicy::default(),
+            mime_registry: MimeRegistry::default(),
+            rate_limiter: None,
+            fixtures: None,
+            http: reqwest::blocking::Client::new(),
+        })
+    }
+
+    /// Builds the endpoint from your Cloudflare account ID instead of making you paste the whole
+    /// `https://<account_id>.r2.cloudflarestorage.com` URL, which also differs per [`Jurisdiction`].
+    pub fn from_account(
+        account_id: &str,
+        jurisdiction: Jurisdiction,
+        access_key: String,
+        secret_key: String,
+    ) -> Result<Self, R2Error> {
+        let provider = Provider::R2 {
+            account_id: account_id.to_owned(),
+            jurisdiction,
+        };
+        Self::from_provider(&provider, access_key, secret_key)
+    }
+
+    /// Sets up the endpoint, region and addressing style for any S3-compatible [`Provider`].
+    pub fn from_provider(
+        provider: &Provider,
+        access_key: String,
+        secret_key: String,
+    ) -> Result<Self, R2Error> {
+        Ok(
+            Self::from_credentials(access_key, secret_key, provider.endpoint()?)?
+                .with_region(provider.region())
+                .with_addressing_style(provider.addressing_style()),
+        )
+    }
+
+    /// The region requests are signed for ("auto" for R2).
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.sigv4.set_region(region);
+        self
+    }
+
+    pub fn region(&self) -> &str {
+        self.sigv4.region()
+    }
+
+    /// Switches between path-style (the default) and virtual-hosted-style URLs.
+    pub fn with_addressing_style(mut self, addressing_style: AddressingStyle) -> Self {
+        self.addressing_style = addressing_style;
+        self
+    }
+
+    pub fn addressing_style(&self) -> AddressingStyle {
+        self.addressing_style
+    }
+
+    /// Replaces the default [`RetryPolicy`] (3 attempts with jittered exponential backoff).
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Replaces the [`MimeRegistry`] used to pick content types for uploads that don't set one.
+    pub fn with_mime_registry(mut self, mime_registry: MimeRegistry) -> Self {
+        self.mime_registry = mime_registry;
+        self
+    }
+
+    pub fn mime_registry(&self) -> &MimeRegistry {
+        &self.mime_registry
+    }
+
+    /// For registering extra types on a client that's already set up.
+    pub fn mime_registry_mut(&mut self) -> &mut MimeRegistry {
+        &mut self.mime_registry
+    }
+
+    /// Caps the bandwidth of every transfer made through this client, together. Transfers can
+    /// set a tighter limit of their own with `rate_limit` in their options.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// Records every request and response to a fixture file, or answers requests from one
+    /// instead of sending them, see [`Fixtures`].
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures = Some(fixtures);
+        self
+    }
+
+    pub fn fixtures(&self) -> Option<&Fixtures> {
+        self.fixtures.as_ref()
+    }
+
+    /// The client's limiter plus the transfer's own, if either is set.
+    fn throttle(&self, rate_limit: Option<&RateLimiter>) -> Throttle {
+        Throttle::new(self.rate_limiter.iter().chain(rate_limit))
     }
 
     fn create_headers(
@@ -41,25 +178,102 @@ impl R2Client {
         method: http::Method,
         bucket: &str,
         key: Option<&str>,
+        query: &[(String, String)],
         payload: impl AsRef<[u8]>,
-        content_type: Option<&str>,
         extra_headers: Option<Vec<(String, String)>>,
     ) -> Result<HeaderMap, R2Error> {
-        let uri = http::Uri::from_str(&self.build_url(bucket, key))
-            .expect("invalid uri rip (make sure the build_url function works as intended)");
+        let url = self.build_url(bucket, key, query);
+        let uri = http::Uri::from_str(&url)
+            .map_err(|e| R2Error::InvalidEndpoint(format!("built invalid URL {url:?} ({e})")))?;
+        // Non-default ports (MinIO's :9000 and friends) are part of the Host header, and therefore
+        // of the signature
+        let host = match (uri.host(), uri.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => {
+                return Err(R2Error::InvalidEndpoint(format!(
+                    "built URL {url:?} has no host"
+                )));
+            }
+        };
         let mut headers = extra_headers.unwrap_or_default();
-        headers.push((
-            "host".to_string(),
-            uri.host().expect("Should have host in URI").to_owned(),
-        ));
-        if let Some(content_type) = content_type {
-            headers.push(("content-type".to_string(), content_type.to_owned()))
-        }
+        headers.push(("host".to_string(), host));
 
-        let (_, header_map) = self.sigv4.signature(method, uri, headers, payload);
+        let (_, mut header_map) = self.sigv4.signature(method, uri, headers, payload);
+        crate::sse::mark_sensitive(&mut header_map);
         Ok(header_map)
     }
 
+    /// Signs and sends a request, retrying it according to the client's [`RetryPolicy`].
+    ///
+    /// Whatever response comes back last is returned as-is, so callers still have to check the
+    /// status themselves.
+    fn send(
+        &self,
+        method: http::Method,
+        bucket: &str,
+        key: Option<&str>,
+        query: &[(String, String)],
+        payload: impl Into<Payload>,
+        extra_headers: Option<Vec<(String, String)>>,
+    ) -> Result<reqwest::blocking::Response, R2Error> {
+        let payload = payload.into();
+        let url = self.build_url(bucket, key, query);
+        let mut attempt = 1;
+        loop {
+            payload.interrupt.check()?;
+            // Signed on every attempt, otherwise x-amz-date goes stale while we back off
+            let headers = self.create_headers(
+                method.clone(),
+                bucket,
+                key,
+                query,
+                &payload.bytes,
+                extra_headers.clone(),
+            )?;
+            trace!("[send] Headers sent to request (attempt {attempt}): {headers:#?}");
+            let mut request = self.http.request(method.clone(), &url).headers(headers);
+            // Blocking requests can't be dropped halfway, but they can be timed out
+            if let Some(remaining) = payload.interrupt.remaining() {
+                request = request.timeout(remaining);
+            }
+            let request = if payload.is_streamed() {
+                request.body(reqwest::blocking::Body::sized(
+                    PayloadReader::new(payload.clone()),
+                    payload.bytes.len() as u64,
+                ))
+            } else {
+                request.body(payload.bytes.clone())
+            };
+            let result = match &self.fixtures {
+                Some(fixtures) => fixtures.send_blocking(request)?,
+                None => request.send(),
+            };
+            if result.is_err() {
+                payload.interrupt.check()?;
+            }
+            let retry_reason = match &result {
+                Ok(resp) if self.retry_policy.retries_status(resp.status()) => {
+                    resp.status().to_string()
+                }
+                Err(e) if self.retry_policy.retries_error(e) => e.to_string(),
+                _ => return result.map_err(R2Error::from),
+            };
+            if !self.retry_policy.has_attempts_left(attempt) {
+                return result.map_err(R2Error::from);
+            }
+            let delay = self.retry_policy.delay(attempt);
+            trace!("[send] {method} {url} failed ({retry_reason}), retrying in {delay:?}");
+            std::thread::sleep(
+                payload
+                    .interrupt
+                    .remaining()
+                    .map_or(delay, |remaining| remaining.min(delay)),
+            );
+            attempt += 1;
+        }
+    }
+
     pub fn upload_file(
         &self,
         bucket: &str,
@@ -67,47 +281,100 @@ impl R2Client {
         r2_file_key: &str,
         content_type: Option<&str>,
     ) -> Result<(), R2Error> {
+        let options = PutOptions {
+            content_type: content_type.map(str::to_owned),
+            ..Default::default()
+        };
+        self.upload_file_with_options(bucket, local_file_path, r2_file_key, &options)
+            .map(|_| ())
+    }
+
+    pub fn upload_file_with_options(
+        &self,
+        bucket: &str,
+        local_file_path: &str,
+        r2_file_key: &str,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
         // Payload (file data)
-        let payload = std::fs::read(local_file_path)?;
+        let payload = Bytes::from(std::fs::read(local_file_path)?);
         trace!(
             "[upload_file] Payload hash for signing: {}",
             aws_sigv4::hash(&payload)
         );
 
         // Set HTTP Headers
-        let content_type = if let Some(content_type) = content_type {
-            Some(content_type)
-        } else {
-            Some(get_mimetype_from_fp(local_file_path))
+        let content_type = options
+            .content_type
+            .as_deref()
+            .unwrap_or_else(|| self.mime_registry.infer(local_file_path, &payload));
+        self.put(bucket, r2_file_key, payload, content_type, options, || {
+            format!(
+                "upload file {local_file_path} to bucket \"{bucket}\" under file key \"{r2_file_key}\""
+            )
+        })
+    }
+
+    /// Uploads an in-memory body. Without [`PutOptions::content_type`], the content type is
+    /// sniffed from the body, or inferred from the key if that doesn't work.
+    pub fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: impl Into<Bytes>,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let body = body.into();
+        let content_type = options
+            .content_type
+            .as_deref()
+            .unwrap_or_else(|| self.mime_registry.infer(key, &body));
+        self.put(bucket, key, body, content_type, options, || {
+            format!("put object \"{key}\" in bucket \"{bucket}\"")
+        })
+    }
+
+    fn put(
+        &self,
+        bucket: &str,
+        key: &str,
+        payload: Bytes,
+        content_type: &str,
+        options: &PutOptions,
+        operation: impl FnOnce() -> String,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let payload = match options.compression {
+            Some(compression) => {
+                let compressed = compression.compress(&payload)?;
+                trace!(
+                    "[put] Compressed {} bytes to {} with {}",
+                    payload.len(),
+                    compressed.len(),
+                    compression.content_encoding()
+                );
+                Bytes::from(compressed)
+            }
+            None => payload,
         };
-        let headers = self.create_headers(
-            Method::PUT,
-            bucket,
-            Some(r2_file_key),
-            &payload,
-            content_type,
-            None,
-        )?;
-        trace!("[upload_file] Headers sent to request: {headers:#?}");
-        let file_url = self.build_url(bucket, Some(r2_file_key));
-        let client = reqwest::blocking::Client::new();
-        let resp = client
-            .put(&file_url)
-            .headers(headers)
-            .body(payload)
-            .send()?;
+        let (mut headers, checksum) = options.headers(&payload)?;
+        headers.push(("content-type".to_string(), content_type.to_owned()));
+        if let Some(checksum) = &checksum {
+            trace!("[put] Sending checksum {checksum}");
+        }
+        let progress = options
+            .progress
+            .as_ref()
+            .map(|observer| ProgressTracker::new(observer, Some(payload.len() as u64)));
+        let throttle = self.throttle(options.rate_limit.as_ref());
+        let payload = Payload::new(payload, progress, throttle);
+        let resp = self.send(Method::PUT, bucket, Some(key), &[], payload, Some(headers))?;
         let status = resp.status();
+        let output = PutObjectOutput::from_response(resp.headers(), checksum);
         let text = resp.text()?;
         if status.is_success() {
-            Ok(())
+            Ok(output)
         } else {
-            Err(R2Error::FailedRequest(
-                format!(
-                    "upload file {local_file_path} to bucket \"{bucket}\" under file key \"{r2_file_key}\""
-                ),
-                status,
-                text,
-            ))
+            Err(R2Error::FailedRequest(operation(), status, text))
         }
     }
     pub fn download_file(
@@ -116,44 +383,531 @@ impl R2Client {
         key: &str,
         local_path: &str,
         extra_headers: Option<Vec<(String, String)>>,
+    ) -> Result<(), R2Error> {
+        self.download(
+            bucket,
+            key,
+            local_path,
+            &GetOptions::default(),
+            extra_headers,
+        )
+    }
+
+    pub fn download_file_with_options(
+        &self,
+        bucket: &str,
+        key: &str,
+        local_path: &str,
+        options: &GetOptions,
+    ) -> Result<(), R2Error> {
+        self.download(bucket, key, local_path, options, None)
+    }
+
+    fn download(
+        &self,
+        bucket: &str,
+        key: &str,
+        local_path: &str,
+        options: &GetOptions,
+        extra_headers: Option<Vec<(String, String)>>,
     ) -> Result<(), R2Error> {
         // https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv-create-signed-request.html#:~:text=For%20Amazon%20S3%2C%20include%20the%20literal%20string%20UNSIGNED%2DPAYLOAD%20when%20constructing%20a%20canonical%20request%2C%20and%20set%20the%20same%20value%20as%20the%20x%2Damz%2Dcontent%2Dsha256%20header%20value%20when%20sending%20the%20request.
         // I don't know if I should trust it though, I don't see public impls with this.
-        let payload = "";
         trace!("[download_file] Payload for signing: (empty)");
-        let headers =
-            self.create_headers(Method::GET, bucket, Some(key), payload, None, extra_headers)?;
-        trace!("[download_file] Headers sent to request: {headers:#?}");
-        let file_url = self.build_url(bucket, Some(key));
-        let client = reqwest::blocking::Client::new();
-        let resp = client.get(&file_url).headers(headers).send()?;
+        let mut headers = extra_headers.unwrap_or_default();
+        headers.extend(options.headers());
+        let resp = self.send(
+            Method::GET,
+            bucket,
+            Some(key),
+            &options.query(),
+            Payload::from(Bytes::new()).with_interrupt(options.interrupt()),
+            Some(headers),
+        )?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(
+                format!("dowloading file \"{key}\" from bucket \"{bucket}\""),
+                status,
+                resp.text()?,
+            ));
+        }
+        let verifier = if options.verify_integrity {
+            ChecksumVerifier::from_response(resp.headers(), options.etag_is_md5())
+        } else {
+            None
+        };
+        let compression = options.decompression(resp.headers())?;
+        let progress = options
+            .progress
+            .as_ref()
+            .map(|observer| ProgressTracker::new(observer, resp.content_length()));
+        let throttle = self.throttle(options.rate_limit.as_ref());
+        let mut file = std::fs::File::create(local_path)?;
+        let result = match DecodingWriter::new(compression, &mut file) {
+            Ok(writer) => Self::write_body(
+                resp,
+                writer,
+                verifier,
+                progress,
+                throttle,
+                options.interrupt(),
+                key,
+            ),
+            Err(e) => Err(e.into()),
+        };
+        if result.is_err() {
+            // Don't leave a truncated or corrupted file lying around
+            drop(file);
+            let _ = std::fs::remove_file(local_path);
+        }
+        result
+    }
+
+    /// Streams the body into `writer`, hashing it along the way if there's something to verify.
+    fn write_body(
+        mut resp: reqwest::blocking::Response,
+        mut writer: DecodingWriter<&mut std::fs::File>,
+        mut verifier: Option<ChecksumVerifier>,
+        progress: Option<ProgressTracker>,
+        throttle: Throttle,
+        interrupt: Interrupt,
+        key: &str,
+    ) -> Result<(), R2Error> {
+        let mut received = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = resp.read(&mut buf).map_err(|e| interrupt.explain(e))?;
+            if read == 0 {
+                break;
+            }
+            interrupt.check()?;
+            let delay = throttle.delay(read);
+            if !delay.is_zero() {
+                std::thread::sleep(delay);
+            }
+            if let Some(verifier) = &mut verifier {
+                verifier.update(&buf[..read]);
+            }
+            writer.write_all(&buf[..read])?;
+            received += read as u64;
+            if let Some(progress) = &progress {
+                progress.report(received);
+            }
+        }
+        writer.finish()?;
+        match verifier {
+            Some(verifier) => verifier.verify(key),
+            None => Ok(()),
+        }
+    }
+
+    /// Downloads an object into memory.
+    pub fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &GetOptions,
+    ) -> Result<GetObjectOutput, R2Error> {
+        let resp = self.send(
+            Method::GET,
+            bucket,
+            Some(key),
+            &options.query(),
+            Payload::from(Bytes::new()).with_interrupt(options.interrupt()),
+            Some(options.headers()),
+        )?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(
+                format!("getting object \"{key}\" from bucket \"{bucket}\""),
+                status,
+                resp.text()?,
+            ));
+        }
+        let metadata = ObjectMetadata::from_response(resp.headers());
+        let verifier = if options.verify_integrity {
+            ChecksumVerifier::from_response(resp.headers(), options.etag_is_md5())
+        } else {
+            None
+        };
+        let compression = options.decompression(resp.headers())?;
+        let progress = options
+            .progress
+            .as_ref()
+            .map(|observer| ProgressTracker::new(observer, resp.content_length()));
+        let throttle = self.throttle(options.rate_limit.as_ref());
+        let interrupt = options.interrupt();
+        let mut body = if progress.is_some() || !throttle.is_empty() {
+            let mut body = Vec::new();
+            let mut resp = resp;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let read = resp.read(&mut buf).map_err(|e| interrupt.explain(e))?;
+                if read == 0 {
+                    break;
+                }
+                interrupt.check()?;
+                let delay = throttle.delay(read);
+                if !delay.is_zero() {
+                    std::thread::sleep(delay);
+                }
+                body.extend_from_slice(&buf[..read]);
+                if let Some(progress) = &progress {
+                    progress.report(body.len() as u64);
+                }
+            }
+            Bytes::from(body)
+        } else {
+            resp.bytes().map_err(|e| interrupt.explain(e))?
+        };
+        if let Some(mut verifier) = verifier {
+            verifier.update(&body);
+            verifier.verify(key)?;
+        }
+        if compression.is_some() {
+            body = decompress(compression, &body)?.into();
+        }
+        Ok(GetObjectOutput { body, metadata })
+    }
+
+    /// A link anyone can download the object from without credentials, until `expires_in` is up
+    /// (at most a week). The `response_*` overrides of `options` are baked into the link.
+    ///
+    /// SSE-C objects can't be presigned, the key would have to be sent as a header.
+    pub fn presigned_get_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        expires_in: Duration,
+        options: &GetOptions,
+    ) -> Result<String, R2Error> {
+        if expires_in > aws_sigv4::MAX_PRESIGN_EXPIRY {
+            return Err(R2Error::InvalidOptions(format!(
+                "presigned URLs can be valid for a week at most, not {expires_in:?}"
+            )));
+        }
+        if options.sse_customer_key.is_some() {
+            return Err(R2Error::InvalidOptions(
+                "SSE-C objects can't be downloaded through a presigned URL".to_string(),
+            ));
+        }
+        let url = self.build_url(bucket, Some(key), &options.query());
+        let uri = http::Uri::from_str(&url)
+            .map_err(|e| R2Error::InvalidEndpoint(format!("built invalid URL {url:?} ({e})")))?;
+        Ok(self.sigv4.presign(Method::GET, uri, expires_in))
+    }
+
+    pub fn head_object(&self, bucket: &str, key: &str) -> Result<ObjectMetadata, R2Error> {
+        self.head_object_with_options(bucket, key, &GetOptions::default())
+    }
+
+    /// Only [`GetOptions::sse_customer_key`] matters for HEAD requests, there's no body to verify.
+    pub fn head_object_with_options(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &GetOptions,
+    ) -> Result<ObjectMetadata, R2Error> {
+        let headers = options.sse_customer_key.as_ref().map(|key| key.headers());
+        let resp = self.send(Method::HEAD, bucket, Some(key), &[], Bytes::new(), headers)?;
+        let status = resp.status();
+        if status.is_success() {
+            Ok(ObjectMetadata::from_response(resp.headers()))
+        } else {
+            Err(R2Error::FailedRequest(
+                format!("heading object \"{key}\" in bucket \"{bucket}\""),
+                status,
+                resp.text()?,
+            ))
+        }
+    }
+
+    /// Server-side copy, the data never passes through this client.
+    pub fn copy_object(
+        &self,
+        source_bucket: &str,
+        source_key: &str,
+        bucket: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let mut headers = options.headers();
+        headers.push((
+            "x-amz-copy-source".to_string(),
+            format!("/{source_bucket}/{}", aws_sigv4::url_encode(source_key)),
+        ));
+        let resp = self.send(
+            Method::PUT,
+            bucket,
+            Some(key),
+            &[],
+            Bytes::new(),
+            Some(headers),
+        )?;
+        let status = resp.status();
+        let text = resp.text()?;
+        let operation = || {
+            format!(
+                "copying \"{source_key}\" from bucket \"{source_bucket}\" to \"{key}\" in bucket \"{bucket}\""
+            )
+        };
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(operation(), status, text));
+        }
+        // Copies can fail after the 200 has already been sent, in which case the body is an
+        // <Error> instead of a <CopyObjectResult>
+        let root = xmltree::Element::parse(text.as_bytes())?;
+        if root.name == "Error" {
+            return Err(R2Error::FailedRequest(operation(), status, text));
+        }
+        Ok(PutObjectOutput {
+            etag: root
+                .get_child("ETag")
+                .and_then(|etag| etag.get_text())
+                .map(|etag| etag.into_owned()),
+            checksum: None,
+        })
+    }
+
+    /// Starts a multipart upload. Everything in `options` applies to the whole object, except
+    /// for checksums and compression, which multipart uploads don't support.
+    ///
+    /// The content type is inferred from the key if `options` doesn't set one.
+    pub fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &PutOptions,
+    ) -> Result<MultipartUpload, R2Error> {
+        let mut headers = options.multipart_headers()?;
+        let content_type = options
+            .content_type
+            .as_deref()
+            .unwrap_or_else(|| self.mime_registry.from_path(key));
+        headers.push(("content-type".to_string(), content_type.to_owned()));
+        let resp = self.send(
+            Method::POST,
+            bucket,
+            Some(key),
+            &[("uploads".to_string(), String::new())],
+            Payload::from(Bytes::new()).with_interrupt(options.interrupt()),
+            Some(headers),
+        )?;
+        let status = resp.status();
+        let text = resp.text()?;
+        let operation =
+            || format!("starting a multipart upload of \"{key}\" to bucket \"{bucket}\"");
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(operation(), status, text));
+        }
+        match parse_upload_id(&text)? {
+            Some(upload_id) => Ok(MultipartUpload {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                upload_id,
+            }),
+            None => Err(R2Error::FailedRequest(operation(), status, text)),
+        }
+    }
+
+    /// Uploads one part (numbered from 1) of a multipart upload. Only the SSE-C key, the
+    /// progress observer, the rate limit, the cancellation token and the deadline of `options`
+    /// matter here. Cancelling a part doesn't abort the upload, that's up to you.
+    pub fn upload_part(
+        &self,
+        upload: &MultipartUpload,
+        part_number: u32,
+        body: impl Into<Bytes>,
+        options: &PutOptions,
+    ) -> Result<CompletedPart, R2Error> {
+        let body = body.into();
+        let progress = options
+            .progress
+            .as_ref()
+            .map(|observer| ProgressTracker::new(observer, Some(body.len() as u64)));
+        self.send_part(upload, part_number, body, options, progress)
+    }
+
+    fn send_part(
+        &self,
+        upload: &MultipartUpload,
+        part_number: u32,
+        body: Bytes,
+        options: &PutOptions,
+        progress: Option<ProgressTracker>,
+    ) -> Result<CompletedPart, R2Error> {
+        let resp = self.send(
+            Method::PUT,
+            &upload.bucket,
+            Some(&upload.key),
+            &upload.part_query(part_number),
+            Payload::new(body, progress, self.throttle(options.rate_limit.as_ref()))
+                .with_interrupt(options.interrupt()),
+            Some(options.part_headers()),
+        )?;
+        let status = resp.status();
+        let etag = resp
+            .headers()
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned);
+        let text = resp.text()?;
+        match etag {
+            Some(etag) if status.is_success() => Ok(CompletedPart { part_number, etag }),
+            _ => Err(R2Error::FailedRequest(
+                format!(
+                    "uploading part {part_number} of \"{}\" to bucket \"{}\"",
+                    upload.key, upload.bucket
+                ),
+                status,
+                text,
+            )),
+        }
+    }
+
+    /// Stitches the uploaded parts together into the final object.
+    pub fn complete_multipart_upload(
+        &self,
+        upload: &MultipartUpload,
+        parts: &[CompletedPart],
+    ) -> Result<PutObjectOutput, R2Error> {
+        let resp = self.send(
+            Method::POST,
+            &upload.bucket,
+            Some(&upload.key),
+            &upload.upload_id_query(),
+            Bytes::from(complete_body(parts)),
+            Some(vec![(
+                "content-type".to_string(),
+                "application/xml".to_string(),
+            )]),
+        )?;
+        let status = resp.status();
+        let text = resp.text()?;
+        let operation = || {
+            format!(
+                "completing the multipart upload of \"{}\" to bucket \"{}\"",
+                upload.key, upload.bucket
+            )
+        };
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(operation(), status, text));
+        }
+        // Like copies, completing can fail after the 200 has been sent
+        let root = xmltree::Element::parse(text.as_bytes())?;
+        if root.name == "Error" {
+            return Err(R2Error::FailedRequest(operation(), status, text));
+        }
+        Ok(PutObjectOutput {
+            etag: root
+                .get_child("ETag")
+                .and_then(|etag| etag.get_text())
+                .map(|etag| etag.into_owned()),
+            checksum: None,
+        })
+    }
+
+    /// Throws away a multipart upload and every part uploaded to it.
+    pub fn abort_multipart_upload(&self, upload: &MultipartUpload) -> Result<(), R2Error> {
+        let resp = self.send(
+            Method::DELETE,
+            &upload.bucket,
+            Some(&upload.key),
+            &upload.upload_id_query(),
+            Bytes::new(),
+            None,
+        )?;
         let status = resp.status();
         if status.is_success() {
-            std::fs::write(local_path, resp.bytes()?)?;
             Ok(())
         } else {
             Err(R2Error::FailedRequest(
-                format!("dowloading file \"{key}\" from bucket \"{bucket}\""),
+                format!(
+                    "aborting the multipart upload of \"{}\" to bucket \"{}\"",
+                    upload.key, upload.bucket
+                ),
                 status,
                 resp.text()?,
             ))
         }
     }
+
+    /// Uploads a file in `part_size` byte parts (see [`crate::DEFAULT_PART_SIZE`]), reading one
+    /// part at a time so the file never has to fit in memory. If anything goes wrong, the upload
+    /// is aborted so the parts don't linger. That includes being cancelled or running past the
+    /// deadline.
+    ///
+    /// The progress observer in `options` hears about the bytes of the whole file, and about
+    /// every part as it finishes.
+    pub fn upload_file_multipart(
+        &self,
+        bucket: &str,
+        local_file_path: &str,
+        key: &str,
+        part_size: u64,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error> {
+        let mut file = std::fs::File::open(local_file_path)?;
+        let total_size = file.metadata()?.len();
+        let total_parts = part_count(total_size, part_size)?;
+        let first_part = read_part(&mut file, part_size)?;
+        let mut options = options.clone();
+        options.content_type.get_or_insert_with(|| {
+            self.mime_registry
+                .infer(local_file_path, &first_part)
+                .to_owned()
+        });
+        let upload = self.create_multipart_upload(bucket, key, &options)?;
+        trace!(
+            "[upload_file_multipart] Uploading {local_file_path} in {total_parts} parts as {}",
+            upload.upload_id
+        );
+
+        let progress = options
+            .progress
+            .as_ref()
+            .map(|observer| ProgressTracker::new(observer, Some(total_size)));
+        let result = (|| {
+            let mut parts = Vec::with_capacity(total_parts as usize);
+            let mut next_part = Some(first_part);
+            let mut offset = 0;
+            for part_number in 1..=total_parts {
+                let body = match next_part.take() {
+                    Some(body) => body,
+                    None => read_part(&mut file, part_size)?,
+                };
+                let part_bytes = body.len() as u64;
+                let part_progress = progress.as_ref().map(|progress| progress.at_offset(offset));
+                parts.push(self.send_part(&upload, part_number, body, &options, part_progress)?);
+                offset += part_bytes;
+                if let Some(progress) = &progress {
+                    progress.part_complete(PartProgress {
+                        part_number,
+                        part_bytes,
+                        parts_completed: part_number,
+                        total_parts,
+                    });
+                }
+            }
+            self.complete_multipart_upload(&upload, &parts)
+        })();
+        if result.is_err() {
+            let _ = self.abort_multipart_upload(&upload);
+        }
+        result
+    }
+
     pub fn delete(&self, bucket: &str, remote_key: &str) -> Result<(), R2Error> {
-        let payload = "";
         trace!("[delete_file] Payload for signing: (empty)");
-        let headers = self.create_headers(
+        let resp = self.send(
             Method::DELETE,
             bucket,
             Some(remote_key),
-            payload,
-            None,
+            &[],
+            Bytes::new(),
             None,
         )?;
-        trace!("[delete_file] Headers sent to request: {headers:#?}");
-        let file_url = self.build_url(bucket, Some(remote_key));
-        let client = reqwest::blocking::Client::new();
-        let resp = client.delete(&file_url).headers(headers).send()?;
         let status = resp.status();
         if status.is_success() {
             Ok(())
@@ -166,17 +920,8 @@ impl R2Client {
         }
     }
     fn get_bucket_listing(&self, bucket: &str) -> Result<String, R2Error> {
-        let payload = "";
         trace!("[get_bucket_listing] Payload for signing: (empty)");
-        let headers = self.create_headers(Method::GET, bucket, None, payload, None, None)?;
-        trace!("[get_bucket_listing] Headers sent to request: {headers:#?}");
-        let url = self.build_url(bucket, None);
-        let client = reqwest::blocking::Client::new();
-        let resp = client
-            .get(&url)
-            .headers(headers)
-            .send()
-            .map_err(R2Error::from)?;
+        let resp = self.send(Method::GET, bucket, None, &[], Bytes::new(), None)?;
         let status = resp.status();
         if status.is_success() {
             Ok(resp.text().map_err(R2Error::from)?)
@@ -232,14 +977,403 @@ impl R2Client {
         Ok(folders.into_iter().collect())
     }
 
-    fn build_url(&self, bucket: &str, key: Option<&str>) -> String {
-        match key {
-            Some(k) => {
-                let encoded_key = aws_sigv4::url_encode(k);
-                format!("{}/{}/{}", self.endpoint, bucket, encoded_key)
+    /// Lists every object whose key starts with `prefix` (every object, for ""), going through
+    /// as many pages as it takes.
+    pub fn list_objects(&self, bucket: &str, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error> {
+        let mut objects = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let page = self.list_page(bucket, prefix, None, continuation_token.as_deref())?;
+            objects.extend(page.objects);
+            match page.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    /// Lists the objects whose key starts with `prefix`, with the keys that have `delimiter` after
+    /// it rolled up into [`Listing::common_prefixes`]. With `/`, that's what's directly under
+    /// `prefix` as if it were a directory.
+    pub fn list_objects_delimited(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        delimiter: &str,
+    ) -> Result<Listing, R2Error> {
+        let mut listing = Listing::default();
+        let mut continuation_token = None;
+        loop {
+            let page = self.list_page(
+                bucket,
+                prefix,
+                Some(delimiter),
+                continuation_token.as_deref(),
+            )?;
+            listing.objects.extend(page.objects);
+            listing.common_prefixes.extend(page.common_prefixes);
+            match page.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(listing),
+            }
+        }
+    }
+
+    fn list_page(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        delimiter: Option<&str>,
+        continuation_token: Option<&str>,
+    ) -> Result<ListPage, R2Error> {
+        let query = list_query(prefix, delimiter, continuation_token);
+        let resp = self.send(Method::GET, bucket, None, &query, Bytes::new(), None)?;
+        let status = resp.status();
+        let text = resp.text()?;
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(
+                format!("listing objects under \"{prefix}\" in bucket \"{bucket}\""),
+                status,
+                text,
+            ));
+        }
+        parse_list_page(&text)
+    }
+
+    /// Uploads every file under `local_dir`, recursively, to `prefix` followed by its path
+    /// relative to `local_dir` (with `/` separators on every platform). `prefix` is treated as a
+    /// directory, so `photos` and `photos/` both upload `a.jpg` to `photos/a.jpg`.
+    ///
+    /// Only fails as a whole if the directory can't be read or the globs in `options` don't
+    /// parse, how each file went is in the report (including the ones that couldn't be read).
+    pub fn upload_dir(
+        &self,
+        bucket: &str,
+        local_dir: &str,
+        prefix: &str,
+        options: &DirOptions,
+    ) -> Result<DirReport, R2Error> {
+        let filter = Filter::new(options)?;
+        let prefix = dir_prefix(prefix);
+        let Walk { files, unreadable } = walk(Path::new(local_dir))?;
+        let files: Vec<_> = files
+            .into_iter()
+            .filter(|file| filter.matches(&file.relative))
+            .collect();
+        trace!(
+            "[upload_dir] Uploading {} files from {local_dir} to {prefix}",
+            files.len()
+        );
+        let mut reports = map_concurrently(files, options.concurrency, |file| {
+            let key = format!("{prefix}{}", file.relative);
+            let result = match file.path.to_str() {
+                Some(path) => self
+                    .upload_file_with_options(bucket, path, &key, &options.put)
+                    .map(|_| file.size),
+                None => Err(not_utf8(&file.path)),
+            };
+            FileReport {
+                local_path: file.path,
+                key,
+                result,
+            }
+        });
+        reports.extend(
+            unreadable
+                .into_iter()
+                .filter(|entry| filter.matches(&entry.relative))
+                .map(|entry| entry.into_report(&prefix)),
+        );
+        Ok(DirReport::new(reports))
+    }
+
+    /// Downloads every object under `prefix` into `local_dir`, recreating the key structure
+    /// below the prefix as directories. The counterpart of [`R2Client::upload_dir`].
+    ///
+    /// Keys that would end up outside of `local_dir` (say `prefix/../../.bashrc`) aren't
+    /// downloaded and show up as failures in the report. Keys ending in `/` (which are only
+    /// there to make "folders" show up in dashboards) are skipped.
+    pub fn download_prefix(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        local_dir: &str,
+        options: &DirOptions,
+    ) -> Result<DirReport, R2Error> {
+        let filter = Filter::new(options)?;
+        let prefix = dir_prefix(prefix);
+        let local_dir = Path::new(local_dir);
+        let objects: Vec<_> = self
+            .list_objects(bucket, &prefix)?
+            .into_iter()
+            .filter(|object| {
+                object.key.starts_with(&prefix)
+                    && !object.key.ends_with('/')
+                    && filter.matches(&object.key[prefix.len()..])
+            })
+            .collect();
+        trace!(
+            "[download_prefix] Downloading {} objects from {prefix} to {}",
+            objects.len(),
+            local_dir.display()
+        );
+        let reports = map_concurrently(objects, options.concurrency, |object| {
+            let relative = &object.key[prefix.len()..];
+            let result = match local_path(local_dir, relative) {
+                Ok(path) => self
+                    .download_to(bucket, &object.key, &path, &options.get)
+                    .map(|_| object.size),
+                Err(e) => Err(e),
+            };
+            FileReport {
+                local_path: local_dir.join(relative),
+                key: object.key,
+                result,
             }
-            None => format!("{}/{}/", self.endpoint, bucket),
+        });
+        Ok(DirReport::new(reports))
+    }
+
+    fn download_to(
+        &self,
+        bucket: &str,
+        key: &str,
+        path: &Path,
+        options: &GetOptions,
+    ) -> Result<(), R2Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
+        let path = path.to_str().ok_or_else(|| not_utf8(path))?;
+        self.download_file_with_options(bucket, key, path, options)
+    }
+
+    /// Makes the objects under `prefix` match the files under `local_dir`, uploading only what's
+    /// new or changed (see [`SyncOptions::comparison`]). With
+    /// [`SyncOptions::delete_extraneous`], objects that aren't there locally are deleted too.
+    /// `prefix` is treated as a directory, like it is for [`R2Client::upload_dir`].
+    pub fn sync_dir_to_prefix(
+        &self,
+        bucket: &str,
+        local_dir: &str,
+        prefix: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncReport, R2Error> {
+        let filter = Filter::new(&options.dir)?;
+        let prefix = dir_prefix(prefix);
+        let local = walk(Path::new(local_dir))?;
+        let remote = self.list_objects(bucket, &prefix)?;
+        let outcomes = plan_upload(local, remote, &prefix, &filter, options)?;
+        Ok(self.run_sync(bucket, outcomes, options))
+    }
+
+    /// The other direction of [`R2Client::sync_dir_to_prefix`]: makes the files under
+    /// `local_dir` (which is created if it doesn't exist) match the objects under `prefix`.
+    pub fn sync_prefix_to_dir(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        local_dir: &str,
+        options: &SyncOptions,
+    ) -> Result<SyncReport, R2Error> {
+        let filter = Filter::new(&options.dir)?;
+        let prefix = dir_prefix(prefix);
+        let local_dir = Path::new(local_dir);
+        let local = match walk(local_dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Walk::default(),
+            local => local?,
+        };
+        let remote = self.list_objects(bucket, &prefix)?;
+        let outcomes = plan_download(local.files, remote, &prefix, local_dir, &filter, options)?;
+        Ok(self.run_sync(bucket, outcomes, options))
+    }
+
+    /// Carries out whatever was planned (and hasn't already failed), unless it's a dry run.
+    fn run_sync(
+        &self,
+        bucket: &str,
+        outcomes: Vec<SyncOutcome>,
+        options: &SyncOptions,
+    ) -> SyncReport {
+        trace!(
+            "[sync] {} actions planned{}",
+            outcomes.len(),
+            if options.dry_run { " (dry run)" } else { "" }
+        );
+        let outcomes = map_concurrently(outcomes, options.dir.concurrency, |outcome| {
+            if options.dry_run || outcome.result.is_some() {
+                return outcome;
+            }
+            let result = self.sync_action(bucket, &outcome.action, &options.dir);
+            SyncOutcome {
+                result: Some(result),
+                ..outcome
+            }
+        });
+        SyncReport {
+            dry_run: options.dry_run,
+            outcomes,
+        }
+    }
+
+    fn sync_action(
+        &self,
+        bucket: &str,
+        action: &SyncAction,
+        options: &DirOptions,
+    ) -> Result<(), R2Error> {
+        match action {
+            SyncAction::Upload {
+                local_path, key, ..
+            } => {
+                let path = local_path.to_str().ok_or_else(|| not_utf8(local_path))?;
+                self.upload_file_with_options(bucket, path, key, &options.put)
+                    .map(|_| ())
+            }
+            SyncAction::Download {
+                key, local_path, ..
+            } => self.download_to(bucket, key, local_path, &options.get),
+            SyncAction::DeleteRemote { key } => self.delete(bucket, key),
+            SyncAction::DeleteLocal { local_path } => Ok(std::fs::remove_file(local_path)?),
+        }
+    }
+
+    /// Copies every object under [`MirrorOptions::prefix`] in `source_bucket` to
+    /// `destination_bucket` on `destination` (which can be this very client), skipping the ones
+    /// the destination already has with the same ETag.
+    ///
+    /// When both clients use the same endpoint and credentials, objects are copied server-side
+    /// with `CopyObject` and never leave the bucket. Otherwise, say for a bucket on another
+    /// account or provider (or an object over the 5 GiB `CopyObject` takes), every object is
+    /// downloaded through this client and uploaded again, in [`crate::DEFAULT_PART_SIZE`] parts
+    /// when it's bigger than that, so big objects don't have to fit in memory.
+    ///
+    /// Only fails as a whole if either listing fails or the checkpoint can't be opened, how each
+    /// object went is in the report.
+    pub fn mirror(
+        &self,
+        source_bucket: &str,
+        destination: &R2Client,
+        destination_bucket: &str,
+        options: &MirrorOptions,
+    ) -> Result<MirrorReport, R2Error> {
+        let method = if self.shares_account_with(destination) {
+            MirrorMethod::ServerSideCopy
+        } else {
+            MirrorMethod::Streamed
+        };
+        let checkpoint = Checkpoint::open(
+            options.checkpoint.as_deref(),
+            destination_bucket,
+            destination_prefix(options),
+        )?;
+        let source = self.list_objects(source_bucket, &options.prefix)?;
+        let existing = destination.list_objects(destination_bucket, destination_prefix(options))?;
+        let (jobs, unchanged) = plan_mirror(source, existing, options, &checkpoint);
+        trace!(
+            "[mirror] Copying {} objects ({method:?}), {unchanged} unchanged",
+            jobs.len()
+        );
+        let outcomes = map_concurrently(jobs, options.concurrency, |job| {
+            let key = &job.source.key;
+            let result = match method.for_object(&job.source) {
+                MirrorMethod::ServerSideCopy => destination
+                    .copy_object(
+                        source_bucket,
+                        key,
+                        destination_bucket,
+                        &job.destination_key,
+                        &CopyOptions::default(),
+                    )
+                    .map(|_| ()),
+                MirrorMethod::Streamed => self.stream_object(
+                    source_bucket,
+                    key,
+                    destination,
+                    destination_bucket,
+                    &job.destination_key,
+                ),
+            };
+            let result = result.and_then(|()| Ok(checkpoint.record(&job.source)?));
+            MirrorOutcome {
+                key: job.source.key,
+                destination_key: job.destination_key,
+                result,
+            }
+        });
+        Ok(MirrorReport::new(method, outcomes, unchanged))
+    }
+
+    /// Whether `other` can read what this client can, making server-side copies possible.
+    fn shares_account_with(&self, other: &R2Client) -> bool {
+        self.endpoint == other.endpoint
+            && self.region() == other.region()
+            && self.sigv4.access_key() == other.sigv4.access_key()
+    }
+
+    /// Downloads an object and uploads it to `destination` as it comes in, verifying it against
+    /// the source's checksum before the copy is finished.
+    fn stream_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        destination: &R2Client,
+        destination_bucket: &str,
+        destination_key: &str,
+    ) -> Result<(), R2Error> {
+        let mut resp = self.send(Method::GET, bucket, Some(key), &[], Bytes::new(), None)?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(R2Error::FailedRequest(
+                format!("getting object \"{key}\" from bucket \"{bucket}\""),
+                status,
+                resp.text()?,
+            ));
+        }
+        let options = put_options(&ObjectMetadata::from_response(resp.headers()));
+        let mut verifier = ChecksumVerifier::from_response(resp.headers(), true);
+        let first_part = read_part(&mut resp, DEFAULT_PART_SIZE)?;
+        if (first_part.len() as u64) < DEFAULT_PART_SIZE {
+            if let Some(mut verifier) = verifier {
+                verifier.update(&first_part);
+                verifier.verify(key)?;
+            }
+            destination.put_object(destination_bucket, destination_key, first_part, &options)?;
+            return Ok(());
+        }
+
+        let upload =
+            destination.create_multipart_upload(destination_bucket, destination_key, &options)?;
+        let result = (|| {
+            let mut parts = Vec::new();
+            let mut next_part = Some(first_part);
+            loop {
+                let body = match next_part.take() {
+                    Some(body) => body,
+                    None => read_part(&mut resp, DEFAULT_PART_SIZE)?,
+                };
+                if body.is_empty() {
+                    break;
+                }
+                if let Some(verifier) = &mut verifier {
+                    verifier.update(&body);
+                }
+                let part_number = parts.len() as u32 + 1;
+                parts.push(destination.send_part(&upload, part_number, body, &options, None)?);
+            }
+            if let Some(verifier) = verifier {
+                verifier.verify(key)?;
+            }
+            destination.complete_multipart_upload(&upload, &parts)
+        })();
+        if result.is_err() {
+            let _ = destination.abort_multipart_upload(&upload);
+        }
+        result.map(|_| ())
+    }
+
+    fn build_url(&self, bucket: &str, key: Option<&str>, query: &[(String, String)]) -> String {
+        object_url(&self.endpoint, self.addressing_style, bucket, key, query)
     }
 }
 impl Default for R2Client {
@@ -248,6 +1382,10 @@ impl Default for R2Client {
     }
 }
 
+fn not_utf8(path: &Path) -> R2Error {
+    R2Error::InvalidOptions(format!("{} isn't valid UTF-8", path.display()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -283,15 +1421,19 @@ mod tests {
             "AKIAEXAMPLE".to_string(),
             "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
             "https://example.r2.cloudflarestorage.com".to_string(),
-        );
+        )
+        .unwrap();
         let headers = client
             .create_headers(
                 Method::PUT,
                 "bucket",
                 Some("key"),
+                &[],
                 "deadbeef",
-                Some("application/octet-stream"),
-                None,
+                Some(vec![(
+                    "content-type".to_string(),
+                    "application/octet-stream".to_string(),
+                )]),
             )
             .unwrap();
         assert!(headers.contains_key("x-amz-date"));
@@ -299,4 +1441,135 @@ mod tests {
         assert!(headers.contains_key("content-type"));
         assert!(headers.contains_key("host"));
     }
+
+    #[test]
+    fn virtual_hosted_signing() {
+        let client = R2Client::from_credentials(
+            "AKIAEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            "https://example.r2.cloudflarestorage.com".to_string(),
+        )
+        .unwrap()
+        .with_addressing_style(AddressingStyle::VirtualHosted);
+        let headers = client
+            .create_headers(Method::GET, "bucket", Some("key"), &[], "", None)
+            .unwrap();
+        assert_eq!(headers["host"], "bucket.example.r2.cloudflarestorage.com");
+        assert!(
+            headers["authorization"]
+                .to_str()
+                .unwrap()
+                .contains("SignedHeaders=host;")
+        );
+    }
+
+    #[test]
+    fn provider_settings() {
+        let client = R2Client::from_provider(
+            &Provider::MinIo {
+                endpoint: "http://localhost:9000".to_string(),
+            },
+            "minioadmin".to_string(),
+            "minioadmin".to_string(),
+        )
+        .unwrap();
+        assert_eq!(client.region(), "us-east-1");
+        assert_eq!(client.addressing_style(), AddressingStyle::Path);
+        let headers = client
+            .create_headers(Method::GET, "bucket", Some("key"), &[], "", None)
+            .unwrap();
+        assert_eq!(headers["host"], "localhost:9000");
+        assert!(
+            headers["authorization"]
+                .to_str()
+                .unwrap()
+                .contains("/us-east-1/s3/aws4_request")
+        );
+
+        let client = R2Client::from_provider(
+            &Provider::AwsS3 {
+                region: "eu-west-2".to_string(),
+            },
+            "AKIAEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        )
+        .unwrap();
+        assert_eq!(client.endpoint, "https://s3.eu-west-2.amazonaws.com");
+        assert_eq!(client.region(), "eu-west-2");
+    }
+
+    #[test]
+    fn from_account() {
+        let client = R2Client::from_account(
+            "023e105f4ecef8ad9ca31a8372d0c353",
+            Jurisdiction::Eu,
+            "AKIAEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            client.endpoint,
+            "https://023e105f4ecef8ad9ca31a8372d0c353.eu.r2.cloudflarestorage.com"
+        );
+    }
+
+    #[test]
+    fn invalid_endpoint() {
+        let result = R2Client::from_credentials(
+            "AKIAEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            "example.r2.cloudflarestorage.com".to_string(),
+        );
+        assert!(matches!(result, Err(R2Error::InvalidEndpoint(_))));
+    }
+
+    #[test]
+    fn presigned_get_url() {
+        let client = R2Client::from_credentials(
+            "AKIAEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            "https://example.r2.cloudflarestorage.com".to_string(),
+        )
+        .unwrap();
+        let options = GetOptions {
+            response_content_disposition: Some("attachment; filename=\"report.pdf\"".to_string()),
+            ..Default::default()
+        };
+        let url = client
+            .presigned_get_url(
+                "bucket",
+                "dir/report 1.pdf",
+                Duration::from_secs(3600),
+                &options,
+            )
+            .unwrap();
+        assert!(url.starts_with(
+            "https://example.r2.cloudflarestorage.com/bucket/dir/report%201.pdf?X-Amz-Algorithm="
+        ));
+        assert!(url.contains("X-Amz-Expires=3600&"));
+        assert!(url.contains(
+            "&response-content-disposition=attachment%3B%20filename%3D%22report.pdf%22&"
+        ));
+        assert!(url.contains("&X-Amz-Signature="));
+
+        assert!(
+            client
+                .presigned_get_url(
+                    "bucket",
+                    "key",
+                    Duration::from_secs(8 * 24 * 3600),
+                    &options
+                )
+                .is_err()
+        );
+        let sse = GetOptions {
+            sse_customer_key: Some(crate::SseCustomerKey::new([1; 32])),
+            ..Default::default()
+        };
+        assert!(
+            client
+                .presigned_get_url("bucket", "key", Duration::from_secs(60), &sse)
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,47 @@
+//! The blocking counterpart of [`crate::Storage`].
+
+use crate::listing::roll_up;
+use crate::{
+    CopyOptions, GetObjectOutput, GetOptions, Listing, ObjectInfo, ObjectMetadata, PutObjectOutput,
+    PutOptions, R2Error,
+};
+use bytes::Bytes;
+
+/// The object operations of a bucket, blocking.
+///
+/// [`R2Bucket`](crate::sync::R2Bucket) implements it against R2,
+/// [`MemoryStorage`](crate::MemoryStorage) against a map in memory and
+/// [`FileStorage`](crate::FileStorage) against a directory, so code that's generic over it can be
+/// tested offline or run locally.
+pub trait Storage: Send + Sync {
+    fn put_object(
+        &self,
+        key: &str,
+        body: Bytes,
+        options: &PutOptions,
+    ) -> Result<PutObjectOutput, R2Error>;
+
+    fn get_object(&self, key: &str, options: &GetOptions) -> Result<GetObjectOutput, R2Error>;
+
+    fn head_object(&self, key: &str) -> Result<ObjectMetadata, R2Error>;
+
+    /// Copies an object within the same bucket.
+    fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+        options: &CopyOptions,
+    ) -> Result<PutObjectOutput, R2Error>;
+
+    /// Deleting a key that doesn't exist isn't an error.
+    fn delete_object(&self, key: &str) -> Result<(), R2Error>;
+
+    /// Every object whose key starts with `prefix` (every object, for ""), sorted by key.
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, R2Error>;
+
+    /// Like [`list_objects`](Self::list_objects), with the keys that have `delimiter` after
+    /// `prefix` rolled up into common prefixes. By default that's done on the full listing.
+    fn list_objects_delimited(&self, prefix: &str, delimiter: &str) -> Result<Listing, R2Error> {
+        Ok(roll_up(self.list_objects(prefix)?, prefix, delimiter))
+    }
+}
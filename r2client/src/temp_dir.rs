@@ -0,0 +1,52 @@
+//! Scratch directories for tests, shared by the unit tests and (through
+//! [`test_support`](crate::test_support)) the end to end ones.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A fresh, empty directory under the system's temp directory, removed again with everything in
+/// it when it's dropped (which includes a test panicking).
+///
+/// Names only have to be unique within a test binary, the process id and a counter keep
+/// concurrent tests and test runs apart.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Panics if the directory can't be created.
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "r2client-{name}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("couldn't create a temporary directory");
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where `relative` goes inside the directory, as a string since that's how the clients take
+    /// local paths.
+    #[cfg_attr(not(feature = "test-support"), allow(dead_code))]
+    pub fn file(&self, relative: &str) -> String {
+        self.path
+            .join(relative)
+            .to_str()
+            .expect("temporary paths are valid UTF-8")
+            .to_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
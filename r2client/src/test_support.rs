@@ -0,0 +1,1261 @@
+//! [`MockServer`], an S3 server in the same process, so every part of the client can be tested
+//! end to end without credentials or a network. [`TempDir`] is there for tests that need files.
+
+pub use crate::temp_dir::TempDir;
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::listing::{format_timestamp, roll_up};
+use crate::multipart::escape_xml;
+use crate::storage::{StoredObject, etag, now, wrong_sse_customer_key};
+use crate::{MIN_PART_SIZE, MemoryStorage, R2Error, SseCustomerKey};
+use aws_sigv4::SigV4Credentials;
+use bytes::Bytes;
+use http::header::HeaderMap;
+use http::{HeaderName, HeaderValue, Method, StatusCode};
+use md5::{Digest, Md5};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+
+/// How many keys a listing page has unless [`MockServer::set_page_size`] says otherwise.
+const DEFAULT_PAGE_SIZE: usize = 1000;
+/// Where SSE-C headers for the object itself start, the copy source's have their own prefix.
+const SSE_CUSTOMER: &str = "x-amz-server-side-encryption-customer";
+const COPY_SOURCE_SSE_CUSTOMER: &str = "x-amz-copy-source-server-side-encryption-customer";
+
+/// An S3 server on a thread of its own, keeping every bucket in a [`MemoryStorage`].
+///
+/// It speaks as much of the API as the client uses: PUT (with conditional headers, checksums and
+/// SSE-C), GET and HEAD (with ranges, conditional headers and `response-*` overrides), DELETE,
+/// CopyObject, ListObjectsV2 with delimiters and pagination, and multipart uploads (parts but the
+/// last have to be at least [`MIN_PART_SIZE`], like on R2). Requests have to be path-style and
+/// signed for [`ACCESS_KEY`](Self::ACCESS_KEY) and [`SECRET_KEY`](Self::SECRET_KEY) in the
+/// "auto" region, or be a presigned URL, and get the 403 R2 would answer with otherwise. Buckets
+/// exist as soon as something is put in them.
+///
+/// ```
+/// use r2client::PutOptions;
+/// use r2client::test_support::MockServer;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let server = MockServer::start();
+/// let client = server.client();
+/// client
+///     .put_object("bucket", "hello.txt", "hello", &PutOptions::default())
+///     .await
+///     .unwrap();
+/// assert_eq!(server.object("bucket", "hello.txt").unwrap(), "hello");
+/// # }
+/// ```
+///
+/// It stops when it's dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    address: SocketAddr,
+    endpoint: String,
+    state: Arc<State>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub const ACCESS_KEY: &str = "mock-access-key";
+    pub const SECRET_KEY: &str = "mock-secret-key";
+
+    /// Starts listening on a free port on localhost. Panics if there isn't one.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("no free port on localhost");
+        let address = listener
+            .local_addr()
+            .expect("a bound listener has an address");
+        let state = Arc::new(State::default());
+        let thread = {
+            let state = state.clone();
+            std::thread::spawn(move || serve(listener, state))
+        };
+        Self {
+            address,
+            endpoint: format!("http://{address}"),
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    /// What to point a client at, e.g. `http://127.0.0.1:41234`.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// A client with the server's credentials.
+    #[cfg(feature = "async")]
+    pub fn client(&self) -> crate::R2Client {
+        crate::R2Client::from_credentials(
+            Self::ACCESS_KEY.to_string(),
+            Self::SECRET_KEY.to_string(),
+            self.endpoint.clone(),
+        )
+        .expect("the endpoint is a valid URL")
+    }
+
+    /// A blocking client with the server's credentials.
+    #[cfg(feature = "sync")]
+    pub fn blocking_client(&self) -> crate::sync::R2Client {
+        crate::sync::R2Client::from_credentials(
+            Self::ACCESS_KEY.to_string(),
+            Self::SECRET_KEY.to_string(),
+            self.endpoint.clone(),
+        )
+        .expect("the endpoint is a valid URL")
+    }
+
+    /// The body of an object, to check what a test uploaded without going through the client.
+    pub fn object(&self, bucket: &str, key: &str) -> Option<Bytes> {
+        let object = self.state.inner().buckets.get(bucket)?.stored(key)?;
+        Some(object.body)
+    }
+
+    /// Every key in `bucket`, sorted.
+    pub fn keys(&self, bucket: &str) -> Vec<String> {
+        let Some(storage) = self.state.inner().buckets.get(bucket).cloned() else {
+            return Vec::new();
+        };
+        storage
+            .list("")
+            .into_iter()
+            .map(|object| object.key)
+            .collect()
+    }
+
+    /// Multipart uploads that have been started but not completed or aborted yet.
+    pub fn uploads_in_progress(&self) -> usize {
+        self.state.inner().uploads.len()
+    }
+
+    /// Every request the server got so far, as the method and the path and query it was sent to,
+    /// e.g. `PUT /bucket/key?partNumber=1&uploadId=1`.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.inner().requests.clone()
+    }
+
+    /// Caps listings at `page_size` keys a page, so pagination can be tested without thousands of
+    /// objects.
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state.inner().page_size = page_size.max(1);
+    }
+
+    /// Answers the next `requests` requests with `status` (and an error body) whatever they are,
+    /// for testing retries.
+    pub fn fail_next(&self, requests: usize, status: StatusCode) {
+        self.state
+            .inner()
+            .failures
+            .extend(std::iter::repeat_n(status, requests));
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.state.stopped.store(true, Ordering::SeqCst);
+        // Wakes the listener up so it notices
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    signer: SigV4Credentials,
+    stopped: AtomicBool,
+    inner: Mutex<Inner>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            signer: SigV4Credentials::new(
+                "s3",
+                "auto",
+                MockServer::ACCESS_KEY,
+                MockServer::SECRET_KEY,
+            ),
+            stopped: AtomicBool::new(false),
+            inner: Mutex::new(Inner {
+                page_size: DEFAULT_PAGE_SIZE,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    buckets: HashMap<String, MemoryStorage>,
+    uploads: HashMap<String, Upload>,
+    next_upload_id: u64,
+    page_size: usize,
+    failures: VecDeque<StatusCode>,
+    requests: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Upload {
+    bucket: String,
+    key: String,
+    headers: Vec<(String, String)>,
+    sse_customer_key: Option<SseCustomerKey>,
+    /// The body and ETag of every part, by part number.
+    parts: BTreeMap<u32, (Bytes, String)>,
+}
+
+#[derive(Debug)]
+struct Request {
+    method: Method,
+    /// The path and query, exactly as they came in.
+    target: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl Response {
+    fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    fn xml(status: StatusCode, xml: String) -> Self {
+        Self {
+            status,
+            headers: vec![("content-type".to_string(), "application/xml".to_string())],
+            body: Bytes::from(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{xml}")),
+        }
+    }
+
+    fn error(status: StatusCode, code: &str, message: &str) -> Self {
+        Self::xml(
+            status,
+            format!(
+                "<Error><Code>{code}</Code><Message>{}</Message></Error>",
+                escape_xml(message)
+            ),
+        )
+    }
+
+    /// What the storage failed with, which is what R2 would answer with already.
+    fn from_storage(error: R2Error) -> Self {
+        match error {
+            R2Error::FailedRequest(_, status, body) => Self::xml(status, body),
+            error => Self::error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                &error.to_string(),
+            ),
+        }
+    }
+
+    fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_owned(), value.into()));
+        self
+    }
+}
+
+fn serve(listener: TcpListener, state: Arc<State>) {
+    for stream in listener.incoming() {
+        if state.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let _ = handle_connection(stream, &state);
+        });
+    }
+}
+
+/// Answers one request and closes the connection, which keeps the server simple and is something
+/// every client has to cope with anyway.
+fn handle_connection(stream: TcpStream, state: &State) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let Some(request) = read_request(&mut reader)? else {
+        return Ok(());
+    };
+    let head = request.method == Method::HEAD;
+    let response = state.handle(request);
+    write_response(&mut &stream, &response, head)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut request_line = line.split_whitespace();
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(invalid_data("malformed request line"));
+    };
+    let method =
+        Method::from_bytes(method.as_bytes()).map_err(|_| invalid_data("invalid method"))?;
+    let target = target.to_owned();
+
+    let mut headers = HeaderMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| invalid_data("malformed header"))?;
+        headers.append(
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| invalid_data("invalid header name"))?,
+            HeaderValue::from_bytes(value.trim().as_bytes())
+                .map_err(|_| invalid_data("invalid header value"))?,
+        );
+    }
+
+    let chunked = headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"chunked"));
+    let body = if chunked {
+        read_chunked(reader)?
+    } else {
+        let length = headers
+            .get("content-length")
+            .and_then(|length| length.to_str().ok()?.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        body
+    };
+    Ok(Some(Request {
+        method,
+        target,
+        headers,
+        body: body.into(),
+    }))
+}
+
+fn read_chunked(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.trim().split(';').next().unwrap_or_default();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| invalid_data("invalid chunk size"))?;
+        if size == 0 {
+            // Trailers, up to the empty line that ends the body
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+fn write_response(stream: &mut impl Write, response: &Response, head: bool) -> io::Result<()> {
+    let status = response.status;
+    let mut head_lines = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    );
+    for (name, value) in &response.headers {
+        head_lines.push_str(&format!("{name}: {value}\r\n"));
+    }
+    // HEAD responses say how long the body would've been
+    if !response
+        .headers
+        .iter()
+        .any(|(name, _)| name == "content-length")
+    {
+        head_lines.push_str(&format!("content-length: {}\r\n", response.body.len()));
+    }
+    head_lines.push_str("connection: close\r\n\r\n");
+    stream.write_all(head_lines.as_bytes())?;
+    if !head {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+impl State {
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn handle(&self, request: Request) -> Response {
+        let mut inner = self.inner();
+        inner
+            .requests
+            .push(format!("{} {}", request.method, request.target));
+        if let Some(status) = inner.failures.pop_front() {
+            let code: String = status
+                .canonical_reason()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect();
+            return Response::error(status, &code, "Failing on purpose");
+        }
+        let (path, query) = request
+            .target
+            .split_once('?')
+            .unwrap_or((&request.target, ""));
+        let query = Query::parse(query);
+        if let Err(response) = self.authenticate(&request, &query) {
+            return response;
+        }
+
+        let path = decode(path.trim_start_matches('/'));
+        let (bucket, key) = path.split_once('/').unwrap_or((&path, ""));
+        match (&request.method, key) {
+            (_, _) if bucket.is_empty() => Response::error(
+                StatusCode::NOT_IMPLEMENTED,
+                "NotImplemented",
+                "Listing buckets isn't supported",
+            ),
+            (&Method::GET, "") => inner.list(bucket, &query),
+            (_, "") => Response::error(
+                StatusCode::NOT_IMPLEMENTED,
+                "NotImplemented",
+                "Only listing is supported on buckets",
+            ),
+            (&Method::PUT, _) if query.get("uploadId").is_some() => {
+                inner.upload_part(bucket, key, &request, &query)
+            }
+            (&Method::PUT, _) if request.headers.contains_key("x-amz-copy-source") => {
+                inner.copy(bucket, key, &request)
+            }
+            (&Method::PUT, _) => inner.put(bucket, key, &request),
+            (&Method::POST, _) if query.get("uploads").is_some() => {
+                inner.create_upload(bucket, key, &request)
+            }
+            (&Method::POST, _) if query.get("uploadId").is_some() => {
+                inner.complete_upload(bucket, key, &request, &query)
+            }
+            (&Method::DELETE, _) if query.get("uploadId").is_some() => {
+                inner.abort_upload(bucket, key, &query)
+            }
+            (&Method::DELETE, _) => {
+                inner.bucket(bucket).delete(key);
+                Response::new(StatusCode::NO_CONTENT)
+            }
+            (&Method::GET | &Method::HEAD, _) => inner.get(bucket, key, &request, &query),
+            _ => Response::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "MethodNotAllowed",
+                "The specified method is not allowed against this resource.",
+            ),
+        }
+    }
+
+    /// Checks the request's signature (or presigned URL), and that the body is what was signed.
+    fn authenticate(&self, request: &Request, query: &Query) -> Result<(), Response> {
+        let host = request.header("host").unwrap_or("localhost");
+        let uri: http::Uri = format!("http://{host}{}", request.target)
+            .parse()
+            .map_err(|_| {
+                Response::error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidURI",
+                    "Couldn't parse the URI",
+                )
+            })?;
+        let presigned = query.get("X-Amz-Signature").is_some();
+        let verified = if presigned {
+            self.signer.verify_presigned(&request.method, &uri)
+        } else {
+            self.signer.verify(&request.method, &uri, &request.headers)
+        };
+        verified.map_err(|message| {
+            Response::error(StatusCode::FORBIDDEN, "SignatureDoesNotMatch", &message)
+        })?;
+        if presigned {
+            return Ok(());
+        }
+        match request.header("x-amz-content-sha256") {
+            Some("UNSIGNED-PAYLOAD") => Ok(()),
+            Some(hash) if hash == aws_sigv4::hash(&request.body) => Ok(()),
+            _ => Err(Response::error(
+                StatusCode::BAD_REQUEST,
+                "XAmzContentSHA256Mismatch",
+                "The provided 'x-amz-content-sha256' header does not match what was computed.",
+            )),
+        }
+    }
+}
+
+impl Inner {
+    /// Buckets come into existence as soon as they're used.
+    fn bucket(&mut self, bucket: &str) -> MemoryStorage {
+        self.buckets.entry(bucket.to_owned()).or_default().clone()
+    }
+
+    fn put(&mut self, bucket: &str, key: &str, request: &Request) -> Response {
+        let storage = self.bucket(bucket);
+        let existing = storage
+            .stored(key)
+            .map(|existing| existing.metadata.etag.unwrap_or_default());
+        let precondition_failed = match (request.header("if-none-match"), &existing) {
+            (Some("*"), Some(_)) => true,
+            _ => match request.header("if-match") {
+                Some(etag) => existing
+                    .as_ref()
+                    .is_none_or(|existing| !etag_matches(etag, existing)),
+                None => false,
+            },
+        };
+        if precondition_failed {
+            return precondition_failed_response();
+        }
+        let checksum = match verify_checksums(request) {
+            Ok(checksum) => checksum,
+            Err(response) => return response,
+        };
+        let sse_customer_key = match sse_customer_key(&request.headers, SSE_CUSTOMER) {
+            Ok(key) => key,
+            Err(response) => return response,
+        };
+        let etag = etag(&request.body);
+        let mut headers = object_headers(&request.headers);
+        headers.extend(checksum.clone());
+        headers.extend(stored_headers(&etag));
+        let object = match StoredObject::new(request.body.clone(), headers, sse_customer_key) {
+            Ok(object) => object,
+            Err(e) => return Response::from_storage(e),
+        };
+        storage.insert(key, object);
+        let mut response = Response::new(StatusCode::OK).with_header("etag", etag);
+        response.headers.extend(checksum);
+        response
+    }
+
+    fn get(&mut self, bucket: &str, key: &str, request: &Request, query: &Query) -> Response {
+        let sse_customer_key = match sse_customer_key(&request.headers, SSE_CUSTOMER) {
+            Ok(key) => key,
+            Err(response) => return response,
+        };
+        let object = match self
+            .bucket(bucket)
+            .object(key, sse_customer_key.as_ref(), || {
+                format!("{} {key}", request.method)
+            }) {
+            Ok(object) => object,
+            Err(e) => return Response::from_storage(e),
+        };
+        let etag = object.metadata.etag.as_deref().unwrap_or_default();
+
+        let modified_since = |name: &str| {
+            let since = httpdate::parse_http_date(request.header(name)?).ok()?;
+            Some(object.metadata.last_modified? > since)
+        };
+        if request
+            .header("if-match")
+            .is_some_and(|condition| !etag_matches(condition, etag))
+            || modified_since("if-unmodified-since") == Some(true)
+        {
+            return precondition_failed_response();
+        }
+        let not_modified = match request.header("if-none-match") {
+            Some(condition) => etag_matches(condition, etag),
+            None => modified_since("if-modified-since") == Some(false),
+        };
+        if not_modified {
+            return Response::new(StatusCode::NOT_MODIFIED).with_header("etag", etag);
+        }
+
+        // Checksums are only sent back when they're asked for
+        let checksum_mode = request.header("x-amz-checksum-mode") == Some("ENABLED");
+        let mut response = Response::new(StatusCode::OK).with_header("accept-ranges", "bytes");
+        response.headers.extend(
+            object
+                .headers
+                .iter()
+                .filter(|(name, _)| checksum_mode || !name.starts_with("x-amz-checksum-"))
+                .cloned(),
+        );
+        for (override_param, header) in [
+            ("response-cache-control", "cache-control"),
+            ("response-content-disposition", "content-disposition"),
+            ("response-content-encoding", "content-encoding"),
+            ("response-content-language", "content-language"),
+            ("response-content-type", "content-type"),
+            ("response-expires", "expires"),
+        ] {
+            if let Some(value) = query.get(override_param) {
+                response.headers.retain(|(name, _)| name != header);
+                response.headers.push((header.to_owned(), value.to_owned()));
+            }
+        }
+
+        let size = object.body.len();
+        let (status, body) = match request.header("range") {
+            Some(range) => match parse_range(range, size) {
+                Some((start, end)) => {
+                    response.headers.push((
+                        "content-range".to_string(),
+                        format!("bytes {start}-{end}/{size}"),
+                    ));
+                    (
+                        StatusCode::PARTIAL_CONTENT,
+                        object.body.slice(start..end + 1),
+                    )
+                }
+                None => {
+                    return Response::error(
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        "InvalidRange",
+                        "The requested range is not satisfiable",
+                    )
+                    .with_header("content-range", format!("bytes */{size}"));
+                }
+            },
+            None => (StatusCode::OK, object.body.clone()),
+        };
+        response.status = status;
+        response
+            .headers
+            .push(("content-length".to_string(), body.len().to_string()));
+        response.body = body;
+        response
+    }
+
+    fn copy(&mut self, bucket: &str, key: &str, request: &Request) -> Response {
+        let source = decode(
+            request
+                .header("x-amz-copy-source")
+                .unwrap_or_default()
+                .trim_start_matches('/'),
+        );
+        let Some((source_bucket, source_key)) = source.split_once('/') else {
+            return Response::error(
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                "Copy Source must mention the source bucket and key: sourcebucket/sourcekey",
+            );
+        };
+        let (source_sse_customer_key, sse_customer_key) = match (
+            sse_customer_key(&request.headers, COPY_SOURCE_SSE_CUSTOMER),
+            sse_customer_key(&request.headers, SSE_CUSTOMER),
+        ) {
+            (Ok(source_key), Ok(key)) => (source_key, key),
+            (Err(response), _) | (_, Err(response)) => return response,
+        };
+        let headers = match request.header("x-amz-metadata-directive") {
+            Some("REPLACE") => Some(object_headers(&request.headers)),
+            _ => None,
+        };
+        let copied = self
+            .bucket(source_bucket)
+            .object(source_key, source_sse_customer_key.as_ref(), || {
+                format!("copying {source_bucket}/{source_key}")
+            })
+            .and_then(|source| source.copy(headers, sse_customer_key));
+        let object = match copied {
+            Ok(object) => object,
+            Err(e) => return Response::from_storage(e),
+        };
+        let response = Response::xml(
+            StatusCode::OK,
+            format!(
+                "<CopyObjectResult><LastModified>{}</LastModified><ETag>{}</ETag></CopyObjectResult>",
+                object
+                    .metadata
+                    .last_modified
+                    .map(format_timestamp)
+                    .unwrap_or_default(),
+                escape_xml(object.metadata.etag.as_deref().unwrap_or_default())
+            ),
+        );
+        self.bucket(bucket).insert(key, object);
+        response
+    }
+
+    fn list(&mut self, bucket: &str, query: &Query) -> Response {
+        let prefix = query.get("prefix").unwrap_or_default();
+        let delimiter = query.get("delimiter").unwrap_or_default();
+        let page_size = query
+            .get("max-keys")
+            .and_then(|max_keys| max_keys.parse().ok())
+            .map_or(self.page_size, |max_keys: usize| {
+                max_keys.min(self.page_size)
+            });
+        let after = query.get("continuation-token").or(query.get("start-after"));
+
+        let listing = roll_up(self.bucket(bucket).list(prefix), prefix, delimiter);
+        // Objects and common prefixes share the pages, in key order
+        let mut entries: Vec<(&str, String)> = listing
+            .objects
+            .iter()
+            .map(|object| {
+                let last_modified = object.last_modified.map(format_timestamp);
+                (
+                    object.key.as_str(),
+                    format!(
+                        "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                        escape_xml(&object.key),
+                        last_modified.unwrap_or_default(),
+                        escape_xml(object.etag.as_deref().unwrap_or_default()),
+                        object.size
+                    ),
+                )
+            })
+            .chain(listing.common_prefixes.iter().map(|common_prefix| {
+                (
+                    common_prefix.as_str(),
+                    format!(
+                        "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                        escape_xml(common_prefix)
+                    ),
+                )
+            }))
+            .filter(|(name, _)| after.is_none_or(|after| *name > after))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let truncated = entries.len() > page_size;
+        entries.truncate(page_size);
+
+        let mut xml = format!(
+            "<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>{}</Name><Prefix>{}</Prefix><MaxKeys>{page_size}</MaxKeys><KeyCount>{}</KeyCount><IsTruncated>{truncated}</IsTruncated>",
+            escape_xml(bucket),
+            escape_xml(prefix),
+            entries.len()
+        );
+        if !delimiter.is_empty() {
+            xml.push_str(&format!("<Delimiter>{}</Delimiter>", escape_xml(delimiter)));
+        }
+        if truncated && let Some((last, _)) = entries.last() {
+            xml.push_str(&format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                escape_xml(last)
+            ));
+        }
+        for (_, entry) in &entries {
+            xml.push_str(entry);
+        }
+        xml.push_str("</ListBucketResult>");
+        Response::xml(StatusCode::OK, xml)
+    }
+
+    fn create_upload(&mut self, bucket: &str, key: &str, request: &Request) -> Response {
+        let sse_customer_key = match sse_customer_key(&request.headers, SSE_CUSTOMER) {
+            Ok(key) => key,
+            Err(response) => return response,
+        };
+        self.next_upload_id += 1;
+        let upload_id = self.next_upload_id.to_string();
+        self.uploads.insert(
+            upload_id.clone(),
+            Upload {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                headers: object_headers(&request.headers),
+                sse_customer_key,
+                parts: BTreeMap::new(),
+            },
+        );
+        Response::xml(
+            StatusCode::OK,
+            format!(
+                "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>{upload_id}</UploadId></InitiateMultipartUploadResult>",
+                escape_xml(bucket),
+                escape_xml(key)
+            ),
+        )
+    }
+
+    /// The upload `uploadId` names, as long as it's for this object.
+    fn upload(
+        &mut self,
+        bucket: &str,
+        key: &str,
+        query: &Query,
+    ) -> Result<(String, &mut Upload), Response> {
+        let upload_id = query.get("uploadId").unwrap_or_default().to_owned();
+        match self.uploads.get_mut(&upload_id) {
+            Some(upload) if upload.bucket == bucket && upload.key == key => Ok((upload_id, upload)),
+            _ => Err(Response::error(
+                StatusCode::NOT_FOUND,
+                "NoSuchUpload",
+                "The specified multipart upload does not exist.",
+            )),
+        }
+    }
+
+    fn upload_part(
+        &mut self,
+        bucket: &str,
+        key: &str,
+        request: &Request,
+        query: &Query,
+    ) -> Response {
+        let part_number = match query
+            .get("partNumber")
+            .and_then(|number| number.parse().ok())
+        {
+            Some(part_number @ 1..=10_000) => part_number,
+            _ => {
+                return Response::error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    "Part number must be an integer between 1 and 10000, inclusive",
+                );
+            }
+        };
+        if let Err(response) = verify_checksums(request) {
+            return response;
+        }
+        let sse_customer_key = sse_customer_key(&request.headers, SSE_CUSTOMER);
+        let upload = match self.upload(bucket, key, query) {
+            Ok((_, upload)) => upload,
+            Err(response) => return response,
+        };
+        match sse_customer_key {
+            Ok(key) if key == upload.sse_customer_key => {}
+            Ok(_) => {
+                return Response::from_storage(wrong_sse_customer_key(format!(
+                    "uploading part {part_number} of {key}"
+                )));
+            }
+            Err(response) => return response,
+        }
+        let etag = etag(&request.body);
+        upload
+            .parts
+            .insert(part_number, (request.body.clone(), etag.clone()));
+        Response::new(StatusCode::OK).with_header("etag", etag)
+    }
+
+    fn complete_upload(
+        &mut self,
+        bucket: &str,
+        key: &str,
+        request: &Request,
+        query: &Query,
+    ) -> Response {
+        let (upload_id, upload) = match self.upload(bucket, key, query) {
+            Ok(upload) => upload,
+            Err(response) => return response,
+        };
+        let Some(requested) = parse_completed_parts(&request.body) else {
+            return Response::error(
+                StatusCode::BAD_REQUEST,
+                "MalformedXML",
+                "The XML you provided was not well-formed or did not validate against our published schema.",
+            );
+        };
+        if !requested.is_sorted_by(|a, b| a.0 < b.0) {
+            return Response::error(
+                StatusCode::BAD_REQUEST,
+                "InvalidPartOrder",
+                "The list of parts was not in ascending order.",
+            );
+        }
+        let mut body = Vec::new();
+        let mut part_md5s = Vec::new();
+        for (index, (part_number, etag)) in requested.iter().enumerate() {
+            let Some((part, part_etag)) = upload
+                .parts
+                .get(part_number)
+                .filter(|(_, part_etag)| etag_matches(etag, part_etag))
+            else {
+                return Response::error(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidPart",
+                    "One or more of the specified parts could not be found.",
+                );
+            };
+            if index + 1 < requested.len() && (part.len() as u64) < MIN_PART_SIZE {
+                return Response::error(
+                    StatusCode::BAD_REQUEST,
+                    "EntityTooSmall",
+                    "Your proposed upload is smaller than the minimum allowed object size.",
+                );
+            }
+            body.extend_from_slice(part);
+            part_md5s.extend(hex_to_bytes(part_etag.trim_matches('"')));
+        }
+        let Some(upload) = self.uploads.remove(&upload_id) else {
+            unreachable!("the upload was just looked up");
+        };
+        let etag = format!("\"{:x}-{}\"", Md5::digest(&part_md5s), requested.len());
+        let mut headers = upload.headers;
+        headers.extend(stored_headers(&etag));
+        let object = match StoredObject::new(body.into(), headers, upload.sse_customer_key) {
+            Ok(object) => object,
+            Err(e) => return Response::from_storage(e),
+        };
+        self.bucket(bucket).insert(key, object);
+        Response::xml(
+            StatusCode::OK,
+            format!(
+                "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+                escape_xml(bucket),
+                escape_xml(key),
+                escape_xml(&etag)
+            ),
+        )
+    }
+
+    fn abort_upload(&mut self, bucket: &str, key: &str, query: &Query) -> Response {
+        match self.upload(bucket, key, query) {
+            Ok((upload_id, _)) => {
+                self.uploads.remove(&upload_id);
+                Response::new(StatusCode::NO_CONTENT)
+            }
+            Err(response) => response,
+        }
+    }
+}
+
+fn precondition_failed_response() -> Response {
+    Response::error(
+        StatusCode::PRECONDITION_FAILED,
+        "PreconditionFailed",
+        "At least one of the pre-conditions you specified did not hold",
+    )
+}
+
+/// The headers every object is stored with on top of the ones it was uploaded with.
+fn stored_headers(etag: &str) -> [(String, String); 2] {
+    [
+        ("etag".to_string(), etag.to_owned()),
+        ("last-modified".to_string(), httpdate::fmt_http_date(now())),
+    ]
+}
+
+/// Whether an `If-Match`/`If-None-Match` value (a list of ETags, or `*`) matches `etag`.
+fn etag_matches(condition: &str, etag: &str) -> bool {
+    condition.split(',').any(|candidate| {
+        let candidate = candidate.trim().trim_start_matches("W/");
+        candidate == "*" || candidate.trim_matches('"') == etag.trim_matches('"')
+    })
+}
+
+/// What an upload was sent with that describes the object.
+fn object_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    let mut object_headers: Vec<_> = headers
+        .iter()
+        .filter(|(name, _)| {
+            matches!(
+                name.as_str(),
+                "cache-control"
+                    | "content-disposition"
+                    | "content-encoding"
+                    | "content-language"
+                    | "content-type"
+                    | "expires"
+            ) || name.as_str().starts_with("x-amz-meta-")
+        })
+        .filter_map(|(name, value)| {
+            Some((name.as_str().to_owned(), value.to_str().ok()?.to_owned()))
+        })
+        .collect();
+    if !headers.contains_key("content-type") {
+        object_headers.push((
+            "content-type".to_string(),
+            "application/octet-stream".to_string(),
+        ));
+    }
+    object_headers
+}
+
+/// Checks the body against every checksum header that came with it, returning the
+/// `x-amz-checksum-*` one to keep.
+fn verify_checksums(request: &Request) -> Result<Option<(String, String)>, Response> {
+    let mut kept = None;
+    for algorithm in [
+        ChecksumAlgorithm::ContentMd5,
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::Sha256,
+    ] {
+        let Some(expected) = request.header(algorithm.header_name()) else {
+            continue;
+        };
+        if algorithm.compute(&request.body).value != expected {
+            return Err(Response::error(
+                StatusCode::BAD_REQUEST,
+                "BadDigest",
+                &format!(
+                    "The {} you specified did not match what we received.",
+                    algorithm.header_name()
+                ),
+            ));
+        }
+        if algorithm != ChecksumAlgorithm::ContentMd5 {
+            kept = Some((algorithm.header_name().to_owned(), expected.to_owned()));
+        }
+    }
+    Ok(kept)
+}
+
+/// The SSE-C key in the headers starting with `prefix`, after checking they're consistent, or
+/// `None` if there aren't any.
+fn sse_customer_key(headers: &HeaderMap, prefix: &str) -> Result<Option<SseCustomerKey>, Response> {
+    let header = |suffix: &str| {
+        headers
+            .get(format!("{prefix}-{suffix}"))
+            .and_then(|value| value.to_str().ok())
+    };
+    let invalid =
+        |message: &str| Response::error(StatusCode::BAD_REQUEST, "InvalidArgument", message);
+    let Some(key) = header("key") else {
+        return match header("algorithm") {
+            Some(_) => Err(invalid(
+                "Requests specifying Server Side Encryption with Customer provided keys must provide an appropriate secret key.",
+            )),
+            None => Ok(None),
+        };
+    };
+    if header("algorithm") != Some("AES256") {
+        return Err(invalid("The encryption algorithm specified is not valid."));
+    }
+    let key = SseCustomerKey::from_base64(key)
+        .map_err(|_| invalid("The secret key was invalid for the specified algorithm."))?;
+    if header("key-md5") != Some(key.key_md5().as_str()) {
+        return Err(invalid(
+            "The calculated MD5 hash of the key did not match the hash that was provided.",
+        ));
+    }
+    Ok(Some(key))
+}
+
+/// The first and last byte `range` asks for out of `size`, `None` if it can't be satisfied.
+fn parse_range(range: &str, size: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.parse::<usize>().ok(), end.parse::<usize>().ok()) {
+        (Some(start), Some(end)) => (start, end.min(size.checked_sub(1)?)),
+        (Some(start), None) if end.is_empty() => (start, size.checked_sub(1)?),
+        (None, Some(suffix)) if start.is_empty() && suffix > 0 => {
+            (size.saturating_sub(suffix), size.checked_sub(1)?)
+        }
+        _ => return None,
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// The part numbers and ETags of a `<CompleteMultipartUpload>`, in the order they were given.
+fn parse_completed_parts(xml: &[u8]) -> Option<Vec<(u32, String)>> {
+    let root = xmltree::Element::parse(xml).ok()?;
+    let parts = root
+        .children
+        .iter()
+        .filter_map(|child| child.as_element())
+        .filter(|element| element.name == "Part")
+        .map(|part| {
+            let text = |name: &str| {
+                part.get_child(name)?
+                    .get_text()
+                    .map(|text| text.into_owned())
+            };
+            Some((text("PartNumber")?.trim().parse().ok()?, text("ETag")?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!parts.is_empty()).then_some(parts)
+}
+
+fn hex_to_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2)
+        .filter_map(|index| u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+/// A request's decoded query parameters.
+#[derive(Debug)]
+struct Query(Vec<(String, String)>);
+
+impl Query {
+    fn parse(query: &str) -> Self {
+        Self(
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (decode(name), decode(value))
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn decode(encoded: &str) -> String {
+    urlencoding::decode(encoded)
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or_else(|_| encoded.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, target: &str, headers: &[(&str, &str)], body: &str) -> Request {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        Request {
+            method,
+            target: target.to_owned(),
+            headers: header_map,
+            body: Bytes::from(body.to_owned()),
+        }
+    }
+
+    #[test]
+    fn reading_requests() {
+        let raw = "PUT /bucket/a%20key?x-id=PutObject HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        let request = read_request(&mut raw.as_bytes()).unwrap().unwrap();
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(request.target, "/bucket/a%20key?x-id=PutObject");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.body, "hello");
+
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n";
+        let request = read_request(&mut chunked.as_bytes()).unwrap().unwrap();
+        assert_eq!(request.body, "hello world");
+        assert!(read_request(&mut "".as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn unsigned_requests_are_refused() {
+        let state = State::default();
+        let response = state.handle(request(
+            Method::GET,
+            "/bucket/key",
+            &[("host", "localhost")],
+            "",
+        ));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert!(String::from_utf8_lossy(&response.body).contains("SignatureDoesNotMatch"));
+        assert_eq!(state.inner().requests, ["GET /bucket/key"]);
+    }
+
+    #[test]
+    fn injected_failures() {
+        let state = State::default();
+        state
+            .inner()
+            .failures
+            .push_back(StatusCode::SERVICE_UNAVAILABLE);
+        let response = state.handle(request(Method::GET, "/bucket/key", &[], ""));
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(
+            String::from_utf8_lossy(&response.body).contains("<Code>ServiceUnavailable</Code>")
+        );
+        let response = state.handle(request(Method::GET, "/bucket/key", &[], ""));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some((0, 4)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 9)));
+        assert_eq!(parse_range("bytes=-3", 10), Some((7, 9)));
+        assert_eq!(parse_range("bytes=8-100", 10), Some((8, 9)));
+        assert_eq!(parse_range("bytes=10-", 10), None);
+        assert_eq!(parse_range("bytes=0-0", 0), None);
+        assert_eq!(parse_range("lines=1-2", 10), None);
+    }
+
+    #[test]
+    fn conditions() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("abc", "\"abc\""));
+        assert!(etag_matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"x\"", "\"abc\""));
+    }
+
+    #[test]
+    fn completed_parts() {
+        let body = crate::multipart::complete_body(&[
+            crate::CompletedPart {
+                part_number: 1,
+                etag: "\"a\"".to_string(),
+            },
+            crate::CompletedPart {
+                part_number: 2,
+                etag: "\"b\"".to_string(),
+            },
+        ]);
+        assert_eq!(
+            parse_completed_parts(body.as_bytes()),
+            Some(vec![(1, "\"a\"".to_string()), (2, "\"b\"".to_string())])
+        );
+        assert_eq!(
+            parse_completed_parts(b"<CompleteMultipartUpload></CompleteMultipartUpload>"),
+            None
+        );
+    }
+
+    #[test]
+    fn sse_customer_headers() {
+        let key = SseCustomerKey::new([1; 32]);
+        let mut headers = HeaderMap::new();
+        assert!(matches!(sse_customer_key(&headers, SSE_CUSTOMER), Ok(None)));
+        for (name, value) in key.headers() {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(&value).unwrap(),
+            );
+        }
+        assert_eq!(
+            sse_customer_key(&headers, SSE_CUSTOMER).ok(),
+            Some(Some(key))
+        );
+        headers.insert(
+            "x-amz-server-side-encryption-customer-key-md5",
+            HeaderValue::from_static("wrong"),
+        );
+        assert!(sse_customer_key(&headers, SSE_CUSTOMER).is_err());
+    }
+}
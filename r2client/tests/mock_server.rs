@@ -0,0 +1,693 @@
+//! End to end tests against the bundled [`MockServer`](r2client::test_support::MockServer), no
+//! credentials or network needed.
+
+use r2client::test_support::MockServer;
+
+#[cfg(feature = "async")]
+mod async_tests {
+    use http::StatusCode;
+    use r2client::test_support::MockServer;
+    use r2client::test_support::TempDir;
+    use r2client::{
+        ChecksumAlgorithm, CopyOptions, DirOptions, Fixtures, GetOptions, MIN_PART_SIZE,
+        MirrorOptions, PutOptions, R2Client, R2Error, RetryPolicy, SseCustomerKey,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn objects_roundtrip() {
+        let server = MockServer::start();
+        let client = server.client();
+        let options = PutOptions {
+            content_type: Some("text/plain".to_string()),
+            cache_control: Some("max-age=60".to_string()),
+            metadata: [("author".to_string(), "me".to_string())].into(),
+            ..Default::default()
+        };
+        let output = client
+            .put_object("bucket", "dir/hello world.txt", "hello", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            server.object("bucket", "dir/hello world.txt").unwrap(),
+            "hello"
+        );
+
+        let object = client
+            .get_object("bucket", "dir/hello world.txt", &GetOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(object.body, "hello");
+        assert_eq!(object.metadata.etag, output.etag);
+        assert_eq!(object.metadata.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(object.metadata.cache_control.as_deref(), Some("max-age=60"));
+        assert_eq!(object.metadata.metadata["author"], "me");
+
+        let metadata = client
+            .head_object("bucket", "dir/hello world.txt")
+            .await
+            .unwrap();
+        assert_eq!(metadata.content_length, Some(5));
+        assert_eq!(metadata.etag, output.etag);
+
+        client
+            .delete("bucket", "dir/hello world.txt")
+            .await
+            .unwrap();
+        assert!(server.keys("bucket").is_empty());
+        let error = client
+            .get_object("bucket", "dir/hello world.txt", &GetOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, R2Error::FailedRequest(_, StatusCode::NOT_FOUND, _)),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn listing() {
+        let server = MockServer::start();
+        server.set_page_size(2);
+        let client = server.client();
+        for key in ["a/1", "a/2", "a/b/3", "a/c/4", "b/5"] {
+            client
+                .put_object("bucket", key, key.to_string(), &PutOptions::default())
+                .await
+                .unwrap();
+        }
+
+        let objects = client.list_objects("bucket", "a/").await.unwrap();
+        let keys: Vec<_> = objects.iter().map(|object| object.key.as_str()).collect();
+        assert_eq!(keys, ["a/1", "a/2", "a/b/3", "a/c/4"]);
+        assert_eq!(objects[2].size, 5);
+        let pages = server
+            .requests()
+            .iter()
+            .filter(|request| request.starts_with("GET /bucket/?list-type=2"))
+            .count();
+        assert_eq!(pages, 2);
+
+        let listing = client
+            .list_objects_delimited("bucket", "a/", "/")
+            .await
+            .unwrap();
+        let keys: Vec<_> = listing
+            .objects
+            .iter()
+            .map(|object| object.key.as_str())
+            .collect();
+        assert_eq!(keys, ["a/1", "a/2"]);
+        assert_eq!(listing.common_prefixes, ["a/b/", "a/c/"]);
+    }
+
+    #[tokio::test]
+    async fn multipart_uploads() {
+        let server = MockServer::start();
+        let client = server.client();
+        let dir = TempDir::new("multipart");
+        let path = dir.file("big.bin");
+        let body: Vec<u8> = (0..MIN_PART_SIZE * 2 + 10).map(|byte| byte as u8).collect();
+        std::fs::write(&path, &body).unwrap();
+
+        let output = client
+            .upload_file_multipart(
+                "bucket",
+                &path,
+                "big.bin",
+                MIN_PART_SIZE,
+                &PutOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert!(output.etag.unwrap().ends_with("-3\""));
+        assert_eq!(server.object("bucket", "big.bin").unwrap(), body);
+        assert_eq!(server.uploads_in_progress(), 0);
+
+        // Parts that are too small are only noticed when completing
+        let upload = client
+            .create_multipart_upload("bucket", "small.bin", &PutOptions::default())
+            .await
+            .unwrap();
+        let parts = vec![
+            client
+                .upload_part(&upload, 1, "tiny", &PutOptions::default())
+                .await
+                .unwrap(),
+            client
+                .upload_part(&upload, 2, "tiny", &PutOptions::default())
+                .await
+                .unwrap(),
+        ];
+        let error = client
+            .complete_multipart_upload(&upload, &parts)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("EntityTooSmall"), "{error}");
+        client.abort_multipart_upload(&upload).await.unwrap();
+        assert_eq!(server.uploads_in_progress(), 0);
+    }
+
+    #[tokio::test]
+    async fn copying() {
+        let server = MockServer::start();
+        let client = server.client();
+        let options = PutOptions {
+            content_type: Some("text/csv".to_string()),
+            ..Default::default()
+        };
+        client
+            .put_object("source", "data.csv", "a,b", &options)
+            .await
+            .unwrap();
+        client
+            .copy_object(
+                "source",
+                "data.csv",
+                "destination",
+                "copy.csv",
+                &CopyOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(server.object("destination", "copy.csv").unwrap(), "a,b");
+        let metadata = client.head_object("destination", "copy.csv").await.unwrap();
+        assert_eq!(metadata.content_type.as_deref(), Some("text/csv"));
+    }
+
+    #[tokio::test]
+    async fn customer_keys() {
+        let server = MockServer::start();
+        let client = server.client();
+        let key = SseCustomerKey::new([7; 32]);
+        let options = PutOptions {
+            sse_customer_key: Some(key.clone()),
+            ..Default::default()
+        };
+        client
+            .put_object("bucket", "secret", "shh", &options)
+            .await
+            .unwrap();
+
+        let get = |key| GetOptions {
+            sse_customer_key: key,
+            ..Default::default()
+        };
+        let object = client
+            .get_object("bucket", "secret", &get(Some(key)))
+            .await
+            .unwrap();
+        assert_eq!(object.body, "shh");
+        for wrong_key in [None, Some(SseCustomerKey::new([8; 32]))] {
+            let error = client
+                .get_object("bucket", "secret", &get(wrong_key))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(error, R2Error::FailedRequest(_, StatusCode::BAD_REQUEST, _)),
+                "{error}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn checksums() {
+        let server = MockServer::start();
+        let client = server.client();
+        for algorithm in [
+            ChecksumAlgorithm::ContentMd5,
+            ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Crc32c,
+            ChecksumAlgorithm::Sha256,
+        ] {
+            let options = PutOptions {
+                checksum: Some(algorithm),
+                ..Default::default()
+            };
+            let output = client
+                .put_object("bucket", "checked", "some data", &options)
+                .await
+                .unwrap();
+            assert_eq!(output.checksum.unwrap().algorithm, algorithm);
+
+            let options = GetOptions {
+                verify_integrity: true,
+                ..Default::default()
+            };
+            let object = client
+                .get_object("bucket", "checked", &options)
+                .await
+                .unwrap();
+            assert_eq!(object.body, "some data");
+        }
+    }
+
+    #[tokio::test]
+    async fn presigned_urls() {
+        let server = MockServer::start();
+        let client = server.client();
+        client
+            .put_object("bucket", "shared.txt", "for anyone", &PutOptions::default())
+            .await
+            .unwrap();
+        let options = GetOptions {
+            response_content_disposition: Some("attachment".to_string()),
+            ..Default::default()
+        };
+        let url = client
+            .presigned_get_url("bucket", "shared.txt", Duration::from_secs(60), &options)
+            .unwrap();
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-disposition"], "attachment");
+        assert_eq!(response.text().await.unwrap(), "for anyone");
+
+        let tampered = url.replace("shared.txt", "other.txt");
+        let response = reqwest::get(&tampered).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn wrong_credentials() {
+        let server = MockServer::start();
+        let client = R2Client::from_credentials(
+            MockServer::ACCESS_KEY.to_string(),
+            "not-the-secret".to_string(),
+            server.endpoint().to_string(),
+        )
+        .unwrap();
+        let error = client
+            .put_object("bucket", "key", "body", &PutOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, R2Error::FailedRequest(_, StatusCode::FORBIDDEN, _)),
+            "{error}"
+        );
+        assert!(error.to_string().contains("SignatureDoesNotMatch"));
+        assert!(server.keys("bucket").is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries() {
+        let server = MockServer::start();
+        let client = server.client().with_retry_policy(RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        });
+        server.fail_next(2, StatusCode::SERVICE_UNAVAILABLE);
+        client
+            .put_object("bucket", "key", "body", &PutOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(server.requests().len(), 3);
+
+        server.fail_next(3, StatusCode::SERVICE_UNAVAILABLE);
+        let error = client
+            .put_object("bucket", "key", "body", &PutOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                R2Error::FailedRequest(_, StatusCode::SERVICE_UNAVAILABLE, _)
+            ),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn directories() {
+        let server = MockServer::start();
+        let client = server.client();
+        let dir = TempDir::new("directories");
+        std::fs::create_dir_all(dir.file("upload/nested")).unwrap();
+        std::fs::write(dir.file("upload/top.txt"), "top").unwrap();
+        std::fs::write(dir.file("upload/nested/deep.txt"), "deep").unwrap();
+
+        let report = client
+            .upload_dir(
+                "bucket",
+                &dir.file("upload"),
+                "backup/",
+                &DirOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        assert_eq!(
+            server.keys("bucket"),
+            ["backup/nested/deep.txt", "backup/top.txt"]
+        );
+
+        let report = client
+            .download_prefix(
+                "bucket",
+                "backup/",
+                &dir.file("download"),
+                &DirOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        assert_eq!(
+            std::fs::read_to_string(dir.file("download/nested/deep.txt")).unwrap(),
+            "deep"
+        );
+    }
+
+    #[tokio::test]
+    async fn recording_and_replaying() {
+        let dir = TempDir::new("fixtures");
+        let fixture = dir.file("fixtures/exchanges.txt");
+        let key = SseCustomerKey::new([9; 32]);
+        let put = PutOptions {
+            sse_customer_key: Some(key.clone()),
+            ..Default::default()
+        };
+        let get = GetOptions {
+            sse_customer_key: Some(key),
+            ..Default::default()
+        };
+
+        let (recorded, endpoint) = {
+            let server = MockServer::start();
+            let client = server
+                .client()
+                .with_retry_policy(RetryPolicy {
+                    base_delay: Duration::from_millis(1),
+                    ..Default::default()
+                })
+                .with_fixtures(Fixtures::once(&fixture).unwrap());
+            assert!(!client.fixtures().unwrap().is_replaying());
+            client
+                .put_object("bucket", "a key", "recorded", &put)
+                .await
+                .unwrap();
+            server.fail_next(1, StatusCode::SERVICE_UNAVAILABLE);
+            let object = client.get_object("bucket", "a key", &get).await.unwrap();
+            let listing = client.list_objects("bucket", "").await.unwrap();
+            (
+                (object.body, object.metadata, listing),
+                server.endpoint().to_string(),
+            )
+        };
+
+        let contents = std::fs::read_to_string(&fixture).unwrap();
+        assert!(contents.contains("> authorization: REDACTED"));
+        assert!(contents.contains("> x-amz-server-side-encryption-customer-key: REDACTED"));
+        assert!(!contents.contains(MockServer::ACCESS_KEY));
+        assert!(contents.contains("< 503"));
+
+        // The server's gone, so everything has to come from the fixtures
+        let client = R2Client::from_credentials(
+            "other-key".to_string(),
+            "other-secret".to_string(),
+            endpoint,
+        )
+        .unwrap()
+        .with_retry_policy(RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .with_fixtures(Fixtures::once(&fixture).unwrap());
+        assert!(client.fixtures().unwrap().is_replaying());
+        client
+            .put_object("bucket", "a key", "recorded", &put)
+            .await
+            .unwrap();
+        let object = client.get_object("bucket", "a key", &get).await.unwrap();
+        let listing = client.list_objects("bucket", "").await.unwrap();
+        assert_eq!((object.body, object.metadata, listing), recorded);
+
+        let error = client.head_object("bucket", "a key").await.unwrap_err();
+        assert!(matches!(error, R2Error::FixtureMissing(_)), "{error}");
+    }
+
+    #[tokio::test]
+    async fn mirroring() {
+        let server = MockServer::start();
+        let client = server.client();
+        for key in ["one", "two"] {
+            client
+                .put_object("source", key, key.to_string(), &PutOptions::default())
+                .await
+                .unwrap();
+        }
+        let report = client
+            .mirror("source", &client, "destination", &MirrorOptions::default())
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        assert_eq!(server.keys("destination"), ["one", "two"]);
+        assert_eq!(server.object("destination", "two").unwrap(), "two");
+    }
+
+    #[cfg(feature = "object_store")]
+    #[tokio::test]
+    async fn object_store() {
+        use object_store::path::Path;
+        use object_store::{ObjectStore, PutMode};
+
+        let server = MockServer::start();
+        let store = r2client::R2Store::new("bucket".to_string(), server.client());
+        store
+            .put(&Path::from("data/file.txt"), "contents".into())
+            .await
+            .unwrap();
+        let bytes = store
+            .get(&Path::from("data/file.txt"))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(bytes, "contents");
+
+        let meta = store.head(&Path::from("data/file.txt")).await.unwrap();
+        assert_eq!(meta.size, 8);
+        assert_eq!(
+            meta.e_tag.as_deref(),
+            Some("\"98bf7d8c15784f0a3d63204441e1e2aa\"")
+        );
+
+        store
+            .copy(&Path::from("data/file.txt"), &Path::from("data/copy.txt"))
+            .await
+            .unwrap();
+        assert_eq!(
+            server.object("bucket", "data/copy.txt").unwrap(),
+            "contents"
+        );
+
+        // Creating something that's already there is the one conflict callers match on
+        let create = object_store::PutOptions {
+            mode: PutMode::Create,
+            ..Default::default()
+        };
+        let error = store
+            .put_opts(&Path::from("data/copy.txt"), "other".into(), create.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, object_store::Error::AlreadyExists { .. }));
+        store
+            .put_opts(&Path::from("data/new.txt"), "new".into(), create)
+            .await
+            .unwrap();
+        assert_eq!(
+            server.object("bucket", "data/copy.txt").unwrap(),
+            "contents"
+        );
+    }
+
+    #[cfg(feature = "object_store")]
+    #[tokio::test]
+    async fn object_store_rate_limit() {
+        use object_store::ObjectStore;
+        use object_store::path::Path;
+        use r2client::RateLimiter;
+        use std::time::{Duration, Instant};
+
+        let server = MockServer::start();
+        let (big, small) = (Path::from("data/big.bin"), Path::from("data/small.bin"));
+        // A second's worth of budget goes out at once, the 2 KB after it has to wait for 200ms
+        let limited = || {
+            let client = server.client().with_rate_limiter(RateLimiter::new(10_000));
+            r2client::R2Store::new("bucket".to_string(), client)
+        };
+
+        let store = limited();
+        store.put(&big, vec![0; 10_000].into()).await.unwrap();
+        let start = Instant::now();
+        store.put(&small, vec![0; 2_000].into()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        let store = limited();
+        let bytes = store.get(&big).await.unwrap().bytes().await.unwrap();
+        assert_eq!(bytes.len(), 10_000);
+        let start = Instant::now();
+        store.get(&small).await.unwrap().bytes().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[cfg(feature = "object_store")]
+    #[tokio::test]
+    async fn object_store_ranges() {
+        use object_store::path::Path;
+        use object_store::{GetOptions, GetRange, ObjectStore};
+
+        let server = MockServer::start();
+        let store = r2client::R2Store::new("bucket".to_string(), server.client());
+        let path = Path::from("file.txt");
+        store.put(&path, "0123456789".into()).await.unwrap();
+
+        assert_eq!(store.get_range(&path, 2..5).await.unwrap(), "234");
+        // Content-Range says which bytes came back, and how big the whole object is
+        for (range, expected, bytes) in [
+            (GetRange::Bounded(2..5), 2..5, "234"),
+            (GetRange::Offset(7), 7..10, "789"),
+            (GetRange::Suffix(4), 6..10, "6789"),
+        ] {
+            let options = GetOptions {
+                range: Some(range),
+                ..Default::default()
+            };
+            let result = store.get_opts(&path, options).await.unwrap();
+            assert_eq!(result.range, expected);
+            assert_eq!(result.meta.size, 10);
+            assert_eq!(result.bytes().await.unwrap(), bytes);
+        }
+        let whole = store.get(&path).await.unwrap();
+        assert_eq!(whole.range, 0..10);
+    }
+
+    #[cfg(feature = "object_store")]
+    #[tokio::test]
+    async fn object_store_multipart() {
+        use object_store::ObjectStore;
+        use object_store::path::Path;
+
+        let server = MockServer::start();
+        let store = r2client::R2Store::new("bucket".to_string(), server.client());
+        let path = Path::from("big.bin");
+        let mut upload = store.put_multipart(&path).await.unwrap();
+        let first = upload.put_part(vec![1; MIN_PART_SIZE as usize].into());
+        let second = upload.put_part(vec![2; 10].into());
+        // Parts can finish in any order, they're completed in the order they were started
+        second.await.unwrap();
+        first.await.unwrap();
+        let result = upload.complete().await.unwrap();
+        assert!(result.e_tag.unwrap().ends_with("-2\""));
+        assert_eq!(server.uploads_in_progress(), 0);
+
+        let body = server.object("bucket", "big.bin").unwrap();
+        assert_eq!(body.len(), MIN_PART_SIZE as usize + 10);
+        assert_eq!(body[0], 1);
+        assert_eq!(body[body.len() - 1], 2);
+    }
+
+    #[cfg(feature = "object_store")]
+    #[tokio::test]
+    async fn object_store_listing() {
+        use object_store::ObjectStore;
+        use object_store::path::Path;
+
+        let server = MockServer::start();
+        let client = server.client();
+        for key in [
+            "logs/a.txt",
+            "logs/b.txt",
+            "logs/2024/x",
+            "logs/2025/y",
+            "other",
+        ] {
+            client
+                .put_object("bucket", key, "data", &PutOptions::default())
+                .await
+                .unwrap();
+        }
+        // Makes the listing take two pages, one of common prefixes and one of objects
+        server.set_page_size(2);
+        let store = r2client::R2Store::new("bucket".to_string(), client);
+        let listing = store
+            .list_with_delimiter(Some(&Path::from("logs")))
+            .await
+            .unwrap();
+        let prefixes: Vec<_> = listing.common_prefixes.iter().map(Path::as_ref).collect();
+        assert_eq!(prefixes, ["logs/2024", "logs/2025"]);
+        let objects: Vec<_> = listing
+            .objects
+            .iter()
+            .map(|object| object.location.as_ref())
+            .collect();
+        assert_eq!(objects, ["logs/a.txt", "logs/b.txt"]);
+        let pages = server
+            .requests()
+            .iter()
+            .filter(|request| request.starts_with("GET /bucket/?list-type=2"))
+            .count();
+        assert_eq!(pages, 2);
+    }
+}
+
+#[cfg(feature = "sync")]
+mod sync_tests {
+    use r2client::sync::R2Client;
+    use r2client::test_support::MockServer;
+    use r2client::test_support::TempDir;
+    use r2client::{Fixtures, GetOptions, PutOptions, R2Error};
+
+    #[test]
+    fn blocking_client() {
+        let server = MockServer::start();
+        let client = server.blocking_client();
+        client
+            .put_object("bucket", "a/key", "blocking", &PutOptions::default())
+            .unwrap();
+        let object = client
+            .get_object("bucket", "a/key", &GetOptions::default())
+            .unwrap();
+        assert_eq!(object.body, "blocking");
+        let objects = client.list_objects("bucket", "a/").unwrap();
+        assert_eq!(objects[0].key, "a/key");
+    }
+
+    #[test]
+    fn blocking_fixtures() {
+        let dir = TempDir::new("blocking-fixtures");
+        let fixture = dir.file("exchanges.txt");
+        let endpoint = {
+            let server = MockServer::start();
+            let client = server
+                .blocking_client()
+                .with_fixtures(Fixtures::record(&fixture).unwrap());
+            client
+                .put_object("bucket", "key", "blocking", &PutOptions::default())
+                .unwrap();
+            server.endpoint().to_string()
+        };
+        let client = R2Client::from_credentials(
+            MockServer::ACCESS_KEY.to_string(),
+            MockServer::SECRET_KEY.to_string(),
+            endpoint,
+        )
+        .unwrap()
+        .with_fixtures(Fixtures::replay(&fixture).unwrap());
+        client
+            .put_object("bucket", "key", "blocking", &PutOptions::default())
+            .unwrap();
+        let error = client
+            .put_object("bucket", "key", "blocking", &PutOptions::default())
+            .unwrap_err();
+        assert!(matches!(error, R2Error::FixtureMissing(_)), "{error}");
+    }
+}
+
+#[test]
+fn stops_when_dropped() {
+    let server = MockServer::start();
+    let endpoint = server.endpoint().trim_start_matches("http://").to_string();
+    drop(server);
+    assert!(std::net::TcpStream::connect(endpoint).is_err());
+}
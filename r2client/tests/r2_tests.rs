@@ -20,6 +20,7 @@ mod sync_tests {
         let secret_key = env::var("R2_SECRET_KEY").expect("R2_SECRET_KEY not set");
         let endpoint = env::var("R2_ENDPOINT").expect("R2_ENDPOINT not set");
         R2Bucket::from_credentials(bucket, access_key, secret_key, endpoint)
+            .expect("R2_ENDPOINT isn't a valid endpoint")
     }
 
     #[test]
@@ -79,6 +80,7 @@ mod async_tests {
         let secret_key = env::var("R2_SECRET_KEY").expect("R2_SECRET_KEY not set");
         let endpoint = env::var("R2_ENDPOINT").expect("R2_ENDPOINT not set");
         R2Bucket::from_credentials(bucket, access_key, secret_key, endpoint)
+            .expect("R2_ENDPOINT isn't a valid endpoint")
     }
 
     #[tokio::test]